@@ -0,0 +1,93 @@
+// Per-record cost of evaluating a query over a large synthetic chain.
+//
+// `compiled` is the query engine as the server runs it: the query is compiled once and the
+// plan evaluated against each record. `reparsed` lexes and parses the predicate again for every
+// record, which is what the engine used to do, and is kept as the point of comparison.
+//
+// Run with `cargo bench --bench query`. Throughput is reported in records, so the time per
+// element is the per-record cost.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::collections::HashMap;
+use tiaf::chain::Blockchain;
+use tiaf::pratt;
+use tiaf::query_chain::{value_of, Query};
+use tiaf::record::Record;
+
+const BLOCKS: u64 = 100;
+const RECORDS_PER_BLOCK: u64 = 100;
+
+fn synthetic_chain() -> Blockchain {
+    let statuses = ["open", "closed", "pending"];
+    let mut chain = Blockchain::new();
+    for b in 0..BLOCKS {
+        let records = (0..RECORDS_PER_BLOCK)
+            .map(|i| {
+                let n = b * RECORDS_PER_BLOCK + i;
+                Record::new(format!(
+                    r#"{{"id": "{n}", "status": "{}", "amount": "{}", "owner": "user{}"}}"#,
+                    statuses[(n % 3) as usize],
+                    n % 1000,
+                    n % 17
+                ))
+            })
+            .collect();
+        chain.append_records(records).unwrap();
+    }
+    chain
+}
+
+fn reparsed(chain: &Blockchain, predicate: &str) -> usize {
+    let mut matched = 0;
+    for block in chain.blocks() {
+        for r in block.data.iter() {
+            let Ok(kv) = r.structured_entry() else {
+                continue;
+            };
+            let env: HashMap<String, pratt::Value> = kv
+                .pairs()
+                .iter()
+                .map(|(k, v)| (k.clone(), value_of(v)))
+                .collect();
+            let Ok(ast) = pratt::lex_parse(predicate.to_string()) else {
+                continue;
+            };
+            if pratt::eval(ast, &env) == Ok(pratt::Value::Bool(true)) {
+                matched += 1;
+            }
+        }
+    }
+    matched
+}
+
+fn bench_query(c: &mut Criterion) {
+    let chain = synthetic_chain();
+    let records = BLOCKS * RECORDS_PER_BLOCK;
+    let queries = [
+        ("equality", "status == \"open\" limit 1000000"),
+        ("arithmetic", "amount * 2 + 1 == 201 limit 1000000"),
+        (
+            "aggregate",
+            "select status, count(*), sum(amount) group by status",
+        ),
+    ];
+
+    let mut group = c.benchmark_group("query");
+    group.throughput(Throughput::Elements(records));
+    group.sample_size(20);
+    for (name, q) in queries {
+        let query = Query::new(q.to_string()).unwrap();
+        group.bench_with_input(BenchmarkId::new("compiled", name), &query, |b, query| {
+            b.iter(|| query.run(&chain, None).unwrap())
+        });
+    }
+    group.bench_function(BenchmarkId::new("reparsed", "equality"), |b| {
+        b.iter(|| reparsed(&chain, "status == \"open\""))
+    });
+    group.bench_function(BenchmarkId::new("reparsed", "arithmetic"), |b| {
+        b.iter(|| reparsed(&chain, "amount * 2 + 1 == 201"))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_query);
+criterion_main!(benches);
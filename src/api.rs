@@ -1,11 +1,30 @@
 use crate::block::Block;
 use crate::chain::{Blockchain, ChainComparison};
+use crate::cluster::{ClusterKey, SIGNATURE_HEADER};
+use crate::encryption::{Encryption, Keyring, ENCRYPTION_KEY_HEADER};
+use crate::events::{Event, Stored};
+use crate::genesis::GENESIS_HEADER;
+use crate::kv::KvEntry;
+use crate::mempool::{Dropped, Pending};
+use crate::namespace::{NamespaceConfig, DEFAULT};
+use crate::query_chain::{Explain, ProjectedRow, QueryError, QueryResult};
+use crate::quota::ClientCounters;
 use crate::record::Record;
+use crate::redaction::Redaction;
+use crate::schema::{SchemaDefined, SchemaError};
+use crate::snapshot::Snapshot;
+use crate::tls::ClientTls;
+use crate::types::{Hashtype, Time};
 use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{BufRead, BufReader};
+use subtle::ConstantTimeEq;
 use url::Url;
 
 // API admin key type, methods, etc
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct AdminKey(String);
 impl AdminKey {
     pub fn new(s: &str) -> AdminKey {
@@ -15,12 +34,18 @@ impl AdminKey {
         self.0.clone()
     }
 
+    // eq_str compares in constant time. Digests are compared rather than the keys themselves,
+    // so that not even the length of the key leaks.
     pub fn eq_str(&self, s: &str) -> bool {
-        self.0 == s
+        let digest = |s: &str| Sha3_256::digest(s.as_bytes());
+        digest(&self.0).ct_eq(&digest(s)).into()
     }
+}
 
-    pub fn eq_string(&self, s: &String) -> bool {
-        self.0 == *s
+// Keys are never printed, so they cannot end up in logs.
+impl fmt::Debug for AdminKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AdminKey(<redacted>)")
     }
 }
 
@@ -39,6 +64,38 @@ pub struct TiafNode {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RecordPut {
     pub data: String,
+    // uuid, when the client sets it, becomes the record's uuid, and makes the write idempotent
+    // just as an Idempotency-Key header does.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<uuid::Uuid>,
+}
+
+impl RecordPut {
+    pub fn new(data: String) -> RecordPut {
+        RecordPut { data, uuid: None }
+    }
+}
+
+/// TiafReceipt acknowledges a write: the record it made. A write repeated under the same
+/// idempotency key gets the receipt of the first, marked as a duplicate.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TiafReceipt {
+    pub record: Hashtype,
+    pub uuid: uuid::Uuid,
+    pub timestamp: Time,
+    #[serde(default)]
+    pub duplicate: bool,
+}
+
+impl TiafReceipt {
+    pub fn of(r: &Record, duplicate: bool) -> TiafReceipt {
+        TiafReceipt {
+            record: r.hash.clone(),
+            uuid: r.uuid,
+            timestamp: r.timestamp,
+            duplicate,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -46,8 +103,18 @@ pub struct TiafStatistics {
     pub node_id: String,
     pub chain_length: u64,
     pub pool_size: u64,
+    // pool_capacity is the pool size at which the node starts refusing writes.
+    #[serde(default)]
+    pub pool_capacity: u64,
+    // pool_dropped counts the acknowledged writes the pool dropped unsealed: expired, or evicted
+    // to make room when mempool_eviction is drop_oldest.
+    #[serde(default)]
+    pub pool_dropped: Dropped,
     pub downstream_count: u64,
     pub upstream_count: u64,
+    // clients counts the writes of each client, by API key name or address.
+    #[serde(default)]
+    pub clients: BTreeMap<String, ClientCounters>,
 }
 
 /// TiafPartialChain is an API interface struct.
@@ -75,11 +142,271 @@ pub struct TiafDownstreams {
     pub sweeping: bool,
 }
 
+/// TiafBatchItem is the result of one entry of a batch: its receipt if it was written, or why
+/// it was not.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TiafBatchItem {
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receipt: Option<TiafReceipt>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl TiafBatchItem {
+    pub fn written(receipt: TiafReceipt) -> TiafBatchItem {
+        TiafBatchItem {
+            status: 200,
+            receipt: Some(receipt),
+            error: None,
+        }
+    }
+
+    pub fn refused(status: u16, error: String) -> TiafBatchItem {
+        TiafBatchItem {
+            status,
+            receipt: None,
+            error: Some(error),
+        }
+    }
+}
+
+/// TiafBatchResult holds the result of each entry of a batch, in order. An atomic batch that was
+/// written names the block holding it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TiafBatchResult {
+    pub items: Vec<TiafBatchItem>,
+    #[serde(default)]
+    pub block: Option<Hashtype>,
+}
+
+/// TiafSealed reports a block sealed on demand: how many records went in, and its hash. When
+/// nothing was pending, no block is sealed.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TiafSealed {
+    pub records: u64,
+    pub block: Option<Hashtype>,
+}
+
+/// TiafMemPool is what a node has pending, oldest first.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TiafMemPool {
+    pub capacity: u64,
+    pub pending: Vec<Pending>,
+}
+
+/// TiafPurge names the pending records to drop, or asks for all of them to be.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TiafPurge {
+    #[serde(default)]
+    pub hashes: Vec<Hashtype>,
+    #[serde(default)]
+    pub all: bool,
+}
+
+/// TiafRedact asks for the entry of a sealed record to be replaced with a tombstone. The
+/// redaction must be signed, for that record, by one of the chain's redaction keys.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TiafRedact {
+    pub record: Hashtype,
+    pub redaction: Redaction,
+}
+
+/// TiafPurged lists the records a purge dropped.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TiafPurged {
+    pub hashes: Vec<Hashtype>,
+}
+
+/// TiafSchemas lists the ids of the schemas a node checks entries against.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TiafSchemas {
+    pub ids: Vec<String>,
+}
+
+/// TiafIndexes lists the entry fields a node keeps secondary indexes on.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TiafIndexes {
+    pub fields: Vec<String>,
+}
+
+/// TiafChains lists the chains a node hosts, the default chain among them.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TiafChains {
+    pub names: Vec<String>,
+}
+
+/// TiafCreateChain asks a node to host a new named chain, run as config says.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TiafCreateChain {
+    pub name: String,
+    #[serde(default)]
+    pub config: NamespaceConfig,
+}
+
+/// TiafSubscriptionEvent is one event of a subscription. id is the hash of the block the
+/// data was sealed in; passing it back as `since` resumes the subscription after that block.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TiafSubscriptionEvent {
+    pub id: Hashtype,
+    pub data: TiafSubscriptionData,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TiafSubscriptionData {
+    Block(Block),
+    Record(Record),
+    Row(ProjectedRow),
+}
+
+impl TiafSubscriptionEvent {
+    // to_sse formats the event for a text/event-stream. The event name says what data holds.
+    pub fn to_sse(&self) -> String {
+        let (event, data) = match &self.data {
+            TiafSubscriptionData::Block(b) => ("block", serde_json::to_string(b)),
+            TiafSubscriptionData::Record(r) => ("record", serde_json::to_string(r)),
+            TiafSubscriptionData::Row(r) => ("row", serde_json::to_string(r)),
+        };
+        // Serializing these types cannot fail; the JSON is always a single line.
+        let data = data.unwrap_or_default();
+        format!("id: {}\nevent: {event}\ndata: {data}\n\n", self.id)
+    }
+
+    // from_sse reads an event back from its id, event name and data fields.
+    pub fn from_sse(id: &str, event: &str, data: &str) -> Result<TiafSubscriptionEvent, String> {
+        let data = match event {
+            "block" => serde_json::from_str(data).map(TiafSubscriptionData::Block),
+            "record" => serde_json::from_str(data).map(TiafSubscriptionData::Record),
+            "row" => serde_json::from_str(data).map(TiafSubscriptionData::Row),
+            _ => return Err(format!("unknown event type: {event}")),
+        }
+        .map_err(|e| format!("failed to parse json: {e}"))?;
+        Ok(TiafSubscriptionEvent {
+            id: id.to_string(),
+            data,
+        })
+    }
+}
+
+// Reopen opens the next response of a subscription, resuming after the block with the given id.
+type Reopen<R> = Box<dyn FnMut(&str) -> Result<R, String> + Send>;
+
+/// TiafSubscription is a blocking iterator over the events of a subscription. Each response
+/// the server sends ends after a while; when it can reopen the subscription, it resumes after
+/// the last id it saw, and otherwise it ends with the response.
+pub struct TiafSubscription<R: std::io::Read> {
+    lines: std::io::Lines<BufReader<R>>,
+    // last is the last id the server sent, with an event or alone.
+    last: String,
+    reopen: Option<Reopen<R>>,
+}
+
+impl<R: std::io::Read> TiafSubscription<R> {
+    pub fn new(stream: R) -> TiafSubscription<R> {
+        TiafSubscription {
+            lines: BufReader::new(stream).lines(),
+            last: String::new(),
+            reopen: None,
+        }
+    }
+
+    pub fn with_reopen(mut self, reopen: Reopen<R>) -> TiafSubscription<R> {
+        self.reopen = Some(reopen);
+        self
+    }
+}
+
+impl<R: std::io::Read> Iterator for TiafSubscription<R> {
+    type Item = Result<TiafSubscriptionEvent, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (mut event, mut data) = (String::new(), String::new());
+        loop {
+            let line = match self.lines.next() {
+                Some(Ok(line)) => line,
+                Some(Err(e)) => return Some(Err(format!("failed to read subscription: {e}"))),
+                None => {
+                    let reopen = self.reopen.as_mut()?;
+                    match reopen(&self.last) {
+                        Ok(stream) => self.lines = BufReader::new(stream).lines(),
+                        Err(e) => {
+                            self.reopen = None;
+                            return Some(Err(e));
+                        }
+                    }
+                    continue;
+                }
+            };
+            if line.is_empty() {
+                if data.is_empty() {
+                    // a keepalive, or an id alone, which is where to resume.
+                    event.clear();
+                    continue;
+                }
+                return Some(TiafSubscriptionEvent::from_sse(&self.last, &event, &data));
+            }
+            // lines starting with a colon are comments.
+            let (field, value) = line.split_once(':').unwrap_or((&line, ""));
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match field {
+                "id" => self.last = value.to_string(),
+                "event" => event = value.to_string(),
+                "data" => data.push_str(value),
+                _ => {}
+            }
+        }
+    }
+}
+
 // API Client Code
 pub struct TiafClient {
     url: Url,
-    #[allow(dead_code)]
     admin_key: Option<AdminKey>,
+    // api_key identifies the client to the node, which limits writes per client.
+    api_key: Option<String>,
+    // cluster signs requests to fellow members of a cluster.
+    cluster: Option<ClusterKey>,
+    tls: Option<ClientTls>,
+    // namespace is the named chain the client uses, rather than the node's default chain.
+    namespace: Option<String>,
+    // genesis is the genesis hash of our chain, which peers must share.
+    genesis: Option<Hashtype>,
+    // encryption encrypts what we write, and decrypts what we read.
+    encryption: Option<Encryption>,
+    http: reqwest::blocking::Client,
+}
+
+// refused describes a write the node refused for now: because its mempool was full, or the
+// client was over its limits.
+fn refused(resp: &reqwest::blocking::Response) -> String {
+    let why = match resp.status() {
+        reqwest::StatusCode::TOO_MANY_REQUESTS => "rate limited",
+        _ => "mempool is full",
+    };
+    match resp
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+    {
+        Some(secs) => format!("{why}, retry after {secs}s"),
+        None => why.to_string(),
+    }
+}
+
+// on_our_chain checks that a peer answered from the chain that began with genesis. Peers that do
+// not say which chain they are on are let through, and checked when their blocks are.
+fn on_our_chain(
+    resp: &reqwest::blocking::Response,
+    genesis: Option<&Hashtype>,
+) -> Result<(), String> {
+    let theirs = resp
+        .headers()
+        .get(GENESIS_HEADER)
+        .and_then(|v| v.to_str().ok());
+    match (genesis, theirs) {
+        (Some(ours), Some(theirs)) if ours != theirs => Err("peer is on another chain".to_string()),
+        _ => Ok(()),
+    }
 }
 
 impl TiafClient {
@@ -87,14 +414,227 @@ impl TiafClient {
         TiafClient {
             url: Url::parse(&url).unwrap(),
             admin_key: key.map(|key| AdminKey::new(&key)),
+            api_key: None,
+            cluster: None,
+            tls: None,
+            namespace: None,
+            genesis: None,
+            encryption: None,
+            http: reqwest::blocking::Client::new(),
+        }
+    }
+
+    pub fn with_namespace(mut self, namespace: Option<String>) -> TiafClient {
+        self.namespace = namespace;
+        self
+    }
+
+    pub fn with_genesis(mut self, genesis: Option<Hashtype>) -> TiafClient {
+        self.genesis = genesis;
+        self
+    }
+
+    // with_encryption has the client encrypt the entries it writes, and decrypt what it reads.
+    // Queries carry the keys, so that the node can evaluate them over encrypted fields.
+    pub fn with_encryption(mut self, encryption: Option<Encryption>) -> TiafClient {
+        self.encryption = encryption;
+        self
+    }
+
+    // keyring is our keys, for the chain we use.
+    fn keyring(&self) -> Result<Option<Keyring>, String> {
+        let chain = self.namespace.as_deref().unwrap_or(DEFAULT);
+        self.encryption
+            .as_ref()
+            .map(|e| Keyring::new(chain, e.keys.clone()))
+            .transpose()
+    }
+
+    // encrypt encrypts what we encrypt of put. seed makes the encryption repeatable, for retries.
+    fn encrypt(&self, put: &RecordPut, seed: Option<&str>) -> Result<RecordPut, String> {
+        let uuid = put.uuid.map(|u| u.to_string());
+        let data = match (self.keyring()?, &self.encryption) {
+            (Some(keys), Some(e)) => {
+                keys.encrypt_entry(&put.data, &e.encrypted, seed.or(uuid.as_deref()))?
+            }
+            _ => put.data.clone(),
+        };
+        Ok(RecordPut {
+            data,
+            uuid: put.uuid,
+        })
+    }
+
+    // decrypt is record with what encrypted values our keys decrypt decrypted. Its hash is of the
+    // entry as encrypted, so a decrypted record no longer validates.
+    pub fn decrypt(&self, record: &Record) -> Result<Record, String> {
+        Ok(match self.keyring()? {
+            Some(keys) => Record {
+                entry: keys.decrypt_entry(&record.entry),
+                ..record.clone()
+            },
+            None => record.clone(),
+        })
+    }
+
+    // endpoint is the url of path on the client's chain.
+    fn endpoint(&self, path: &str) -> Result<Url, url::ParseError> {
+        match &self.namespace {
+            Some(name) => self.url.join(&format!("/api/v1/chains/{name}/{path}")),
+            None => self.url.join(&format!("/api/v1/{path}")),
+        }
+    }
+
+    // with_tls has the client connect with tls: trusting only its CA, if it pins one, and
+    // presenting its certificate, if it has one.
+    pub fn with_tls(mut self, tls: Option<ClientTls>) -> Result<TiafClient, String> {
+        self.tls = tls;
+        self.http = match self.builder().build() {
+            Ok(http) => http,
+            Err(e) => return Err(format!("failed to build client: {e}")),
+        };
+        Ok(self)
+    }
+
+    fn builder(&self) -> reqwest::blocking::ClientBuilder {
+        let builder = reqwest::blocking::Client::builder();
+        match &self.tls {
+            Some(tls) => tls.apply(builder),
+            None => builder,
+        }
+    }
+
+    pub fn with_api_key(mut self, key: Option<String>) -> TiafClient {
+        self.api_key = key;
+        self
+    }
+
+    pub fn with_cluster(mut self, cluster: Option<ClusterKey>) -> TiafClient {
+        self.cluster = cluster;
+        self
+    }
+
+    // peer sends a request to another node of the cluster, signed when we hold the cluster
+    // secret. It gives back the response, and the signature of the request, which the response
+    // is signed against.
+    fn peer(
+        &self,
+        method: reqwest::Method,
+        url: Url,
+        body: Vec<u8>,
+    ) -> Result<(reqwest::blocking::Response, Option<String>), String> {
+        let mut builder = self
+            .keyed(self.http.request(method.clone(), url.clone()))
+            .header(reqwest::header::CONTENT_TYPE, "application/json");
+        if let Some(genesis) = &self.genesis {
+            builder = builder.header(GENESIS_HEADER, genesis);
+        }
+        let mut signature = None;
+        if let Some(cluster) = &self.cluster {
+            let path = match url.query() {
+                Some(query) => format!("{}?{query}", url.path()),
+                None => url.path().to_string(),
+            };
+            for (name, value) in cluster.sign_request(method.as_str(), &path, &body) {
+                if name == SIGNATURE_HEADER {
+                    signature = Some(value.clone());
+                }
+                builder = builder.header(name, value);
+            }
+        }
+        match builder.body(body).send() {
+            Ok(resp) => Ok((resp, signature)),
+            Err(e) => Err(format!("failed to reach peer: {e}")),
+        }
+    }
+
+    // peer_body reads the body of a peer's response, checking it came from a member of the
+    // cluster when we are in one, and from our chain.
+    fn peer_body(
+        &self,
+        resp: reqwest::blocking::Response,
+        request_signature: Option<String>,
+    ) -> Result<Vec<u8>, String> {
+        let status = resp.status().as_u16();
+        on_our_chain(&resp, self.genesis.as_ref())?;
+        let signature = resp
+            .headers()
+            .get(SIGNATURE_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string());
+        let body = match resp.bytes() {
+            Ok(body) => body.to_vec(),
+            Err(e) => return Err(format!("failed to read peer: {e}")),
+        };
+        if let (Some(cluster), Some(request_signature)) = (&self.cluster, request_signature) {
+            match signature {
+                Some(signature) => {
+                    cluster.verify_response(&request_signature, status, &body, &signature)?
+                }
+                None => return Err("peer did not sign its response".to_string()),
+            }
+        }
+        Ok(body)
+    }
+
+    // get_peer_chain reads the whole chain of an upstream, for syncing from it.
+    pub fn get_peer_chain(&self) -> Result<TiafPartialChain, String> {
+        let url = match self.endpoint("chain") {
+            Ok(url) => url,
+            Err(e) => return Err(format!("failed to form url: {e}")),
+        };
+        let (resp, signature) = self.peer(reqwest::Method::GET, url, vec![])?;
+        let body = self.peer_body(resp, signature)?;
+        let chain = match serde_json::from_slice::<Blockchain>(&body) {
+            Ok(chain) => chain,
+            Err(e) => return Err(format!("failed to parse json: {e}")),
+        };
+        Ok(TiafPartialChain {
+            total_length: chain.length(),
+            partial_blocks: (0..chain.length())
+                .filter_map(|i| chain.get(i).cloned())
+                .collect(),
+        })
+    }
+
+    // get_peer_chain_since reads the blocks an upstream sealed after the block hashed hash, for
+    // catching up from it.
+    pub fn get_peer_chain_since(&self, hash: &Hashtype) -> Result<TiafPartialChain, String> {
+        let url = match self.endpoint(&format!("chain/since/{hash}")) {
+            Ok(url) => url,
+            Err(e) => return Err(format!("failed to form url: {e}")),
+        };
+        let (resp, signature) = self.peer(reqwest::Method::GET, url, vec![])?;
+        let body = self.peer_body(resp, signature)?;
+        serde_json::from_slice::<TiafPartialChain>(&body)
+            .map_err(|e| format!("failed to parse json: {e}"))
+    }
+
+    // writer is a request builder for a write, carrying the api key when there is one.
+    fn writer(&self, url: Url) -> reqwest::blocking::RequestBuilder {
+        self.keyed(self.http.post(url))
+    }
+
+    // reader is a request builder for a read, carrying the api key when there is one.
+    fn reader(&self, url: Url) -> reqwest::blocking::RequestBuilder {
+        self.keyed(self.http.get(url))
+    }
+
+    fn keyed(
+        &self,
+        builder: reqwest::blocking::RequestBuilder,
+    ) -> reqwest::blocking::RequestBuilder {
+        match &self.api_key {
+            Some(key) => builder.header("X-TIAF-API-KEY", key),
+            None => builder,
         }
     }
     pub fn get_full_chain(&self) -> Result<Blockchain, String> {
-        let url = match self.url.clone().join("/api/v1/chain") {
+        let url = match self.endpoint("chain") {
             Ok(url) => url,
             Err(e) => return Err(format!("failed to form url: {e}")),
         };
-        match reqwest::blocking::get(url) {
+        match self.reader(url).send() {
             Ok(resp) => match resp.json::<Blockchain>() {
                 Ok(chain) => Ok(chain),
                 Err(e) => Err(format!("failed to parse json: {e}")),
@@ -103,15 +643,11 @@ impl TiafClient {
         }
     }
     pub fn get_chain_tail(&self, n: u64) -> Result<TiafPartialChain, String> {
-        let url = match self
-            .url
-            .clone()
-            .join(format!("/api/v1/chain/tail/{n}").as_str())
-        {
+        let url = match self.endpoint(&format!("chain/tail/{n}")) {
             Ok(url) => url,
             Err(e) => return Err(format!("failed to form url: {e}")),
         };
-        match reqwest::blocking::get(url) {
+        match self.reader(url).send() {
             Ok(resp) => match resp.json::<TiafPartialChain>() {
                 Ok(chain) => Ok(chain),
                 Err(e) => Err(format!("failed to parse json: {e}")),
@@ -120,15 +656,11 @@ impl TiafClient {
         }
     }
     pub fn get_chain_since(&self, hash: &String) -> Result<TiafPartialChain, String> {
-        let url = match self
-            .url
-            .clone()
-            .join(format!("/api/v1/chain/since/{hash}").as_str())
-        {
+        let url = match self.endpoint(&format!("chain/since/{hash}")) {
             Ok(url) => url,
             Err(e) => return Err(format!("failed to form url: {e}")),
         };
-        match reqwest::blocking::get(url) {
+        match self.reader(url).send() {
             Ok(resp) => match resp.json::<TiafPartialChain>() {
                 Ok(chain) => Ok(chain),
                 Err(e) => Err(format!("failed to parse json: {e}")),
@@ -137,15 +669,11 @@ impl TiafClient {
         }
     }
     pub fn post_compare(&self, chain: &Blockchain) -> Result<TiafCompareResult, String> {
-        let url = match self.url.clone().join("/api/v1/chain/compare") {
+        let url = match self.endpoint("chain/compare") {
             Ok(url) => url,
             Err(e) => return Err(format!("failed to form url: {e}")),
         };
-        match reqwest::blocking::Client::new()
-            .post(url)
-            .json(chain)
-            .send()
-        {
+        match self.writer(url).json(chain).send() {
             Ok(resp) => match resp.json::<TiafCompareResult>() {
                 Ok(chain) => Ok(chain),
                 Err(e) => Err(format!("failed to parse json: {e}")),
@@ -154,11 +682,11 @@ impl TiafClient {
         }
     }
     pub fn get_statistics(&self) -> Result<TiafStatistics, String> {
-        let url = match self.url.clone().join("/api/v1/statistics") {
+        let url = match self.endpoint("statistics") {
             Ok(url) => url,
             Err(e) => return Err(format!("failed to form url: {e}")),
         };
-        match reqwest::blocking::get(url) {
+        match self.reader(url).send() {
             Ok(resp) => match resp.json::<TiafStatistics>() {
                 Ok(chain) => Ok(chain),
                 Err(e) => Err(format!("failed to parse json: {e}")),
@@ -167,56 +695,537 @@ impl TiafClient {
         }
     }
 
-    pub fn put_data(&self, records: &RecordPut) -> Result<(), String> {
-        let url = match self.url.clone().join("/api/v1/data") {
+    pub fn put_data(&self, records: &RecordPut) -> Result<TiafReceipt, String> {
+        self.put_data_with_key(records, None)
+    }
+
+    // put_data_with_key puts data under an idempotency key, so that it is safe to send again
+    // if the first attempt's outcome is unknown.
+    pub fn put_data_with_key(
+        &self,
+        records: &RecordPut,
+        key: Option<&str>,
+    ) -> Result<TiafReceipt, String> {
+        let url = match self.endpoint("data") {
             Ok(url) => url,
             Err(e) => return Err(format!("failed to form url: {e}")),
         };
-        match reqwest::blocking::Client::new()
-            .put(url)
-            .json(records)
-            .send()
-        {
+        let mut builder = self.writer(url).json(&self.encrypt(records, key)?);
+        if let Some(key) = key {
+            builder = builder.header("Idempotency-Key", key);
+        }
+        match builder.send() {
             Ok(resp) => match resp.status() {
-                reqwest::StatusCode::OK => Ok(()),
+                reqwest::StatusCode::OK => match resp.json::<TiafReceipt>() {
+                    Ok(receipt) => Ok(receipt),
+                    Err(e) => Err(format!("failed to parse json: {e}")),
+                },
+                reqwest::StatusCode::CONFLICT => match resp.json::<TiafBoringResponse>() {
+                    Ok(TiafBoringResponse::Error(e)) => Err(e),
+                    _ => Err("failed to put data: conflict".to_string()),
+                },
+                reqwest::StatusCode::UNPROCESSABLE_ENTITY => match resp.json::<SchemaError>() {
+                    Ok(e) => Err(e.to_string()),
+                    Err(e) => Err(format!("failed to parse json: {e}")),
+                },
+                reqwest::StatusCode::SERVICE_UNAVAILABLE
+                | reqwest::StatusCode::TOO_MANY_REQUESTS => Err(refused(&resp)),
                 _ => Err(format!("failed to put data: {}", resp.status())),
             },
             Err(e) => Err(format!("failed to put data: {e}")),
         }
     }
 
-    pub fn put_record(&self, record: &Record) -> Result<(), String> {
-        let url = match self.url.clone().join("/api/v1/record") {
+    // put_batch writes many entries in one request. Each entry gets its own result; with atomic,
+    // either all are sealed together into one block or none are written, and the result says why.
+    pub fn put_batch(&self, puts: &[RecordPut], atomic: bool) -> Result<TiafBatchResult, String> {
+        let mut url = match self.endpoint("data/batch") {
             Ok(url) => url,
             Err(e) => return Err(format!("failed to form url: {e}")),
         };
-        match reqwest::blocking::Client::new()
-            .put(url)
-            .json(record)
-            .send()
-        {
+        if atomic {
+            url.query_pairs_mut().append_pair("atomic", "true");
+        }
+        let puts = puts
+            .iter()
+            .map(|put| self.encrypt(put, None))
+            .collect::<Result<Vec<_>, _>>()?;
+        match self.writer(url).json(&puts).send() {
             Ok(resp) => match resp.status() {
+                reqwest::StatusCode::BAD_REQUEST
+                | reqwest::StatusCode::UNAUTHORIZED
+                | reqwest::StatusCode::FORBIDDEN => match resp.json::<TiafBoringResponse>() {
+                    Ok(TiafBoringResponse::Error(e)) => Err(format!("failed to put batch: {e}")),
+                    _ => Err("failed to put batch".to_string()),
+                },
+                _ => match resp.json::<TiafBatchResult>() {
+                    Ok(result) => Ok(result),
+                    Err(e) => Err(format!("failed to parse json: {e}")),
+                },
+            },
+            Err(e) => Err(format!("failed to put batch: {e}")),
+        }
+    }
+
+    pub fn put_record(&self, record: &Record) -> Result<(), String> {
+        let url = match self.endpoint("record") {
+            Ok(url) => url,
+            Err(e) => return Err(format!("failed to form url: {e}")),
+        };
+        let body = match serde_json::to_vec(record) {
+            Ok(body) => body,
+            Err(e) => return Err(format!("failed to encode record: {e}")),
+        };
+        match self.peer(reqwest::Method::POST, url, body) {
+            Ok((resp, _)) => match resp.status() {
+                _ if on_our_chain(&resp, self.genesis.as_ref()).is_err() => {
+                    Err("failed to put record: peer is on another chain".to_string())
+                }
                 reqwest::StatusCode::OK => Ok(()),
+                reqwest::StatusCode::SERVICE_UNAVAILABLE
+                | reqwest::StatusCode::TOO_MANY_REQUESTS => Err(refused(&resp)),
                 _ => Err(format!("failed to put record: {}", resp.status())),
             },
             Err(e) => Err(format!("failed to put record: {e}")),
         }
     }
 
-    pub fn query(&self, query: String) -> Result<Vec<Record>, String> {
-        let mut url = match self.url.clone().join("/api/v1/query/") {
+    // put_block hands a peer a block we sealed outside the pool. The peer appends it if it
+    // follows its tip, and otherwise pools its records to seal them itself.
+    pub fn put_block(&self, block: &Block) -> Result<(), String> {
+        let url = match self.endpoint("block") {
+            Ok(url) => url,
+            Err(e) => return Err(format!("failed to form url: {e}")),
+        };
+        let body = match serde_json::to_vec(block) {
+            Ok(body) => body,
+            Err(e) => return Err(format!("failed to encode block: {e}")),
+        };
+        match self.peer(reqwest::Method::POST, url, body) {
+            Ok((resp, _)) => match resp.status() {
+                _ if on_our_chain(&resp, self.genesis.as_ref()).is_err() => {
+                    Err("failed to put block: peer is on another chain".to_string())
+                }
+                reqwest::StatusCode::OK => Ok(()),
+                reqwest::StatusCode::SERVICE_UNAVAILABLE
+                | reqwest::StatusCode::TOO_MANY_REQUESTS => Err(refused(&resp)),
+                _ => Err(format!("failed to put block: {}", resp.status())),
+            },
+            Err(e) => Err(format!("failed to put block: {e}")),
+        }
+    }
+
+    // query runs query on the server. Pass the next_cursor of a previous result to get the next page.
+    pub fn query(&self, query: String, cursor: Option<String>) -> Result<QueryResult, String> {
+        let mut url = match self.endpoint("query") {
             Ok(url) => url,
             Err(e) => return Err(format!("failed to form url: {e}")),
         };
 
-        url.set_query(Some(format!("q={}", &query).as_str()));
+        url.query_pairs_mut().append_pair("q", &query);
+        if let Some(cursor) = cursor {
+            url.query_pairs_mut().append_pair("cursor", &cursor);
+        }
 
-        match reqwest::blocking::get(url) {
-            Ok(resp) => match resp.json::<Vec<Record>>() {
-                Ok(records) => Ok(records),
+        let mut builder = self.reader(url);
+        if let Some(keys) = self.keyring()? {
+            builder = builder.header(ENCRYPTION_KEY_HEADER, keys.header());
+        }
+        match builder.send() {
+            Ok(resp) if resp.status().is_success() => match resp.json::<QueryResult>() {
+                Ok(result) => Ok(result),
+                Err(e) => Err(format!("failed to parse json: {e}")),
+            },
+            Ok(resp) => match resp.json::<QueryError>() {
+                Ok(e) => Err(e.to_string()),
                 Err(e) => Err(format!("failed to parse json: {e}")),
             },
             Err(e) => Err(format!("failed to get chain: {e}")),
         }
     }
+
+    // explain asks the server how it would run query, without running it.
+    pub fn explain(&self, query: String) -> Result<Explain, String> {
+        let mut url = match self.endpoint("query/explain") {
+            Ok(url) => url,
+            Err(e) => return Err(format!("failed to form url: {e}")),
+        };
+        url.query_pairs_mut().append_pair("q", &query);
+
+        match self.reader(url).send() {
+            Ok(resp) if resp.status().is_success() => match resp.json::<Explain>() {
+                Ok(explain) => Ok(explain),
+                Err(e) => Err(format!("failed to parse json: {e}")),
+            },
+            Ok(resp) => match resp.json::<QueryError>() {
+                Ok(e) => Err(e.to_string()),
+                Err(e) => Err(format!("failed to parse json: {e}")),
+            },
+            Err(e) => Err(format!("failed to explain query: {e}")),
+        }
+    }
+
+    pub fn seal(&self) -> Result<TiafSealed, String> {
+        let url = match self.endpoint("admin/seal") {
+            Ok(url) => url,
+            Err(e) => return Err(format!("failed to form url: {e}")),
+        };
+        match self
+            .http
+            .post(url)
+            .header("X-TIAF-ADMIN-KEY", self.admin_key_header())
+            .send()
+        {
+            Ok(resp) if resp.status().is_success() => match resp.json::<TiafSealed>() {
+                Ok(sealed) => Ok(sealed),
+                Err(e) => Err(format!("failed to parse json: {e}")),
+            },
+            Ok(resp) => match resp.json::<TiafBoringResponse>() {
+                Ok(TiafBoringResponse::Error(e)) => Err(format!("failed to seal: {e}")),
+                _ => Err("failed to seal".to_string()),
+            },
+            Err(e) => Err(format!("failed to seal: {e}")),
+        }
+    }
+
+    pub fn get_mempool(&self) -> Result<TiafMemPool, String> {
+        let url = match self.endpoint("admin/mempool") {
+            Ok(url) => url,
+            Err(e) => return Err(format!("failed to form url: {e}")),
+        };
+        match self
+            .http
+            .get(url)
+            .header("X-TIAF-ADMIN-KEY", self.admin_key_header())
+            .send()
+        {
+            Ok(resp) if resp.status().is_success() => match resp.json::<TiafMemPool>() {
+                Ok(pool) => Ok(pool),
+                Err(e) => Err(format!("failed to parse json: {e}")),
+            },
+            Ok(resp) => Err(format!("failed to get mempool: {}", resp.status())),
+            Err(e) => Err(format!("failed to get mempool: {e}")),
+        }
+    }
+
+    pub fn purge_mempool(&self, purge: &TiafPurge) -> Result<TiafPurged, String> {
+        let url = match self.endpoint("admin/mempool/purge") {
+            Ok(url) => url,
+            Err(e) => return Err(format!("failed to form url: {e}")),
+        };
+        match self
+            .http
+            .post(url)
+            .header("X-TIAF-ADMIN-KEY", self.admin_key_header())
+            .json(purge)
+            .send()
+        {
+            Ok(resp) if resp.status().is_success() => match resp.json::<TiafPurged>() {
+                Ok(purged) => Ok(purged),
+                Err(e) => Err(format!("failed to parse json: {e}")),
+            },
+            Ok(resp) => Err(format!("failed to purge mempool: {}", resp.status())),
+            Err(e) => Err(format!("failed to purge mempool: {e}")),
+        }
+    }
+
+    // snapshot fetches a signed snapshot of the chain, for a new node to begin from.
+    pub fn snapshot(&self) -> Result<Snapshot, String> {
+        let url = match self.endpoint("admin/snapshot") {
+            Ok(url) => url,
+            Err(e) => return Err(format!("failed to form url: {e}")),
+        };
+        match self
+            .http
+            .get(url)
+            .header("X-TIAF-ADMIN-KEY", self.admin_key_header())
+            .send()
+        {
+            Ok(resp) if resp.status().is_success() => match resp.json::<Snapshot>() {
+                Ok(snapshot) => Ok(snapshot),
+                Err(e) => Err(format!("failed to parse json: {e}")),
+            },
+            Ok(resp) => match resp.json::<TiafBoringResponse>() {
+                Ok(TiafBoringResponse::Error(e)) => Err(format!("failed to take snapshot: {e}")),
+                _ => Err("failed to take snapshot".to_string()),
+            },
+            Err(e) => Err(format!("failed to take snapshot: {e}")),
+        }
+    }
+
+    // redact removes the entry of a sealed record from the node, keeping its hash.
+    pub fn redact(&self, redact: &TiafRedact) -> Result<(), String> {
+        let url = match self.endpoint("admin/redactions") {
+            Ok(url) => url,
+            Err(e) => return Err(format!("failed to form url: {e}")),
+        };
+        match self
+            .http
+            .post(url)
+            .header("X-TIAF-ADMIN-KEY", self.admin_key_header())
+            .json(redact)
+            .send()
+        {
+            Ok(resp) if resp.status().is_success() => Ok(()),
+            Ok(resp) => match resp.json::<TiafBoringResponse>() {
+                Ok(TiafBoringResponse::Error(e)) => Err(format!("failed to redact: {e}")),
+                _ => Err("failed to redact".to_string()),
+            },
+            Err(e) => Err(format!("failed to redact: {e}")),
+        }
+    }
+
+    pub fn get_schemas(&self) -> Result<TiafSchemas, String> {
+        let url = match self.endpoint("schema") {
+            Ok(url) => url,
+            Err(e) => return Err(format!("failed to form url: {e}")),
+        };
+        match self.reader(url).send() {
+            Ok(resp) => match resp.json::<TiafSchemas>() {
+                Ok(schemas) => Ok(schemas),
+                Err(e) => Err(format!("failed to parse json: {e}")),
+            },
+            Err(e) => Err(format!("failed to get schemas: {e}")),
+        }
+    }
+
+    pub fn register_schema(&self, definition: &SchemaDefined) -> Result<(), String> {
+        let url = match self.endpoint("admin/schema") {
+            Ok(url) => url,
+            Err(e) => return Err(format!("failed to form url: {e}")),
+        };
+        match self
+            .http
+            .post(url)
+            .header("X-TIAF-ADMIN-KEY", self.admin_key_header())
+            .json(definition)
+            .send()
+        {
+            Ok(resp) if resp.status().is_success() => Ok(()),
+            Ok(resp) => match resp.json::<TiafBoringResponse>() {
+                Ok(TiafBoringResponse::Error(e)) => Err(format!("failed to register schema: {e}")),
+                _ => Err("failed to register schema".to_string()),
+            },
+            Err(e) => Err(format!("failed to register schema: {e}")),
+        }
+    }
+
+    pub fn get_indexes(&self) -> Result<TiafIndexes, String> {
+        let url = match self.endpoint("admin/index") {
+            Ok(url) => url,
+            Err(e) => return Err(format!("failed to form url: {e}")),
+        };
+        match self
+            .http
+            .get(url)
+            .header("X-TIAF-ADMIN-KEY", self.admin_key_header())
+            .send()
+        {
+            Ok(resp) if resp.status().is_success() => match resp.json::<TiafIndexes>() {
+                Ok(indexes) => Ok(indexes),
+                Err(e) => Err(format!("failed to parse json: {e}")),
+            },
+            Ok(resp) => Err(format!("failed to get indexes: {}", resp.status())),
+            Err(e) => Err(format!("failed to get indexes: {e}")),
+        }
+    }
+
+    pub fn set_indexes(&self, indexes: &TiafIndexes) -> Result<(), String> {
+        let url = match self.endpoint("admin/index") {
+            Ok(url) => url,
+            Err(e) => return Err(format!("failed to form url: {e}")),
+        };
+        match self
+            .http
+            .post(url)
+            .header("X-TIAF-ADMIN-KEY", self.admin_key_header())
+            .json(indexes)
+            .send()
+        {
+            Ok(resp) => match resp.status() {
+                reqwest::StatusCode::OK => Ok(()),
+                _ => Err(format!("failed to set indexes: {}", resp.status())),
+            },
+            Err(e) => Err(format!("failed to set indexes: {e}")),
+        }
+    }
+
+    // get_chains lists the chains the node hosts.
+    pub fn get_chains(&self) -> Result<TiafChains, String> {
+        let url = match self.url.clone().join("/api/v1/admin/chains") {
+            Ok(url) => url,
+            Err(e) => return Err(format!("failed to form url: {e}")),
+        };
+        match self
+            .http
+            .get(url)
+            .header("X-TIAF-ADMIN-KEY", self.admin_key_header())
+            .send()
+        {
+            Ok(resp) if resp.status().is_success() => match resp.json::<TiafChains>() {
+                Ok(chains) => Ok(chains),
+                Err(e) => Err(format!("failed to parse json: {e}")),
+            },
+            Ok(resp) => Err(format!("failed to get chains: {}", resp.status())),
+            Err(e) => Err(format!("failed to get chains: {e}")),
+        }
+    }
+
+    // create_chain has the node host a new named chain. Use with_namespace to reach it.
+    pub fn create_chain(&self, chain: &TiafCreateChain) -> Result<(), String> {
+        let url = match self.url.clone().join("/api/v1/admin/chains") {
+            Ok(url) => url,
+            Err(e) => return Err(format!("failed to form url: {e}")),
+        };
+        match self
+            .http
+            .post(url)
+            .header("X-TIAF-ADMIN-KEY", self.admin_key_header())
+            .json(chain)
+            .send()
+        {
+            Ok(resp) if resp.status().is_success() => Ok(()),
+            Ok(resp) => match resp.json::<TiafBoringResponse>() {
+                Ok(TiafBoringResponse::Error(e)) => Err(format!("failed to create chain: {e}")),
+                _ => Err("failed to create chain".to_string()),
+            },
+            Err(e) => Err(format!("failed to create chain: {e}")),
+        }
+    }
+
+    fn admin_key_header(&self) -> String {
+        self.admin_key.as_ref().map(|k| k.get()).unwrap_or_default()
+    }
+
+    // append stores event on the chain, by way of the server's pool like any other data.
+    pub fn append<T: Event>(&self, event: &T) -> Result<(), String> {
+        let data = crate::events::encode(event)?;
+        self.put_data(&RecordPut::new(data)).map(|_| ())
+    }
+
+    // events fetches the events of type T sealed after the block since, or on the whole chain.
+    // It fails on the first event of type T it cannot read.
+    pub fn events<T: Event>(&self, since: Option<&Hashtype>) -> Result<Vec<Stored<T>>, String> {
+        let blocks = match since {
+            Some(hash) => self.get_chain_since(hash)?.partial_blocks,
+            None => self.get_full_chain()?.blocks().cloned().collect(),
+        };
+        crate::events::events_in(blocks.iter()).collect()
+    }
+
+    // kv_get fetches the current value of key, or None when it has none.
+    pub fn kv_get(&self, key: &str) -> Result<Option<KvEntry>, String> {
+        let url = self.kv_url(key, false)?;
+        match self.reader(url).send() {
+            Ok(resp) if resp.status() == reqwest::StatusCode::NOT_FOUND => Ok(None),
+            Ok(resp) if resp.status().is_success() => match resp.json::<KvEntry>() {
+                Ok(entry) => Ok(Some(self.decrypt_kv(entry)?)),
+                Err(e) => Err(format!("failed to parse json: {e}")),
+            },
+            Ok(resp) => match resp.json::<TiafBoringResponse>() {
+                Ok(TiafBoringResponse::Error(e)) => Err(format!("failed to get key: {e}")),
+                _ => Err("failed to get key".to_string()),
+            },
+            Err(e) => Err(format!("failed to get key: {e}")),
+        }
+    }
+
+    // kv_history fetches every version of key, oldest first.
+    pub fn kv_history(&self, key: &str) -> Result<Vec<KvEntry>, String> {
+        let url = self.kv_url(key, true)?;
+        match self.reader(url).send() {
+            Ok(resp) if resp.status().is_success() => match resp.json::<Vec<KvEntry>>() {
+                Ok(history) => history.into_iter().map(|e| self.decrypt_kv(e)).collect(),
+                Err(e) => Err(format!("failed to parse json: {e}")),
+            },
+            Ok(resp) => Err(format!("failed to get key history: {}", resp.status())),
+            Err(e) => Err(format!("failed to get key history: {e}")),
+        }
+    }
+
+    fn decrypt_kv(&self, mut entry: KvEntry) -> Result<KvEntry, String> {
+        if let (Some(keys), Some(fields)) = (self.keyring()?, entry.fields.as_mut()) {
+            keys.decrypt_fields(fields.iter_mut());
+        }
+        Ok(entry)
+    }
+
+    // kv_url escapes key into its place in the path.
+    fn kv_url(&self, key: &str, history: bool) -> Result<Url, String> {
+        let mut url = self.url.clone();
+        {
+            let mut path = url
+                .path_segments_mut()
+                .map_err(|_| "failed to form url".to_string())?;
+            path.clear().extend(["api", "v1"]);
+            if let Some(name) = &self.namespace {
+                path.extend(["chains", name]);
+            }
+            path.extend(["kv", key]);
+            if history {
+                path.push("history");
+            }
+        }
+        Ok(url)
+    }
+
+    // subscribe streams what is sealed into the chain from now on: every record matching
+    // query, or every block when there is no query. Pass the id of the last event seen as
+    // since to pick up where a previous subscription left off.
+    pub fn subscribe(
+        &self,
+        query: Option<String>,
+        since: Option<Hashtype>,
+    ) -> Result<TiafSubscription<reqwest::blocking::Response>, String> {
+        let mut url = match self.endpoint("subscribe") {
+            Ok(url) => url,
+            Err(e) => return Err(format!("failed to form url: {e}")),
+        };
+        if let Some(query) = query {
+            url.query_pairs_mut().append_pair("q", &query);
+        }
+
+        // a subscription is expected to sit idle, so it must not time out.
+        let client = match self.builder().timeout(None).build() {
+            Ok(client) => client,
+            Err(e) => return Err(format!("failed to build client: {e}")),
+        };
+        let request = self.keyed(client.get(url));
+        let open = move |since: Option<&str>| {
+            let mut request = request
+                .try_clone()
+                .ok_or_else(|| "failed to subscribe: request cannot be repeated".to_string())?;
+            if let Some(since) = since.filter(|s| !s.is_empty()) {
+                request = request.query(&[("since", since)]);
+            }
+            match request.send() {
+                Ok(resp) if resp.status().is_success() => Ok(resp),
+                Ok(resp) => Err(format!("failed to subscribe: {}", resp.status())),
+                Err(e) => Err(format!("failed to subscribe: {e}")),
+            }
+        };
+        let first = open(since.as_deref())?;
+        Ok(TiafSubscription::new(first).with_reopen(Box::new(move |last| open(Some(last)))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscription_stream() {
+        let record = Record::new("{\"k\": \"v\"}".to_string());
+        let event = TiafSubscriptionEvent {
+            id: "abc".to_string(),
+            data: TiafSubscriptionData::Record(record),
+        };
+        let stream = format!(": keepalive\n\n{}{}", event.to_sse(), event.to_sse());
+        let events: Vec<_> = TiafSubscription::new(stream.as_bytes()).collect();
+        assert_eq!(events, vec![Ok(event.clone()), Ok(event)]);
+
+        let bad = "id: x\nevent: nonsense\ndata: {}\n\n";
+        let events: Vec<_> = TiafSubscription::new(bad.as_bytes()).collect();
+        assert!(events[0].is_err());
+    }
 }
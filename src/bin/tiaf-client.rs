@@ -1,8 +1,11 @@
-use clap::{Arg, Command};
+use clap::{Arg, ArgAction, Command};
 use std::str::FromStr;
 use std::{fmt, fs};
 use tiaf::api;
 use tiaf::chain::Blockchain;
+use tiaf::encryption::{Encrypted, Encryption, EncryptionKey};
+use tiaf::redaction::{Redaction, RedactionKey};
+use tiaf::snapshot::SnapshotKey;
 use tiaf::woody;
 
 #[derive(Clone, Debug)]
@@ -11,6 +14,10 @@ struct TiafArgs {
     port: u16,
     log_level: u8,
     admin_key: Option<String>,
+    api_key: Option<String>,
+    tls: tiaf::tls::ClientTlsConfig,
+    chain: Option<String>,
+    encryption: Option<Encryption>,
 }
 
 impl fmt::Display for TiafArgs {
@@ -29,6 +36,11 @@ impl TiafArgs {
             format!("{}:{}", self.host, self.port),
             self.admin_key.clone(),
         )
+        .with_api_key(self.api_key.clone())
+        .with_namespace(self.chain.clone())
+        .with_encryption(self.encryption.clone())
+        .with_tls(Some(tiaf::tls::ClientTls::load(&self.tls).unwrap()))
+        .unwrap()
     }
 }
 
@@ -63,6 +75,64 @@ fn main() {
                 .required(false)
                 .help("specify a tiaf admin key"),
         )
+        .arg(
+            Arg::new("apiKey")
+                .long("apiKey")
+                .required(false)
+                .help("specify a tiaf api key"),
+        )
+        .arg(
+            Arg::new("caFile")
+                .long("caFile")
+                .required(false)
+                .help("trust only nodes whose certificates this CA signed"),
+        )
+        .arg(
+            Arg::new("certFile")
+                .long("certFile")
+                .required(false)
+                .help("present this client certificate"),
+        )
+        .arg(
+            Arg::new("keyFile")
+                .long("keyFile")
+                .required(false)
+                .help("the key of the client certificate"),
+        )
+        .arg(
+            Arg::new("chain")
+                .long("chain")
+                .required(false)
+                .help("use this named chain rather than the node's default chain"),
+        )
+        .arg(
+            Arg::new("encryptionKeyFile")
+                .long("encryptionKeyFile")
+                .action(ArgAction::Append)
+                .help("encrypt what we write with this key, and decrypt what we read; the first of several encrypts"),
+        )
+        .arg(
+            Arg::new("encryptFields")
+                .long("encryptFields")
+                .requires("encryptionKeyFile")
+                .value_delimiter(',')
+                .help("encrypt only these fields of what we write, rather than whole entries"),
+        )
+        .subcommand(
+            Command::new("chains")
+                .about("list the chains the node hosts, or create one")
+                .arg(
+                    Arg::new("create")
+                        .long("create")
+                        .help("the name of a chain to create"),
+                )
+                .arg(
+                    Arg::new("config")
+                        .long("config")
+                        .requires("create")
+                        .help("a TOML file of how the new chain runs"),
+                ),
+        )
         .subcommand(
             Command::new("query")
                 .short_flag('Q')
@@ -70,17 +140,145 @@ fn main() {
                     Arg::new("query")
                         .long("query")
                         .short('q')
-                        .exclusive(true)
+                        .conflicts_with("file")
                         .help("query the tiaf chain"),
                 )
                 .arg(
                     Arg::new("file")
                         .long("file")
-                        .exclusive(true)
+                        .conflicts_with("query")
                         .help("query the tiaf chain from a file"),
+                )
+                .arg(
+                    Arg::new("cursor")
+                        .long("cursor")
+                        .help("continue a query from the cursor of a previous page"),
+                )
+                .arg(
+                    Arg::new("explain")
+                        .long("explain")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("show how the query would be run, without running it"),
+                ),
+        )
+        .subcommand(
+            Command::new("subscribe")
+                .about("print newly sealed data as it arrives")
+                .arg(
+                    Arg::new("query")
+                        .long("query")
+                        .short('q')
+                        .help("only print records matching the query"),
+                )
+                .arg(
+                    Arg::new("since")
+                        .long("since")
+                        .help("resume after the block with this hash"),
+                ),
+        )
+        .subcommand(
+            Command::new("kv")
+                .about("read the current value of a key")
+                .arg(Arg::new("key").required(true).help("the key to read"))
+                .arg(
+                    Arg::new("history")
+                        .long("history")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("list every version of the key instead"),
+                ),
+        )
+        .subcommand(
+            Command::new("index")
+                .short_flag('I')
+                .about("list the indexed entry fields, or set them")
+                .arg(
+                    Arg::new("fields")
+                        .long("fields")
+                        .num_args(0..)
+                        .help("the complete set of fields to index"),
                 ),
         )
         .subcommand(Command::new("statistics").short_flag('S'))
+        .subcommand(Command::new("seal").about("seal pending records into a block now"))
+        .subcommand(
+            Command::new("mempool")
+                .about("show pending records, or drop them unsealed")
+                .arg(
+                    Arg::new("purge")
+                        .long("purge")
+                        .num_args(1..)
+                        .help("hashes of pending records to drop"),
+                )
+                .arg(
+                    Arg::new("purge-all")
+                        .long("purge-all")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with("purge")
+                        .help("drop every pending record"),
+                ),
+        )
+        .subcommand(
+            Command::new("redact")
+                .about("replace the entry of a sealed record with a tombstone, keeping its hash")
+                .arg(
+                    Arg::new("record")
+                        .required(true)
+                        .help("hash of the record to redact"),
+                )
+                .arg(
+                    Arg::new("keyFile")
+                        .long("keyFile")
+                        .required(true)
+                        .help("file holding the redaction key to sign with"),
+                )
+                .arg(
+                    Arg::new("reason")
+                        .long("reason")
+                        .required(true)
+                        .help("why the record is redacted, kept on the tombstone"),
+                ),
+        )
+        .subcommand(
+            Command::new("redaction-key")
+                .about("make a redaction key, printing the public key for redaction_keys")
+                .arg(
+                    Arg::new("file")
+                        .required(true)
+                        .help("file to write the private key to"),
+                ),
+        )
+        .subcommand(
+            Command::new("snapshot")
+                .about("take a signed snapshot of the chain, for a new node's --snapshot-file")
+                .arg(
+                    Arg::new("file")
+                        .required(true)
+                        .help("file to write the snapshot to"),
+                ),
+        )
+        .subcommand(
+            Command::new("snapshot-key")
+                .about("make a snapshot key, printing the public key for snapshot_keys")
+                .arg(
+                    Arg::new("file")
+                        .required(true)
+                        .help("file to write the private key to"),
+                ),
+        )
+        .subcommand(
+            Command::new("encryption-key")
+                .about("make an encryption key for a chain, for --encryptionKeyFile")
+                .arg(
+                    Arg::new("id")
+                        .required(true)
+                        .help("the name of the key, such as the year it is used in"),
+                )
+                .arg(
+                    Arg::new("file")
+                        .required(true)
+                        .help("file to write the key to"),
+                ),
+        )
         .subcommand(
             Command::new("chain")
                 .short_flag('C')
@@ -117,6 +315,27 @@ fn main() {
         port: u16::from_str(matches.get_one::<String>("port").unwrap()).unwrap(),
         log_level: u8::from_str(matches.get_one::<String>("loglevel").unwrap()).unwrap(),
         admin_key: matches.get_one::<String>("adminKey").map(|s| s.to_string()),
+        api_key: matches.get_one::<String>("apiKey").map(|s| s.to_string()),
+        tls: tiaf::tls::ClientTlsConfig {
+            ca_file: matches.get_one::<String>("caFile").map(|s| s.into()),
+            cert_file: matches.get_one::<String>("certFile").map(|s| s.into()),
+            key_file: matches.get_one::<String>("keyFile").map(|s| s.into()),
+        },
+        chain: matches.get_one::<String>("chain").cloned(),
+        encryption: matches
+            .get_many::<String>("encryptionKeyFile")
+            .map(|files| Encryption {
+                keys: files
+                    .map(|f| {
+                        EncryptionKey::load(f.as_ref())
+                            .unwrap_or_else(|e| panic!("encryption: error: {e}"))
+                    })
+                    .collect(),
+                encrypted: match matches.get_many::<String>("encryptFields") {
+                    Some(fields) => Encrypted::Fields(fields.cloned().collect()),
+                    None => Encrypted::Entry,
+                },
+            }),
     };
     let _logger = woody::new(woody::Level::from_u8(&global_args.log_level).unwrap());
 
@@ -141,6 +360,38 @@ fn main() {
         }
     }
 
+    if let Some(sub_m) = matches.subcommand_matches("chains") {
+        let result = match sub_m.get_one::<String>("create") {
+            Some(name) => {
+                let config = match sub_m.get_one::<String>("config") {
+                    Some(file) => {
+                        let toml = fs::read_to_string(file)
+                            .unwrap_or_else(|e| panic!("chains: error reading config: {e}"));
+                        toml::from_str(&toml)
+                            .unwrap_or_else(|e| panic!("chains: error parsing config: {e}"))
+                    }
+                    None => Default::default(),
+                };
+                let chain = api::TiafCreateChain {
+                    name: name.clone(),
+                    config,
+                };
+                global_args
+                    .client()
+                    .create_chain(&chain)
+                    .map(|_| format!("chain {name} created"))
+            }
+            None => global_args
+                .client()
+                .get_chains()
+                .map(|chains| format!("{:?}", chains.names)),
+        };
+        match result {
+            Ok(s) => println!("{s}"),
+            Err(e) => println!("chains: error: {e}"),
+        }
+    }
+
     if let Some(_sub_m) = matches.subcommand_matches("statistics") {
         let s: Result<api::TiafStatistics, String> = global_args.client().get_statistics();
         match s {
@@ -149,6 +400,149 @@ fn main() {
         }
     }
 
+    if let Some(sub_m) = matches.subcommand_matches("mempool") {
+        let purge = api::TiafPurge {
+            hashes: sub_m
+                .get_many::<String>("purge")
+                .map(|hashes| hashes.cloned().collect())
+                .unwrap_or_default(),
+            all: sub_m.get_flag("purge-all"),
+        };
+        if purge.all || !purge.hashes.is_empty() {
+            match global_args.client().purge_mempool(&purge) {
+                Ok(p) => println!("{p:?}"),
+                Err(e) => println!("mempool: error: {e}"),
+            }
+        } else {
+            match global_args.client().get_mempool() {
+                Ok(p) => println!("{p:?}"),
+                Err(e) => println!("mempool: error: {e}"),
+            }
+        }
+    }
+
+    if let Some(_sub_m) = matches.subcommand_matches("seal") {
+        match global_args.client().seal() {
+            Ok(s) => println!("{s:?}"),
+            Err(e) => println!("seal: error: {e}"),
+        }
+    }
+
+    if let Some(sub_m) = matches.subcommand_matches("redact") {
+        let record = sub_m.get_one::<String>("record").unwrap().clone();
+        let reason = sub_m.get_one::<String>("reason").unwrap();
+        let result = RedactionKey::load(sub_m.get_one::<String>("keyFile").unwrap().as_ref())
+            .and_then(|key| {
+                let redaction = Redaction::sign(&key, &record, reason);
+                global_args
+                    .client()
+                    .redact(&api::TiafRedact { record, redaction })
+            });
+        match result {
+            Ok(()) => println!("redacted"),
+            Err(e) => println!("redact: error: {e}"),
+        }
+    }
+
+    if let Some(sub_m) = matches.subcommand_matches("redaction-key") {
+        let file = sub_m.get_one::<String>("file").unwrap();
+        let key = RedactionKey::generate();
+        match fs::write(file, key.secret()) {
+            Ok(()) => println!("{}", key.public()),
+            Err(e) => println!("redaction-key: error: {e}"),
+        }
+    }
+
+    if let Some(sub_m) = matches.subcommand_matches("snapshot") {
+        let file = sub_m.get_one::<String>("file").unwrap();
+        let result = global_args.client().snapshot().and_then(|snapshot| {
+            let json = serde_json::to_string(&snapshot).map_err(|e| e.to_string())?;
+            fs::write(file, json).map_err(|e| e.to_string())?;
+            Ok(snapshot)
+        });
+        match result {
+            Ok(snapshot) => println!(
+                "snapshot of {} blocks, to {}, written to {file}",
+                snapshot.length, snapshot.tip
+            ),
+            Err(e) => println!("snapshot: error: {e}"),
+        }
+    }
+
+    if let Some(sub_m) = matches.subcommand_matches("snapshot-key") {
+        let file = sub_m.get_one::<String>("file").unwrap();
+        let key = SnapshotKey::generate();
+        match fs::write(file, key.secret()) {
+            Ok(()) => println!("{}", key.public()),
+            Err(e) => println!("snapshot-key: error: {e}"),
+        }
+    }
+
+    if let Some(sub_m) = matches.subcommand_matches("encryption-key") {
+        let file = sub_m.get_one::<String>("file").unwrap();
+        let result = EncryptionKey::generate(sub_m.get_one::<String>("id").unwrap())
+            .and_then(|key| fs::write(file, key.export()).map_err(|e| e.to_string()));
+        match result {
+            Ok(()) => println!("encryption key written to {file}"),
+            Err(e) => println!("encryption-key: error: {e}"),
+        }
+    }
+
+    if let Some(sub_m) = matches.subcommand_matches("subscribe") {
+        let query = sub_m.get_one::<String>("query").cloned();
+        let since = sub_m.get_one::<String>("since").cloned();
+        match global_args.client().subscribe(query, since) {
+            Ok(events) => {
+                for event in events {
+                    match event {
+                        Ok(event) => println!("{event:?}"),
+                        Err(e) => println!("subscribe: error: {e}"),
+                    }
+                }
+            }
+            Err(e) => println!("subscribe: error: {e}"),
+        }
+    }
+
+    if let Some(sub_m) = matches.subcommand_matches("kv") {
+        let key = sub_m.get_one::<String>("key").unwrap();
+        let result = if sub_m.get_flag("history") {
+            global_args
+                .client()
+                .kv_history(key)
+                .map(|history| format!("{history:?}"))
+        } else {
+            global_args
+                .client()
+                .kv_get(key)
+                .map(|entry| format!("{entry:?}"))
+        };
+        match result {
+            Ok(s) => println!("{s}"),
+            Err(e) => println!("kv: error: {e}"),
+        }
+    }
+
+    if let Some(sub_m) = matches.subcommand_matches("index") {
+        let result = match sub_m.get_many::<String>("fields") {
+            Some(fields) => {
+                let fields = fields.cloned().collect();
+                global_args
+                    .client()
+                    .set_indexes(&api::TiafIndexes { fields })
+                    .map(|_| "indexes set".to_string())
+            }
+            None => global_args
+                .client()
+                .get_indexes()
+                .map(|indexes| format!("{:?}", indexes.fields)),
+        };
+        match result {
+            Ok(s) => println!("{s}"),
+            Err(e) => println!("index: error: {e}"),
+        }
+    }
+
     if let Some(sub_m) = matches.subcommand_matches("query") {
         let inline = sub_m.get_one::<String>("query");
         let file = sub_m.get_one::<String>("file");
@@ -171,6 +565,17 @@ fn main() {
             })
             .unwrap();
         println!("search: {query}");
-        println!("{:?}", global_args.client().query(query));
+        if sub_m.get_flag("explain") {
+            match global_args.client().explain(query) {
+                Ok(explain) => println!("{explain:?}"),
+                Err(e) => println!("query: error: {e}"),
+            }
+            return;
+        }
+        let cursor = sub_m.get_one::<String>("cursor").cloned();
+        match global_args.client().query(query, cursor) {
+            Ok(result) => println!("{result:?}"),
+            Err(e) => println!("query: error: {e}"),
+        }
     }
 }
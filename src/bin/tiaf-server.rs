@@ -1,193 +1,23 @@
 use clap::Parser;
 
 use rand::Rng;
-use std::thread;
+use std::path::PathBuf;
 use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
-use std::sync::{Arc, RwLock};
-use tiaf::api::AdminKey;
-use tiaf::chain::Blockchain;
-use tiaf::mempool::MemPool;
-use tiaf::peers::{Downstreams, ReadHost, Upstreams, WriteHost};
-use tiaf::record::Record;
-use tiaf::woody::{Level, Logger};
+use std::sync::Arc;
+use tiaf::cluster::{ClusterConfig, ClusterKey};
+use tiaf::credentials::{KeyConfig, Keys};
+use tiaf::daemons;
+use tiaf::genesis::Genesis;
+use tiaf::kv::KvConfig;
+use tiaf::namespace::{NamespaceConfig, Namespaces, DEFAULT};
+use tiaf::peers::PeerAuth;
+use tiaf::snapshot::{Snapshot, SnapshotKey};
+use tiaf::tls::{ClientTls, ClientTlsConfig, TlsConfig};
+use tiaf::woody::Level;
 use tiaf::{notes, woody, Attributes};
 
-fn sleep_with_jitter(d: Duration, width: u32) {
-    let jitter = rand::random::<u64>() % (width * 1000) as u64;
-    // d +/- jitter/2.
-    let d = d - Duration::from_millis(jitter) / 2 + Duration::from_millis(jitter);
-    thread::sleep(d);
-}
-
-/// sleep with 10% jitter to avoid thundering herd.
-fn sleep_with_10p_jitter(d: Duration) {
-    sleep_with_jitter(d, d.as_secs() as u32 / 10);
-}
-
-struct DownstreamsNotifier {
-    mp: Arc<RwLock<MemPool>>,
-    downstreams: Arc<RwLock<Downstreams>>,
-    sleep_duration: Duration,
-}
-
-// or do we have a fifo queue that flushes to network?
-fn downstream_notifying_daemon(args: DownstreamsNotifier) {
-    let logger = woody::new(woody::Level::Info).lock().unwrap();
-    loop {
-        downstream_notify(&args, &logger);
-        logger.info(notes!(
-            "ts",
-            chrono::Utc::now().to_rfc3339(),
-            "msg",
-            "sleeping downstream notifier".to_string()
-        ));
-        sleep_with_10p_jitter(args.sleep_duration);
-    }
-}
-
-fn downstream_notify(args: &DownstreamsNotifier, logger: &Logger) {
-    let mp = args.mp.read().unwrap();
-    let hosts = args.downstreams.read().unwrap();
-    if hosts.sweeping {
-        let records: Vec<&Record> = mp.contents().iter().collect();
-        for mut p in hosts.downstreams() {
-            for r in records.iter() {
-                match p.notify_host(r) {
-                    Ok(_) => {
-                        logger.info(notes!(
-                            "ts",
-                            chrono::Utc::now().to_rfc3339(),
-                            "msg",
-                            format!("notified downstream of record: {}", p.url()).to_string()
-                        ));
-                    }
-                    Err(e) => {
-                        logger.error(notes!(
-                            "ts",
-                            chrono::Utc::now().to_rfc3339(),
-                            "msg",
-                            format!("failed to notify downstream of record: {e}").to_string()
-                        ));
-                    }
-                }
-            }
-        }
-    } else {
-        logger.info(notes!(
-            "ts",
-            chrono::Utc::now().to_rfc3339(),
-            "msg",
-            "downstream notification disabled".to_string()
-        ));
-    }
-}
-
-struct PoolSweeper {
-    chain: Arc<RwLock<Blockchain>>,
-    mp: Arc<RwLock<MemPool>>,
-    // sleep_duration- everyone has the same duration.
-    sleep_duration: Duration,
-}
-
-fn pool_sweeping_daemon(args: PoolSweeper) {
-    let logger = woody::new(woody::Level::Info).lock().unwrap();
-    loop {
-        {
-            pool_sweep(&args, &logger);
-        }
-        logger.info(notes!(
-            "ts",
-            chrono::Utc::now().to_rfc3339(),
-            "msg",
-            "sleeping mempool sweeper".to_string()
-        ));
-        sleep_with_10p_jitter(args.sleep_duration);
-    }
-}
-
-fn pool_sweep(args: &PoolSweeper, logger: &Logger) {
-    let mut mp = args.mp.write().unwrap();
-    if mp.length() > 0 {
-        let mut p = args.chain.write().unwrap();
-        let records: Vec<Record> = mp.reset().drain().collect();
-
-        match p.append_new_records(records) {
-            Ok(_) => {
-                logger.info(notes!(
-                    "ts",
-                    chrono::Utc::now().to_rfc3339(),
-                    "msg",
-                    "appended records to chain".to_string()
-                ));
-            }
-            Err(e) => {
-                logger.error(notes!(
-                    "ts",
-                    chrono::Utc::now().to_rfc3339(),
-                    "msg",
-                    format!("failed to append records to chain: {e}").to_string()
-                ));
-            }
-        }
-    }
-}
-
-struct UpstreamSweeper {
-    chain: Arc<RwLock<Blockchain>>,
-    sleep_duration: Duration,
-    upstreams: Arc<RwLock<tiaf::peers::Upstreams>>,
-}
-
-/// upstream_sweeping_daemon is a daemon that periodically sweeps all peers in the Peers
-/// struct. It will attempt to update the blockchain
-fn upstream_sweeping_daemon(args: UpstreamSweeper) {
-    let logger = woody::new(woody::Level::Info).lock().unwrap();
-    loop {
-        upstream_sweep(&args, &logger);
-        logger.info(notes!(
-            "ts",
-            chrono::Utc::now().to_rfc3339(),
-            "msg",
-            "sleeping upstream sweeper".to_string()
-        ));
-        sleep_with_10p_jitter(args.sleep_duration);
-    }
-}
-
-fn upstream_sweep(args: &UpstreamSweeper, logger: &Logger) {
-    let mut chain = args.chain.write().unwrap();
-    let mut hosts = args.upstreams.write().unwrap();
-    if hosts.sweeping {
-        match hosts.sweep_all_upstreams(&mut chain) {
-            Ok(_) => {
-                logger.info(notes!(
-                    "ts",
-                    chrono::Utc::now().to_rfc3339(),
-                    "msg",
-                    "swept all upstreams".to_string()
-                ));
-            }
-            Err(e) => {
-                logger.error(notes!(
-                    "ts",
-                    chrono::Utc::now().to_rfc3339(),
-                    "msg",
-                    format!("failed to sweep upstreams: {e}").to_string()
-                ));
-            }
-        }
-    } else {
-        logger.info(notes!(
-            "ts",
-            chrono::Utc::now().to_rfc3339(),
-            "msg",
-            "upstream sweeping disabled".to_string()
-        ));
-    }
-}
-
 /// ServerConfig is also used as the schema for the config file.
 #[derive(Debug, Serialize, Deserialize)]
 struct ServerConfig {
@@ -195,13 +25,49 @@ struct ServerConfig {
     node_id: String,
 
     ip: String,
-    #[serde(default)]
-    downstreams: Vec<String>,
-    #[serde(default)]
-    upstreams: Vec<String>,
     port: u16,
     #[serde(default)]
     log_level: Level,
+    // How the default chain runs: its peers, indexes, mempool, block policy and access.
+    #[serde(flatten)]
+    chain: NamespaceConfig,
+    // How long a write's idempotency key is remembered, so that retrying it is safe.
+    #[serde(default = "default_idempotency_window_secs")]
+    idempotency_window_secs: u64,
+    // Keys admin requests may be made with, on every chain. More can be given through the
+    // environment.
+    #[serde(default)]
+    admin_keys: Vec<KeyConfig>,
+    // The secret this node shares with the rest of its cluster. Set, records from peers and
+    // blocks from upstreams are signed with it.
+    #[serde(default)]
+    cluster: Option<ClusterConfig>,
+    // The certificate and key to serve https with. Unset, the node serves plain http.
+    #[serde(default)]
+    tls: Option<TlsConfig>,
+    // How this node connects to its peers over https: the CA to pin, and the certificate to
+    // present as a member of the cluster.
+    #[serde(default)]
+    peer_tls: Option<ClientTlsConfig>,
+    // Where every chain's config and blocks are kept. Unset, chains live in memory only, and
+    // named chains are lost on restart.
+    #[serde(default)]
+    data_dir: Option<PathBuf>,
+    // A genesis file the default chain begins with, in place of a [genesis] section. Every node
+    // of a cluster must be given the same one.
+    #[serde(default)]
+    genesis_file: Option<PathBuf>,
+    // The private key this node signs the snapshots it is asked for with. Unset, it takes none.
+    #[serde(default)]
+    snapshot_key_file: Option<PathBuf>,
+    // A snapshot to begin from rather than replaying the chain it is of from upstreams. Once it
+    // is taken up, the node catches up from its tip.
+    #[serde(default)]
+    snapshot_file: Option<PathBuf>,
+}
+
+fn default_idempotency_window_secs() -> u64 {
+    24 * 60 * 60
 }
 
 #[derive(Debug, Parser)]
@@ -233,6 +99,28 @@ struct Cli {
     /// Logging level
     #[arg(long, required = false)]
     log_level: Option<String>,
+    /// Entry fields to index
+    #[arg(long, required = false)]
+    indexed_fields: Option<Vec<String>>,
+    /// Entry field keying records for the key-value view
+    #[arg(long, required = false)]
+    kv_key_field: Option<String>,
+    /// Records the mempool holds between sweeps
+    #[arg(long, required = false)]
+    mempool_size: Option<usize>,
+    /// Seconds a record may wait to be sealed before it is dropped
+    #[arg(long, required = false)]
+    mempool_ttl_secs: Option<u64>,
+    /// File holding an admin key. Keys are not taken on the command line, where any user could
+    /// read them.
+    #[arg(long, required = false)]
+    admin_key_file: Option<std::path::PathBuf>,
+    /// Genesis file the default chain begins with
+    #[arg(long, required = false)]
+    genesis_file: Option<std::path::PathBuf>,
+    /// Snapshot to begin from, in place of replaying the chain
+    #[arg(long, required = false)]
+    snapshot_file: Option<std::path::PathBuf>,
 }
 
 fn parse_arguments() -> Result<ServerConfig, String> {
@@ -246,11 +134,19 @@ fn parse_arguments() -> Result<ServerConfig, String> {
 
     let mut server_config: ServerConfig = ServerConfig {
         ip: "127.0.0.1".to_string(),
-        downstreams: vec![],
-        upstreams: vec![],
         port: 9999,
         node_id: rand_string,
         log_level: woody::Level::Info,
+        chain: NamespaceConfig::default(),
+        idempotency_window_secs: default_idempotency_window_secs(),
+        admin_keys: vec![],
+        cluster: None,
+        tls: None,
+        peer_tls: None,
+        data_dir: None,
+        genesis_file: None,
+        snapshot_key_file: None,
+        snapshot_file: None,
     };
     if let Some(config) = cli.config {
         let config = match std::fs::read_to_string(config) {
@@ -276,10 +172,61 @@ fn parse_arguments() -> Result<ServerConfig, String> {
     }
 
     if let Some(downstreams) = cli.downstreams {
-        server_config.downstreams = downstreams;
+        server_config.chain.downstreams = downstreams;
     }
     if let Some(upstreams) = cli.upstreams {
-        server_config.upstreams = upstreams;
+        server_config.chain.upstreams = upstreams;
+    }
+
+    if let Some(indexed_fields) = cli.indexed_fields {
+        server_config.chain.indexed_fields = indexed_fields;
+    }
+
+    if let Some(key_field) = cli.kv_key_field {
+        server_config.chain.kv = Some(KvConfig::new(&key_field));
+    }
+
+    if let Some(mempool_size) = cli.mempool_size {
+        server_config.chain.mempool_size = mempool_size;
+    }
+
+    if let Some(ttl) = cli.mempool_ttl_secs {
+        server_config.chain.mempool_ttl_secs = Some(ttl);
+    }
+
+    if let Some(genesis_file) = cli.genesis_file {
+        server_config.genesis_file = Some(genesis_file);
+    }
+    if let Some(genesis_file) = &server_config.genesis_file {
+        if server_config.chain.genesis.is_some() {
+            return Err("give a genesis file or a [genesis] section, not both".to_string());
+        }
+        server_config.chain.genesis = Some(Genesis::load(genesis_file)?);
+    }
+
+    if let Some(snapshot_file) = cli.snapshot_file {
+        server_config.snapshot_file = Some(snapshot_file);
+    }
+
+    if let Some(key_file) = cli.admin_key_file {
+        server_config.admin_keys.push(KeyConfig {
+            name: "cli".to_string(),
+            key_file: Some(key_file),
+            ..KeyConfig::default()
+        });
+    }
+    // TIAF_ADMIN_KEY holds a key, and TIAF_ADMIN_KEY_FILE names a file holding one.
+    if let Ok(key) = std::env::var("TIAF_ADMIN_KEY") {
+        server_config
+            .admin_keys
+            .push(KeyConfig::new("env", &key, &[]));
+    }
+    if let Ok(key_file) = std::env::var("TIAF_ADMIN_KEY_FILE") {
+        server_config.admin_keys.push(KeyConfig {
+            name: "env-file".to_string(),
+            key_file: Some(key_file.into()),
+            ..KeyConfig::default()
+        });
     }
 
     if let Some(log_level) = cli.log_level {
@@ -289,66 +236,75 @@ fn parse_arguments() -> Result<ServerConfig, String> {
     Ok(server_config)
 }
 
-#[derive(Clone)]
-struct ServerGlobals {
-    blockchain: Arc<RwLock<Blockchain>>,
-    mem_pool: Arc<RwLock<MemPool>>,
-    upstreams: Arc<RwLock<Upstreams>>,
-    downstreams: Arc<RwLock<Downstreams>>,
-}
-
 fn main() {
-    let logger = woody::new(woody::Level::Info).lock().unwrap();
+    let logger = woody::new(woody::Level::Info);
 
     let server_config = parse_arguments().unwrap();
-    logger.debug(notes!("server_config", format!("{:?}", server_config)));
-
-    let sg = ServerGlobals {
-        blockchain: Arc::new(RwLock::new(Blockchain::new())),
-        mem_pool: Arc::new(RwLock::new(MemPool::new(8))),
-        upstreams: Arc::new(RwLock::new(Upstreams::new(
-            server_config.upstreams.iter().map(ReadHost::new).collect(),
-        ))),
-        downstreams: Arc::new(RwLock::new(Downstreams::new(
-            server_config
-                .downstreams
-                .iter()
-                .map(WriteHost::new)
-                .collect(),
-        ))),
+    let admins: Vec<KeyConfig> = server_config
+        .admin_keys
+        .iter()
+        .map(|k| k.clone().admin())
+        .collect();
+    let cluster = server_config
+        .cluster
+        .as_ref()
+        .map(|config| ClusterKey::load(&server_config.node_id, config))
+        .transpose()
+        .unwrap();
+    let peer_auth = PeerAuth {
+        cluster: cluster.clone(),
+        tls: server_config
+            .peer_tls
+            .as_ref()
+            .map(ClientTls::load)
+            .transpose()
+            .unwrap(),
+        namespace: None,
+        genesis: None,
     };
+    logger
+        .lock()
+        .unwrap()
+        .debug(notes!("server_config", format!("{:?}", server_config)));
+
+    let namespaces = Namespaces::new(
+        Keys::load(&admins).unwrap(),
+        Duration::from_secs(server_config.idempotency_window_secs),
+    )
+    .with_peer_auth(peer_auth)
+    .with_data_dir(server_config.data_dir.clone())
+    .with_snapshot_key(
+        server_config
+            .snapshot_key_file
+            .as_deref()
+            .map(SnapshotKey::load)
+            .transpose()
+            .unwrap(),
+    );
+    let default = namespaces
+        .open(DEFAULT, server_config.chain.clone())
+        .unwrap();
+    let stored = namespaces.load_all().unwrap();
+    if let Some(file) = &server_config.snapshot_file {
+        bootstrap(&namespaces, file).unwrap();
+    }
+    // start the daemons of every chain: sealing the pool, and syncing with peers.
+    daemons::start(default);
+    for ns in stored {
+        logger.lock().unwrap().info(notes!(
+            "ts",
+            chrono::Utc::now().to_rfc3339(),
+            "msg",
+            "hosting stored chain".to_string(),
+            "chain",
+            ns.name().to_string(),
+            "length",
+            ns.blockchain.read().unwrap().length().to_string()
+        ));
+        daemons::start(ns);
+    }
 
-    let pool_sweeper_sg = sg.clone();
-    // start the mempool sweeper
-    thread::spawn(move || {
-        pool_sweeping_daemon(PoolSweeper {
-            chain: pool_sweeper_sg.blockchain,
-            mp: pool_sweeper_sg.mem_pool,
-            sleep_duration: Duration::from_secs(15),
-        })
-    });
-
-    let chain_sweeper_sg = sg.clone();
-    thread::spawn(move || {
-        upstream_sweeping_daemon(UpstreamSweeper {
-            chain: chain_sweeper_sg.blockchain,
-            sleep_duration: Duration::from_secs(15),
-            upstreams: chain_sweeper_sg.upstreams,
-        })
-    });
-
-    let peer_sg = sg.clone();
-    thread::spawn(move || {
-        downstream_notifying_daemon(DownstreamsNotifier {
-            mp: peer_sg.mem_pool,
-            sleep_duration: Duration::from_secs(15),
-            downstreams: peer_sg.downstreams,
-        })
-    });
-
-    let http_sg = sg.clone();
-
-    logger.info(notes!(
+    logger.lock().unwrap().info(notes!(
         "server",
         "launching server".to_string(),
         "port",
@@ -360,17 +316,56 @@ fn main() {
         server_config.node_id,
         server_config.ip,
         server_config.port,
-        AdminKey::new("bob"),
-        http_sg.blockchain,
-        http_sg.mem_pool,
-        http_sg.downstreams,
-        http_sg.upstreams,
+        Arc::new(namespaces),
+        cluster,
+        server_config.tls,
     );
 }
 
+// bootstrap takes up the snapshot in file, for the chain it is of, and catches up from its tip.
+fn bootstrap(namespaces: &Namespaces, file: &std::path::Path) -> Result<(), String> {
+    let logger = woody::new(woody::Level::Info);
+    let snapshot = Snapshot::load(file)?;
+    let ns = namespaces.get(&snapshot.chain).ok_or_else(|| {
+        format!(
+            "snapshot is of chain {}, which is not hosted",
+            snapshot.chain
+        )
+    })?;
+    if !ns.import(&snapshot)? {
+        logger.lock().unwrap().info(notes!(
+            "ts",
+            chrono::Utc::now().to_rfc3339(),
+            "msg",
+            "chain already reaches the snapshot".to_string(),
+            "chain",
+            ns.name().to_string()
+        ));
+        return Ok(());
+    }
+    let mut chain = ns.blockchain.write().unwrap();
+    ns.upstreams.write().unwrap().catch_up(&mut chain)?;
+    logger.lock().unwrap().info(notes!(
+        "ts",
+        chrono::Utc::now().to_rfc3339(),
+        "msg",
+        "began from snapshot".to_string(),
+        "chain",
+        ns.name().to_string(),
+        "tip",
+        snapshot.tip,
+        "length",
+        chain.length().to_string()
+    ));
+    Ok(())
+}
+
 #[cfg(test)]
 mod toplevel {
     use super::*;
+    use std::sync::RwLock;
+    use tiaf::chain::Blockchain;
+    use tiaf::record::Record;
 
     #[test]
     fn exercise() {
@@ -378,11 +373,11 @@ mod toplevel {
         let r2 = Record::new("sixes".to_string());
         let r3 = Record::new("sevens".to_string());
         let r4 = Record::new("eights".to_string());
-        let rs = vec![r2, r3, r4];
+        let rs = [r2, r3, r4];
         let r2 = Record::new("foo".to_string());
         let r3 = Record::new("bar ".to_string());
         let r4 = Record::new("baz".to_string());
-        let rs2 = vec![r2, r3, r4];
+        let rs2 = [r2, r3, r4];
         let bc = Arc::new(RwLock::new(Blockchain::new()));
         {
             let mut b = bc.write().unwrap();
@@ -51,14 +51,24 @@ impl Eq for Block {}
 impl Block {
     // first ever block
     pub(crate) fn genesis() -> Block {
-        let starter = Record::genesis_record();
+        Block::genesis_with(Record::genesis_record())
+    }
+    // first block of the chain called name
+    pub(crate) fn named_genesis(name: &str) -> Block {
+        Block::genesis_with(Record::named_genesis_record(name))
+    }
+    fn genesis_with(starter: Record) -> Block {
+        Block::genesis_from(vec![starter], 0)
+    }
+    // first block of a chain begun from a genesis file; see genesis::Genesis.
+    pub(crate) fn genesis_from(records: Vec<Record>, timestamp: Time) -> Block {
         let previous: Hashtype = GENESIS_INIT_HASH.to_string();
         let mut block = Block {
             index: 0,
             previous_hash: previous,
-            timestamp: 0,
+            timestamp,
             hash: BLOCK_INIT_HASH.to_string(),
-            data: vec![starter],
+            data: records,
         };
         block.update_hash();
         block
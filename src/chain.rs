@@ -1,7 +1,14 @@
 use crate::block::Block;
+use crate::events::{Event, Stored};
+use crate::genesis::Genesis;
+use crate::index::{Indexes, Location};
+use crate::kv::{KvConfig, KvView};
 use crate::record::Record;
+use crate::redaction::Redaction;
+use crate::schema::SchemaRegistry;
 use std::collections::HashMap;
 use std::str;
+use uuid::Uuid;
 
 use crate::types::Hashtype;
 use serde::de::DeserializeOwned;
@@ -17,10 +24,28 @@ pub struct Blockchain {
     known_block_hashes: Vec<Hashtype>,
     #[serde(skip)]
     known_record_hashes: Vec<Hashtype>,
+    // uuids locates every record by its uuid, so a write retried under a client-chosen uuid is
+    // found once sealed.
+    #[serde(skip)]
+    uuids: HashMap<Uuid, Location>,
     // max_verified is the highest index that has been verified.
     //
     #[serde(skip)]
     max_verified: u64,
+    // indexes are derived from the blocks, so they are rebuilt rather than serialized.
+    #[serde(skip)]
+    indexes: Indexes,
+    #[serde(skip)]
+    kv: Option<KvView>,
+    // redaction_keys are the public keys whose redactions the chain accepts.
+    #[serde(skip)]
+    redaction_keys: Vec<String>,
+    // redactions counts the redacted records on the chain.
+    #[serde(skip)]
+    redactions: u64,
+    // schemas are those defined on the chain, with any registered since and not yet sealed.
+    #[serde(skip)]
+    schemas: SchemaRegistry,
 }
 
 impl PartialEq for Blockchain {
@@ -76,21 +101,99 @@ impl Default for Blockchain {
 
 impl Blockchain {
     pub fn new() -> Blockchain {
+        Blockchain::with_genesis(Block::genesis())
+    }
+
+    // named is a new chain of its own, which no block of another chain can be appended to.
+    pub fn named(name: &str) -> Blockchain {
+        Blockchain::with_genesis(Block::named_genesis(name))
+    }
+
+    // from_genesis is a new chain begun as genesis says. Every node given the same genesis
+    // begins the same chain.
+    pub fn from_genesis(genesis: &Genesis) -> Result<Blockchain, String> {
+        Ok(Blockchain::with_genesis(genesis.block()?))
+    }
+
+    fn with_genesis(genesis: Block) -> Blockchain {
+        let mut schemas = SchemaRegistry::new();
+        schemas.add_block(&genesis);
+        let uuids = genesis
+            .data
+            .iter()
+            .enumerate()
+            .map(|(record, r)| (r.uuid, Location { block: 0, record }))
+            .collect();
         let mut data = HashMap::new();
-        let genesis = Block::genesis();
         data.insert(0, genesis.clone());
         Blockchain {
             data,
             size: 1,
             max_verified: 0,
-            known_record_hashes: vec![genesis.data[0].hash.clone()],
+            known_record_hashes: genesis.data.iter().map(|r| r.hash.clone()).collect(),
             known_block_hashes: vec![genesis.hash],
+            uuids,
+            indexes: Indexes::default(),
+            kv: None,
+            redaction_keys: vec![],
+            redactions: 0,
+            schemas,
         }
     }
+
+    // genesis_hash identifies the chain: peers on the same chain share it.
+    pub fn genesis_hash(&self) -> &Hashtype {
+        &self.data[&0].hash
+    }
+
+    // tip is the hash of the latest block.
+    pub fn tip(&self) -> &Hashtype {
+        &self.data[&(self.size - 1)].hash
+    }
+
+    // state is the chain as a snapshot holds it: its blocks, and what is derived from them.
+    pub fn state(&self) -> ChainState {
+        ChainState {
+            blocks: self.blocks().cloned().collect(),
+            indexes: self.indexes.clone(),
+            kv: self.kv.clone(),
+        }
+    }
+
+    // restore is the chain state holds, taken on trust rather than replayed: blocks are checked
+    // to follow one another, but are not rehashed, and indexes and the key-value view are taken
+    // as they are. Only state from a source already trusted, such as a signed snapshot, should
+    // be restored.
+    pub fn restore(state: ChainState, redaction_keys: &[String]) -> Result<Blockchain, String> {
+        let mut blocks = state.blocks.into_iter();
+        let genesis = blocks.next().ok_or("state holds no blocks")?;
+        let mut chain = Blockchain::with_genesis(genesis);
+        chain.set_redaction_keys(redaction_keys)?;
+        for block in blocks {
+            if block.previous_hash() != chain.tip() {
+                return Err(format!(
+                    "block {} does not follow the block before it",
+                    chain.size
+                ));
+            }
+            chain.check_redactions(&block)?;
+            chain.admit(block);
+        }
+        chain.max_verified = chain.size - 1;
+        chain.indexes = state.indexes;
+        chain.kv = state.kv;
+        Ok(chain)
+    }
+
     pub fn get(&self, idx: u64) -> Option<&Block> {
         self.data.get(&idx)
     }
 
+    // blocks walks the chain in order, borrowing rather than cloning each block.
+    pub fn blocks(&self) -> impl Iterator<Item = &Block> {
+        (0..self.size).filter_map(|i| self.data.get(&i))
+    }
+
     pub fn tail(&self, n: u64) -> Vec<&Block> {
         let mut tail: Vec<&Block> = Vec::new();
         if n > self.size {
@@ -123,10 +226,9 @@ impl Blockchain {
             return Err("Blockchain size does not match data size".to_string());
         }
         for i in self.max_verified..self.size {
-            self.data
-                .get(&{ i })
-                .ok_or("no data found at index")?
-                .validate()?
+            let block = self.data.get(&{ i }).ok_or("no data found at index")?;
+            block.validate()?;
+            self.check_redactions(block)?;
         }
         self.max_verified = self.size - 1;
         Ok(())
@@ -136,11 +238,68 @@ impl Blockchain {
             return Err("Blockchain size does not match data size".to_string());
         }
         for i in self.max_verified..self.size {
-            self.data
-                .get(&{ i })
-                .ok_or("no data found at index")?
-                .validate()?
+            let block = self.data.get(&{ i }).ok_or("no data found at index")?;
+            block.validate()?;
+            self.check_redactions(block)?;
+        }
+        Ok(())
+    }
+
+    // check_redactions checks that every redaction in block was signed by one of our redaction
+    // keys.
+    fn check_redactions(&self, block: &Block) -> Result<(), String> {
+        for r in &block.data {
+            if let Some(redaction) = &r.redaction {
+                if !self.redaction_keys.contains(&redaction.key) {
+                    return Err(format!(
+                        "record {} was redacted by {}, which is not a redaction key",
+                        r.hash, redaction.key
+                    ));
+                }
+                redaction.verify(&r.hash)?;
+            }
+        }
+        Ok(())
+    }
+
+    // set_redaction_keys sets the public keys whose redactions the chain accepts.
+    pub fn set_redaction_keys(&mut self, keys: &[String]) -> Result<(), String> {
+        for key in keys {
+            crate::signing::public_key(key)?;
+        }
+        self.redaction_keys = keys.to_vec();
+        Ok(())
+    }
+
+    pub fn redactions(&self) -> u64 {
+        self.redactions
+    }
+
+    // redact replaces the entry of the record hashed h with a tombstone. The block hash is
+    // unchanged, so the chain stays valid; indexes and the key-value view are rebuilt, so that
+    // nothing of the entry remains. Records of the genesis block are never redacted.
+    pub fn redact(&mut self, h: &Hashtype, redaction: Redaction) -> Result<(), String> {
+        if !self.redaction_keys.contains(&redaction.key) {
+            return Err(format!("{} is not a redaction key", redaction.key));
         }
+        let at = (1..self.size)
+            .find_map(|i| {
+                let block = self.data.get(&i)?;
+                let record = block.data.iter().position(|r| &r.hash == h)?;
+                Some(Location { block: i, record })
+            })
+            .ok_or_else(|| format!("record {h} is not on the chain"))?;
+        let block = self
+            .data
+            .get_mut(&at.block)
+            .ok_or("no data found at index")?;
+        block.data[at.record].redact(redaction)?;
+        self.redactions += 1;
+
+        let fields = self.indexes.fields();
+        self.indexes = Indexes::default();
+        self.set_indexes(&fields);
+        self.set_kv(self.kv.as_ref().map(|kv| kv.config().clone()));
         Ok(())
     }
 
@@ -152,6 +311,11 @@ impl Blockchain {
         self.known_record_hashes.contains(h)
     }
 
+    // record_with_uuid is the first record on the chain with the given uuid.
+    pub fn record_with_uuid(&self, uuid: &Uuid) -> Option<&Record> {
+        self.uuids.get(uuid).and_then(|at| self.record_at(*at))
+    }
+
     pub fn block_seen(&self, h: &Hashtype) -> bool {
         self.known_block_hashes.contains(h)
     }
@@ -170,16 +334,9 @@ impl Blockchain {
         let previous_block: &Block = self.get(self.size - 1).ok_or("no data found at index")?;
         let previous_hash = previous_block.hash.clone();
 
-        for record in &records {
-            self.known_record_hashes.push(record.hash.clone());
-        }
-
         let block = Block::new(self.size, previous_hash, records);
-
-        self.known_block_hashes.push(block.hash.clone());
-        self.data.insert(self.size, block);
-
-        self.size += 1;
+        self.check_redactions(&block)?;
+        self.admit(block);
         Ok(())
     }
 
@@ -188,18 +345,101 @@ impl Blockchain {
         if *blocks[0].previous_hash() != self.get(self.size - 1).unwrap().hash {
             return Err("blockchain does not match".to_string());
         }
-
-        let blocks = blocks.clone();
         for block in &blocks {
-            self.known_block_hashes.push(block.hash.clone());
+            self.check_redactions(block)?;
         }
+
         for block in blocks {
-            self.data.insert(self.size, block);
-            self.size += 1;
+            self.admit(block);
         }
         Ok(())
     }
 
+    // admit is the one place a block joins the chain. Everything derived from the blocks,
+    // known hashes, indexes, the key-value view and schemas alike, is brought up to date here.
+    fn admit(&mut self, block: Block) {
+        for (i, record) in block.data.iter().enumerate() {
+            self.known_record_hashes.push(record.hash.clone());
+            let at = Location {
+                block: self.size,
+                record: i,
+            };
+            self.uuids.entry(record.uuid).or_insert(at);
+            if record.is_redacted() {
+                self.redactions += 1;
+            }
+        }
+        self.known_block_hashes.push(block.hash.clone());
+        self.indexes.add_block(self.size, &block);
+        if let Some(kv) = self.kv.as_mut() {
+            kv.add_block(self.size, &block);
+        }
+        self.schemas.add_block(&block);
+        self.data.insert(self.size, block);
+        self.size += 1;
+    }
+
+    // create_index starts maintaining an index on an entry field, built over the chain so far.
+    pub fn create_index(&mut self, field: &str) {
+        let blocks = (0..self.size).filter_map(|i| self.data.get(&i).map(|b| (i, b)));
+        self.indexes.declare(field, blocks);
+    }
+
+    pub fn drop_index(&mut self, field: &str) {
+        self.indexes.remove(field);
+    }
+
+    // set_indexes makes fields exactly the set of indexed fields.
+    pub fn set_indexes(&mut self, fields: &[String]) {
+        for field in self.indexes.fields() {
+            if !fields.contains(&field) {
+                self.drop_index(&field);
+            }
+        }
+        for field in fields {
+            self.create_index(field);
+        }
+    }
+
+    pub fn indexes(&self) -> &Indexes {
+        &self.indexes
+    }
+
+    // set_kv maintains a key-value view of the chain as configured, built over the chain so
+    // far, or stops maintaining one.
+    pub fn set_kv(&mut self, config: Option<KvConfig>) {
+        self.kv = config.map(|config| {
+            let mut view = KvView::new(config);
+            for i in 0..self.size {
+                if let Some(block) = self.data.get(&i) {
+                    view.add_block(i, block);
+                }
+            }
+            view
+        });
+    }
+
+    pub fn kv(&self) -> Option<&KvView> {
+        self.kv.as_ref()
+    }
+
+    pub fn schemas(&self) -> &SchemaRegistry {
+        &self.schemas
+    }
+
+    pub fn schemas_mut(&mut self) -> &mut SchemaRegistry {
+        &mut self.schemas
+    }
+
+    // events streams the events of type T on the chain, oldest first. See events::Event.
+    pub fn events<T: Event>(&self) -> impl Iterator<Item = Result<Stored<T>, String>> + '_ {
+        crate::events::events_in(self.blocks())
+    }
+
+    pub fn record_at(&self, at: Location) -> Option<&Record> {
+        self.get(at.block).and_then(|b| b.data.get(at.record))
+    }
+
     pub fn to_json(&self, validation: bool) -> Result<String, String> {
         if validation {
             self.full_validate()?;
@@ -242,6 +482,15 @@ impl Blockchain {
     }
 }
 
+/// ChainState is a chain's blocks, in order, along with the indexes and key-value view derived
+/// from them.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChainState {
+    pub blocks: Vec<Block>,
+    pub indexes: Indexes,
+    pub kv: Option<KvView>,
+}
+
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ChainComparison {
     Longer,
@@ -249,14 +498,14 @@ pub enum ChainComparison {
     Invalid(String),
 }
 
-#[allow(dead_code)]
-fn deserialize_blocks<T>(links: Vec<Block>) -> Result<Vec<T>, String>
+// deserialize_blocks reads every entry in links that parses as a T, skipping the rest. It suits
+// chains of plain, untagged JSON entries; for typed data see Blockchain::events.
+pub fn deserialize_blocks<T>(links: Vec<Block>) -> Result<Vec<T>, String>
 where
     T: DeserializeOwned,
 {
     let mut records: Vec<T> = vec![];
     for l in links {
-        println!("block: {l:?}");
         for r in l.data {
             if let Ok(record) = serde_json::from_str(r.entry.as_str()) {
                 records.push(record)
@@ -270,7 +519,9 @@ where
 mod tests {
     use crate::block::Block;
     use crate::chain::{deserialize_blocks, Blockchain};
+    use crate::pratt::Value;
     use crate::record::Record;
+    use crate::redaction::{Redaction, RedactionKey};
     use rand::distributions::{Alphanumeric, DistString};
     use std::sync::{Arc, Mutex};
 
@@ -350,6 +601,39 @@ mod tests {
         assert_eq!(b.data.get(&1).unwrap().data.len(), 10);
         assert_eq!(b.data.get(&2).unwrap().data.len(), 10);
     }
+    #[test]
+    fn test_indexes_follow_appends() {
+        let mut b = Blockchain::new();
+        let rec = |v: &str| Record::new(format!("{{\"k\": \"{v}\"}}"));
+        b.append_records(vec![rec("a"), rec("b")]).unwrap();
+        b.create_index("k");
+        b.append_records(vec![Record::new("unstructured".to_string()), rec("a")])
+            .unwrap();
+
+        let mut other = Blockchain::new();
+        other.set_indexes(&["k".to_string()]);
+        other
+            .append_blocks(vec![b.get(1).unwrap().clone(), b.get(2).unwrap().clone()])
+            .unwrap();
+
+        for chain in [&b, &other] {
+            let found = chain
+                .indexes()
+                .get("k")
+                .unwrap()
+                .get(&Value::Str("a".to_string()));
+            let records: Vec<&Record> = found.iter().filter_map(|l| chain.record_at(*l)).collect();
+            assert_eq!(
+                records,
+                vec![&b.get(1).unwrap().data[0], &b.get(2).unwrap().data[1]]
+            );
+        }
+        assert!(other.record_seen(&b.get(2).unwrap().data[1].hash));
+
+        other.set_indexes(&[]);
+        assert!(other.indexes().fields().is_empty());
+    }
+
     #[test]
     fn test_blockhashes() {
         let bc = Arc::new(Mutex::new(Blockchain::new()));
@@ -372,7 +656,7 @@ mod tests {
         b.append_records(r2.clone()).unwrap();
         let hashes = b.known_record_hashes.clone();
         assert_eq!(hashes.len(), 21);
-        vec![r1, r2].iter().for_each(|r| {
+        [r1, r2].iter().for_each(|r| {
             r.iter().for_each(|r| {
                 assert!(hashes.contains(&r.hash));
             })
@@ -392,7 +676,7 @@ mod tests {
             b.append_records(r2.clone()).unwrap();
             let hashes = b.known_record_hashes.clone();
             assert_eq!(hashes.len(), 21);
-            vec![r1, r2].iter().for_each(|r| {
+            [r1, r2].iter().for_each(|r| {
                 r.iter().for_each(|r| {
                     assert!(hashes.contains(&r.hash));
                 })
@@ -407,7 +691,7 @@ mod tests {
             b.append_new_records(r2.clone()).unwrap();
             let hashes = b.known_record_hashes.clone();
             assert_eq!(hashes.len(), 21);
-            vec![r1, r2].iter().for_each(|r| {
+            [r1, r2].iter().for_each(|r| {
                 r.iter().for_each(|r| {
                     assert!(hashes.contains(&r.hash));
                 })
@@ -422,17 +706,98 @@ mod tests {
             b.append_new_records(r1.clone()).unwrap();
             let hashes = b.known_record_hashes.clone();
             assert_eq!(hashes.len(), 11);
-            vec![r1].iter().for_each(|r| {
+            [r1].iter().for_each(|r| {
                 r.iter().for_each(|r| {
                     assert!(hashes.contains(&r.hash));
                 })
             });
             // verify didn't see what ain'tthere.
-            vec![r2].iter().for_each(|r| {
+            [r2].iter().for_each(|r| {
                 r.iter().for_each(|r| {
                     assert!(!hashes.contains(&r.hash));
                 })
             });
         }
     }
+
+    #[test]
+    fn test_named_chains_do_not_mix() {
+        let mut a = Blockchain::named("a");
+        let mut b = Blockchain::named("b");
+        assert_ne!(a.get(0).unwrap().hash, b.get(0).unwrap().hash);
+        assert_ne!(
+            a.get(0).unwrap().hash,
+            Blockchain::new().get(0).unwrap().hash
+        );
+        assert_eq!(a.get(0), Blockchain::named("a").get(0));
+
+        a.append_records(generate_records(2)).unwrap();
+        let block = a.get(1).unwrap().clone();
+        assert!(b.append_blocks(vec![block.clone()]).is_err());
+        let mut a2 = Blockchain::named("a");
+        a2.append_blocks(vec![block]).unwrap();
+        a2.validate().unwrap();
+    }
+
+    #[test]
+    fn test_redaction_keeps_the_chain_valid() {
+        let admin = RedactionKey::generate();
+        let mut chain = Blockchain::new();
+        chain.set_redaction_keys(&[admin.public()]).unwrap();
+        chain.set_indexes(&["ssn".to_string()]);
+        chain
+            .append_records(vec![
+                Record::new("{\"ssn\": \"078-05-1120\"}".to_string()),
+                Record::new("{\"ssn\": \"219-09-9999\"}".to_string()),
+            ])
+            .unwrap();
+        let block = chain.get(1).unwrap().clone();
+        let target = block.data[0].hash.clone();
+
+        let outsider = RedactionKey::generate();
+        assert!(chain
+            .redact(&target, Redaction::sign(&outsider, &target, "erasure"))
+            .is_err());
+        assert!(chain
+            .redact(
+                &target,
+                Redaction::sign(&admin, &block.data[1].hash, "erasure")
+            )
+            .is_err());
+        let genesis = chain.get(0).unwrap().data[0].hash.clone();
+        assert!(chain
+            .redact(&genesis, Redaction::sign(&admin, &genesis, "erasure"))
+            .is_err());
+
+        chain
+            .redact(&target, Redaction::sign(&admin, &target, "erasure"))
+            .unwrap();
+        assert!(chain
+            .redact(&target, Redaction::sign(&admin, &target, "again"))
+            .is_err());
+        let redacted = chain.get(1).unwrap();
+        assert_eq!(redacted.hash, block.hash);
+        assert_eq!(redacted.data[0].hash, target);
+        assert!(redacted.data[0].entry.is_empty());
+        chain.full_validate().unwrap();
+        assert_eq!(chain.redactions(), 1);
+        assert_eq!(chain.indexes().get("ssn").unwrap().distinct_values(), 1);
+        assert!(redacted.data[0].structured_entry().is_err());
+
+        // a chain takes up the tombstone only if it trusts the key that signed it.
+        let tombstoned = chain.get(1).unwrap().clone();
+        let mut trusting = Blockchain::new();
+        trusting.set_redaction_keys(&[admin.public()]).unwrap();
+        trusting.append_blocks(vec![tombstoned.clone()]).unwrap();
+        trusting.full_validate().unwrap();
+        assert!(Blockchain::new()
+            .append_blocks(vec![tombstoned.clone()])
+            .is_err());
+        assert!(Blockchain::new()
+            .append_records(tombstoned.data.clone())
+            .is_err());
+        let mut forged = block;
+        forged.data[0].entry = String::new();
+        assert!(forged.validate().is_err());
+    }
 }
@@ -0,0 +1,251 @@
+// Cluster membership. The nodes of a cluster share a secret and sign the requests they make of
+// each other with it, so that only members may hand over records or sync blocks. A signature
+// covers the method, path, time, sending node and body of a request. The node answering signs
+// its response in turn, bound to the request, so a node syncing knows the blocks it got came
+// from a member.
+use crate::hexdisplay::{hex, unhex};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use std::fmt;
+use std::path::PathBuf;
+
+type HmacSha3 = Hmac<Sha3_256>;
+
+pub const NODE_HEADER: &str = "X-TIAF-NODE";
+pub const TIMESTAMP_HEADER: &str = "X-TIAF-TIMESTAMP";
+pub const SIGNATURE_HEADER: &str = "X-TIAF-SIGNATURE";
+
+fn default_max_skew_secs() -> u64 {
+    300
+}
+
+/// ClusterConfig is the `[cluster]` section of the server config. The secret is given either
+/// inline or as a file holding it.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ClusterConfig {
+    #[serde(default)]
+    pub secret: Option<String>,
+    #[serde(default)]
+    pub secret_file: Option<PathBuf>,
+    // max_skew_secs is how far a signed request's time may be from ours. Records are deduplicated
+    // by hash, so a request replayed within it does no harm.
+    #[serde(default = "default_max_skew_secs")]
+    pub max_skew_secs: u64,
+}
+
+impl Default for ClusterConfig {
+    fn default() -> ClusterConfig {
+        ClusterConfig {
+            secret: None,
+            secret_file: None,
+            max_skew_secs: default_max_skew_secs(),
+        }
+    }
+}
+
+impl fmt::Debug for ClusterConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ClusterConfig")
+            .field("secret", &self.secret.as_ref().map(|_| "<redacted>"))
+            .field("secret_file", &self.secret_file)
+            .field("max_skew_secs", &self.max_skew_secs)
+            .finish()
+    }
+}
+
+/// ClusterKey is what a node signs and checks peer traffic with: the cluster secret, and the id
+/// it signs as.
+#[derive(Clone)]
+pub struct ClusterKey {
+    node_id: String,
+    secret: Vec<u8>,
+    max_skew_secs: i64,
+}
+
+impl fmt::Debug for ClusterKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ClusterKey")
+            .field("node_id", &self.node_id)
+            .field("secret", &"<redacted>")
+            .finish()
+    }
+}
+
+fn digest(body: &[u8]) -> String {
+    hex(&Sha3_256::digest(body))
+}
+
+impl ClusterKey {
+    pub fn new(node_id: &str, secret: &str) -> ClusterKey {
+        ClusterKey {
+            node_id: node_id.to_string(),
+            secret: secret.as_bytes().to_vec(),
+            max_skew_secs: default_max_skew_secs() as i64,
+        }
+    }
+
+    // load reads the secret of config, from a file if it says to.
+    pub fn load(node_id: &str, config: &ClusterConfig) -> Result<ClusterKey, String> {
+        let secret = match (&config.secret, &config.secret_file) {
+            (Some(secret), None) => secret.clone(),
+            (None, Some(path)) => std::fs::read_to_string(path)
+                .map_err(|e| format!("failed to read cluster secret: {e}"))?
+                .trim_end_matches(['\r', '\n'])
+                .to_string(),
+            _ => return Err("cluster needs exactly one of secret and secret_file".to_string()),
+        };
+        if secret.is_empty() {
+            return Err("cluster secret is empty".to_string());
+        }
+        Ok(ClusterKey {
+            max_skew_secs: config.max_skew_secs as i64,
+            ..ClusterKey::new(node_id, &secret)
+        })
+    }
+
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    fn mac(&self, parts: &[&str]) -> HmacSha3 {
+        let mut mac = HmacSha3::new_from_slice(&self.secret).expect("hmac takes keys of any size");
+        for part in parts {
+            mac.update(part.as_bytes());
+            mac.update(b"\n");
+        }
+        mac
+    }
+
+    // sign_request gives the headers signing a request of ours. path includes any query.
+    pub fn sign_request(&self, method: &str, path: &str, body: &[u8]) -> Vec<(String, String)> {
+        self.sign_request_at(method, path, body, Utc::now().timestamp())
+    }
+
+    fn sign_request_at(
+        &self,
+        method: &str,
+        path: &str,
+        body: &[u8],
+        now: i64,
+    ) -> Vec<(String, String)> {
+        let timestamp = now.to_string();
+        let mac = self.mac(&[method, path, &timestamp, &self.node_id, &digest(body)]);
+        vec![
+            (NODE_HEADER.to_string(), self.node_id.clone()),
+            (TIMESTAMP_HEADER.to_string(), timestamp),
+            (
+                SIGNATURE_HEADER.to_string(),
+                hex(&mac.finalize().into_bytes()),
+            ),
+        ]
+    }
+
+    // verify_request checks a request signed by a peer, given its headers.
+    pub fn verify_request(
+        &self,
+        method: &str,
+        path: &str,
+        node: &str,
+        timestamp: &str,
+        signature: &str,
+        body: &[u8],
+    ) -> Result<(), String> {
+        self.verify_request_at(
+            method,
+            path,
+            node,
+            timestamp,
+            signature,
+            body,
+            Utc::now().timestamp(),
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn verify_request_at(
+        &self,
+        method: &str,
+        path: &str,
+        node: &str,
+        timestamp: &str,
+        signature: &str,
+        body: &[u8],
+        now: i64,
+    ) -> Result<(), String> {
+        let signed_at: i64 = timestamp
+            .parse()
+            .map_err(|_| "signature has no time".to_string())?;
+        if (now - signed_at).abs() > self.max_skew_secs {
+            return Err("signature is too old or too new".to_string());
+        }
+        let signature = unhex(signature).ok_or_else(|| "signature is not hex".to_string())?;
+        self.mac(&[method, path, timestamp, node, &digest(body)])
+            .verify_slice(&signature)
+            .map_err(|_| "bad signature".to_string())
+    }
+
+    // sign_response signs our answer to a peer's request, given the request's signature.
+    pub fn sign_response(&self, request_signature: &str, status: u16, body: &[u8]) -> String {
+        let mac = self.mac(&[request_signature, &status.to_string(), &digest(body)]);
+        hex(&mac.finalize().into_bytes())
+    }
+
+    // verify_response checks that a peer's answer to our request came from a member.
+    pub fn verify_response(
+        &self,
+        request_signature: &str,
+        status: u16,
+        body: &[u8],
+        signature: &str,
+    ) -> Result<(), String> {
+        let signature = unhex(signature).ok_or_else(|| "signature is not hex".to_string())?;
+        self.mac(&[request_signature, &status.to_string(), &digest(body)])
+            .verify_slice(&signature)
+            .map_err(|_| "response is not signed by a member of the cluster".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signatures() {
+        let a = ClusterKey::new("a", "shared");
+        let b = ClusterKey::new("b", "shared");
+        let outsider = ClusterKey::new("c", "guessed");
+        let now = 1_700_000_000;
+
+        let headers = a.sign_request_at("POST", "/api/v1/record", b"{}", now);
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.clone())
+                .unwrap()
+        };
+        let (node, ts, sig) = (
+            header(NODE_HEADER),
+            header(TIMESTAMP_HEADER),
+            header(SIGNATURE_HEADER),
+        );
+        let verify = |key: &ClusterKey, path: &str, body: &[u8], at: i64| {
+            key.verify_request_at("POST", path, &node, &ts, &sig, body, at)
+        };
+        assert_eq!(verify(&b, "/api/v1/record", b"{}", now + 10), Ok(()));
+        assert!(verify(&outsider, "/api/v1/record", b"{}", now).is_err());
+        assert!(verify(&b, "/api/v1/record", b"{\"x\":1}", now).is_err());
+        assert!(verify(&b, "/api/v1/data", b"{}", now).is_err());
+        assert!(verify(&b, "/api/v1/record", b"{}", now + 301).is_err());
+
+        let response = b.sign_response(&sig, 200, b"blocks");
+        assert_eq!(a.verify_response(&sig, 200, b"blocks", &response), Ok(()));
+        assert!(a.verify_response(&sig, 200, b"other", &response).is_err());
+        assert!(outsider
+            .verify_response(&sig, 200, b"blocks", &response)
+            .is_err());
+        assert!(!format!("{a:?}").contains("shared"));
+    }
+}
@@ -0,0 +1,371 @@
+// Credentials, and what they allow. Every key is named and carries scopes: read, write, peer or
+// admin. A node may hold several keys at once, so a key can be rotated without downtime: add the
+// new key, move clients over, then let the old one expire or remove it. Keys come from the
+// config, from the environment, or from a secrets file, and are never printed.
+use crate::api::AdminKey;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::PathBuf;
+
+/// Scope is a kind of request a key may make. Admin allows every other scope too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    // Read is reading the chain, queries, schemas and statistics.
+    Read,
+    // Write is sending data to be recorded.
+    Write,
+    // Peer is another node handing over records.
+    Peer,
+    // Admin is changing how the node runs.
+    Admin,
+}
+
+impl Scope {
+    pub fn allows(self, scope: Scope) -> bool {
+        self == scope || self == Scope::Admin
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Scope::Read => "read",
+            Scope::Write => "write",
+            Scope::Peer => "peer",
+            Scope::Admin => "admin",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// KeyConfig is one `[[admin_keys]]` or `[[api_keys]]` entry of the server config. The key is
+/// given either inline or as a file holding it; expires, if set, is an RFC 3339 time after which
+/// the key is no longer accepted. Admin keys hold every scope, whatever scopes says.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct KeyConfig {
+    pub name: String,
+    #[serde(default)]
+    pub key: Option<String>,
+    #[serde(default)]
+    pub key_file: Option<PathBuf>,
+    #[serde(default)]
+    pub expires: Option<String>,
+    #[serde(default)]
+    pub scopes: Vec<Scope>,
+}
+
+impl KeyConfig {
+    pub fn new(name: &str, key: &str, scopes: &[Scope]) -> KeyConfig {
+        KeyConfig {
+            name: name.to_string(),
+            key: Some(key.to_string()),
+            scopes: scopes.to_vec(),
+            ..KeyConfig::default()
+        }
+    }
+
+    // admin is this key with every scope.
+    pub fn admin(self) -> KeyConfig {
+        KeyConfig {
+            scopes: vec![Scope::Admin],
+            ..self
+        }
+    }
+}
+
+impl fmt::Debug for KeyConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("KeyConfig")
+            .field("name", &self.name)
+            .field("key", &self.key.as_ref().map(|_| "<redacted>"))
+            .field("key_file", &self.key_file)
+            .field("expires", &self.expires)
+            .field("scopes", &self.scopes)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct NamedKey {
+    name: String,
+    key: AdminKey,
+    expires: Option<DateTime<Utc>>,
+    scopes: Vec<Scope>,
+}
+
+/// Keys is every key a node accepts.
+#[derive(Debug, Clone, Default)]
+pub struct Keys {
+    keys: Vec<NamedKey>,
+}
+
+impl Keys {
+    // load reads the keys of configs, from files where they say to.
+    pub fn load(configs: &[KeyConfig]) -> Result<Keys, String> {
+        let mut keys = vec![];
+        for config in configs {
+            let key = match (&config.key, &config.key_file) {
+                (Some(key), None) => key.clone(),
+                (None, Some(path)) => std::fs::read_to_string(path)
+                    .map_err(|e| format!("failed to read key {}: {e}", config.name))?
+                    .trim_end_matches(['\r', '\n'])
+                    .to_string(),
+                _ => {
+                    return Err(format!(
+                        "key {} needs exactly one of key and key_file",
+                        config.name
+                    ))
+                }
+            };
+            if key.is_empty() {
+                return Err(format!("key {} is empty", config.name));
+            }
+            if config.scopes.is_empty() {
+                return Err(format!("key {} has no scopes", config.name));
+            }
+            let expires = match &config.expires {
+                Some(expires) => Some(
+                    DateTime::parse_from_rfc3339(expires)
+                        .map_err(|e| format!("key {} expires: {e}", config.name))?
+                        .with_timezone(&Utc),
+                ),
+                None => None,
+            };
+            if keys.iter().any(|k: &NamedKey| k.name == config.name) {
+                return Err(format!("key {} is named twice", config.name));
+            }
+            keys.push(NamedKey {
+                name: config.name.clone(),
+                key: AdminKey::new(&key),
+                expires,
+                scopes: config.scopes.clone(),
+            });
+        }
+        Ok(Keys { keys })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    // and is these keys together with others, which may not reuse their names.
+    pub fn and(&self, others: Keys) -> Result<Keys, String> {
+        let mut keys = self.keys.clone();
+        for k in others.keys {
+            if keys.iter().any(|held| held.name == k.name) {
+                return Err(format!("key {} is named twice", k.name));
+            }
+            keys.push(k);
+        }
+        Ok(Keys { keys })
+    }
+
+    // names lists the name of every key, with its scopes.
+    pub fn names(&self) -> Vec<String> {
+        self.keys
+            .iter()
+            .map(|k| {
+                let scopes: Vec<String> = k.scopes.iter().map(|s| s.to_string()).collect();
+                format!("{}({})", k.name, scopes.join("+"))
+            })
+            .collect()
+    }
+
+    // verify returns the name of the key presented, if it is one we accept. Every key is
+    // compared, so the time taken does not say which one matched.
+    pub fn verify(&self, presented: &str) -> Option<&str> {
+        self.verify_at(presented, Utc::now())
+            .map(|k| k.name.as_str())
+    }
+
+    fn verify_at(&self, presented: &str, now: DateTime<Utc>) -> Option<&NamedKey> {
+        let mut matched = None;
+        for k in &self.keys {
+            let live = k.expires.is_none_or(|expires| now < expires);
+            if k.key.eq_str(presented) && live {
+                matched = Some(k);
+            }
+        }
+        matched
+    }
+}
+
+/// Denied is why a request was refused: it came without a key we accept, or its key does not
+/// hold the scope the request needs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Denied {
+    Unauthenticated,
+    Forbidden(Scope),
+}
+
+/// Access decides which requests a node serves. Every request is allowed the anonymous scopes,
+/// and a request presenting keys is allowed what they hold besides. A key that is presented but
+/// not accepted is refused outright, rather than treated as no key at all.
+#[derive(Debug, Clone, Default)]
+pub struct Access {
+    keys: Keys,
+    anonymous: Vec<Scope>,
+}
+
+impl Access {
+    pub fn new(keys: Keys, anonymous: &[Scope]) -> Access {
+        Access {
+            keys,
+            anonymous: anonymous.to_vec(),
+        }
+    }
+
+    pub fn keys(&self) -> &Keys {
+        &self.keys
+    }
+
+    pub fn anonymous(&self) -> &[Scope] {
+        &self.anonymous
+    }
+
+    // check says whether a request presenting keys may make a request needing scope. Empty keys
+    // count as absent.
+    pub fn check(&self, presented: &[Option<&str>], scope: Scope) -> Result<(), Denied> {
+        self.check_at(presented, scope, &self.anonymous, Utc::now())
+    }
+
+    // check_in_cluster is check on a node whose cluster can tell its members apart. Peer
+    // traffic is then for members and holders of peer keys alone, so anonymous requests are
+    // not allowed the peer scope, whatever the chain's config says.
+    pub fn check_in_cluster(&self, presented: &[Option<&str>], scope: Scope) -> Result<(), Denied> {
+        let anonymous: Vec<Scope> = self
+            .anonymous
+            .iter()
+            .copied()
+            .filter(|s| *s != Scope::Peer)
+            .collect();
+        self.check_at(presented, scope, &anonymous, Utc::now())
+    }
+
+    fn check_at(
+        &self,
+        presented: &[Option<&str>],
+        scope: Scope,
+        anonymous: &[Scope],
+        now: DateTime<Utc>,
+    ) -> Result<(), Denied> {
+        let mut held = anonymous.to_vec();
+        let mut authenticated = false;
+        for key in presented.iter().flatten().filter(|k| !k.is_empty()) {
+            match self.keys.verify_at(key, now) {
+                Some(k) => held.extend(k.scopes.iter().copied()),
+                None => return Err(Denied::Unauthenticated),
+            }
+            authenticated = true;
+        }
+        if held.iter().any(|s| s.allows(scope)) {
+            Ok(())
+        } else if authenticated {
+            Err(Denied::Forbidden(scope))
+        } else {
+            Err(Denied::Unauthenticated)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys() {
+        let file = std::env::temp_dir().join(format!("tiaf-admin-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&file, "from-file\n").unwrap();
+        let keys = Keys::load(&[
+            KeyConfig {
+                expires: Some("2030-01-01T00:00:00Z".to_string()),
+                ..KeyConfig::new("old", "s3cret", &[]).admin()
+            },
+            KeyConfig {
+                name: "new".to_string(),
+                key_file: Some(file.clone()),
+                scopes: vec![Scope::Admin],
+                ..KeyConfig::default()
+            },
+        ])
+        .unwrap();
+        std::fs::remove_file(file).unwrap();
+
+        let before = "2029-12-31T00:00:00Z".parse().unwrap();
+        let after = "2030-01-02T00:00:00Z".parse().unwrap();
+        let name = |k: Option<&NamedKey>| k.map(|k| k.name.clone());
+        assert_eq!(
+            name(keys.verify_at("s3cret", before)),
+            Some("old".to_string())
+        );
+        assert_eq!(
+            name(keys.verify_at("from-file", before)),
+            Some("new".to_string())
+        );
+        assert_eq!(name(keys.verify_at("s3cret", after)), None);
+        assert_eq!(
+            name(keys.verify_at("from-file", after)),
+            Some("new".to_string())
+        );
+        assert_eq!(name(keys.verify_at("nope", before)), None);
+        assert!(!format!("{keys:?}").contains("s3cret"));
+
+        assert!(Keys::load(&[KeyConfig::default()]).is_err());
+        assert!(Keys::load(&[KeyConfig::new("a", "", &[Scope::Read])]).is_err());
+        assert!(Keys::load(&[KeyConfig::new("a", "k", &[])]).is_err());
+        assert!(Keys::default().verify("").is_none());
+
+        let more = Keys::load(&[KeyConfig::new("team", "t", &[Scope::Read])]).unwrap();
+        let both = keys.and(more.clone()).unwrap();
+        assert_eq!(both.verify("t"), Some("team"));
+        assert_eq!(both.verify("from-file"), Some("new"));
+        assert!(both.and(more).is_err());
+    }
+
+    #[test]
+    fn test_access() {
+        let keys = Keys::load(&[
+            KeyConfig::new("root", "r00t", &[]).admin(),
+            KeyConfig::new("analyst", "look", &[Scope::Read]),
+            KeyConfig::new("producer", "send", &[Scope::Write]),
+        ])
+        .unwrap();
+        let access = Access::new(keys, &[Scope::Read]);
+
+        assert_eq!(access.check(&[None], Scope::Read), Ok(()));
+        assert_eq!(access.check(&[Some("")], Scope::Read), Ok(()));
+        assert_eq!(
+            access.check(&[None], Scope::Write),
+            Err(Denied::Unauthenticated)
+        );
+        assert_eq!(access.check(&[Some("send")], Scope::Write), Ok(()));
+        assert_eq!(access.check(&[Some("send")], Scope::Read), Ok(()));
+        assert_eq!(
+            access.check(&[Some("send")], Scope::Peer),
+            Err(Denied::Forbidden(Scope::Peer))
+        );
+        assert_eq!(
+            access.check(&[Some("look")], Scope::Admin),
+            Err(Denied::Forbidden(Scope::Admin))
+        );
+        assert_eq!(
+            access.check(&[Some("nope")], Scope::Read),
+            Err(Denied::Unauthenticated)
+        );
+        assert_eq!(access.check(&[Some("r00t")], Scope::Peer), Ok(()));
+        assert_eq!(
+            access.check(&[Some("look"), Some("send")], Scope::Write),
+            Ok(())
+        );
+
+        let open = Access::new(Keys::default(), &[Scope::Read, Scope::Peer]);
+        assert_eq!(open.check(&[None], Scope::Peer), Ok(()));
+        assert_eq!(
+            open.check_in_cluster(&[None], Scope::Peer),
+            Err(Denied::Unauthenticated)
+        );
+        assert_eq!(open.check_in_cluster(&[None], Scope::Read), Ok(()));
+    }
+}
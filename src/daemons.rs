@@ -0,0 +1,222 @@
+// The daemons that keep a chain moving: the pool sweeper seals pending records into blocks and
+// stores them, the upstream sweeper syncs blocks from upstreams, and the downstream notifier
+// pushes pending records to downstreams. Each chain a node hosts has its own.
+use crate::namespace::Namespace;
+use crate::record::Record;
+use crate::woody::Logger;
+use crate::{notes, woody, Attributes};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How often the sweeper checks the pool against the block policy.
+const POOL_SWEEP_TICK: Duration = Duration::from_secs(1);
+// How often peers are synced with.
+const PEER_SWEEP: Duration = Duration::from_secs(15);
+
+// start runs the daemons of ns, for as long as the node runs.
+pub fn start(ns: Arc<Namespace>) {
+    let pool = ns.clone();
+    thread::spawn(move || pool_sweeping_daemon(&pool));
+    let upstreams = ns.clone();
+    thread::spawn(move || upstream_sweeping_daemon(&upstreams, PEER_SWEEP));
+    thread::spawn(move || downstream_notifying_daemon(&ns, PEER_SWEEP));
+}
+
+fn sleep_with_jitter(d: Duration, width: u32) {
+    let jitter = rand::random::<u64>() % (width * 1000) as u64;
+    // d +/- jitter/2.
+    let d = d - Duration::from_millis(jitter) / 2 + Duration::from_millis(jitter);
+    thread::sleep(d);
+}
+
+/// sleep with 10% jitter to avoid thundering herd.
+fn sleep_with_10p_jitter(d: Duration) {
+    sleep_with_jitter(d, d.as_secs() as u32 / 10);
+}
+
+// or do we have a fifo queue that flushes to network?
+fn downstream_notifying_daemon(ns: &Namespace, sleep_duration: Duration) {
+    let logger = woody::new(woody::Level::Info);
+    loop {
+        downstream_notify(ns, logger);
+        logger.lock().unwrap().info(notes!(
+            "ts",
+            chrono::Utc::now().to_rfc3339(),
+            "msg",
+            "sleeping downstream notifier".to_string(),
+            "chain",
+            ns.name().to_string()
+        ));
+        sleep_with_10p_jitter(sleep_duration);
+    }
+}
+
+fn downstream_notify(ns: &Namespace, logger: &Mutex<Logger>) {
+    let mp = ns.mem_pool.read().unwrap();
+    let mut hosts = ns.downstreams.write().unwrap();
+    if hosts.sweeping {
+        for e in hosts.push_sealed() {
+            logger.lock().unwrap().error(notes!(
+                "ts",
+                chrono::Utc::now().to_rfc3339(),
+                "msg",
+                format!("failed to push block to downstream: {e}").to_string()
+            ));
+        }
+        let records: Vec<&Record> = mp.contents().collect();
+        for mut p in hosts.downstreams() {
+            for r in records.iter() {
+                match p.notify_host(r, hosts.auth()) {
+                    Ok(_) => {
+                        logger.lock().unwrap().info(notes!(
+                            "ts",
+                            chrono::Utc::now().to_rfc3339(),
+                            "msg",
+                            format!("notified downstream of record: {}", p.url()).to_string()
+                        ));
+                    }
+                    Err(e) => {
+                        logger.lock().unwrap().error(notes!(
+                            "ts",
+                            chrono::Utc::now().to_rfc3339(),
+                            "msg",
+                            format!("failed to notify downstream of record: {e}").to_string()
+                        ));
+                    }
+                }
+            }
+        }
+    } else {
+        logger.lock().unwrap().info(notes!(
+            "ts",
+            chrono::Utc::now().to_rfc3339(),
+            "msg",
+            "downstream notification disabled".to_string(),
+            "chain",
+            ns.name().to_string()
+        ));
+    }
+}
+
+fn pool_sweeping_daemon(ns: &Namespace) {
+    let logger = woody::new(woody::Level::Info);
+    let mut last_seal = Instant::now();
+    loop {
+        if pool_sweep(ns, last_seal.elapsed(), logger) {
+            last_seal = Instant::now();
+        }
+        // blocks also arrive from upstreams and atomic batches, so every tick stores what is new.
+        if let Err(e) = ns.flush() {
+            logger.lock().unwrap().error(notes!(
+                "ts",
+                chrono::Utc::now().to_rfc3339(),
+                "msg",
+                format!("failed to store chain: {e}").to_string(),
+                "chain",
+                ns.name().to_string()
+            ));
+        }
+        thread::sleep(POOL_SWEEP_TICK);
+    }
+}
+
+// pool_sweep seals whatever blocks the policy says are due, returning whether any were.
+fn pool_sweep(ns: &Namespace, since_last_seal: Duration, logger: &Mutex<Logger>) -> bool {
+    let mut mp = ns.mem_pool.write().unwrap();
+    for r in mp.expire() {
+        logger.lock().unwrap().warn(notes!(
+            "ts",
+            chrono::Utc::now().to_rfc3339(),
+            "msg",
+            "record dropped unsealed: expired".to_string(),
+            "record",
+            r.hash
+        ));
+    }
+    if mp.length() == 0 {
+        return false;
+    }
+    let mut p = ns.blockchain.write().unwrap();
+    match ns
+        .config()
+        .block_policy
+        .sweep(&mut p, &mut mp, since_last_seal)
+    {
+        Ok(0) => false,
+        Ok(blocks) => {
+            logger.lock().unwrap().info(notes!(
+                "ts",
+                chrono::Utc::now().to_rfc3339(),
+                "msg",
+                "appended records to chain".to_string(),
+                "blocks",
+                blocks.to_string(),
+                "chain",
+                ns.name().to_string()
+            ));
+            true
+        }
+        Err(e) => {
+            logger.lock().unwrap().error(notes!(
+                "ts",
+                chrono::Utc::now().to_rfc3339(),
+                "msg",
+                format!("failed to append records to chain: {e}").to_string()
+            ));
+            false
+        }
+    }
+}
+
+/// upstream_sweeping_daemon is a daemon that periodically sweeps all peers in the Peers
+/// struct. It will attempt to update the blockchain
+fn upstream_sweeping_daemon(ns: &Namespace, sleep_duration: Duration) {
+    let logger = woody::new(woody::Level::Info);
+    loop {
+        upstream_sweep(ns, logger);
+        logger.lock().unwrap().info(notes!(
+            "ts",
+            chrono::Utc::now().to_rfc3339(),
+            "msg",
+            "sleeping upstream sweeper".to_string(),
+            "chain",
+            ns.name().to_string()
+        ));
+        sleep_with_10p_jitter(sleep_duration);
+    }
+}
+
+fn upstream_sweep(ns: &Namespace, logger: &Mutex<Logger>) {
+    let mut chain = ns.blockchain.write().unwrap();
+    let mut hosts = ns.upstreams.write().unwrap();
+    if hosts.sweeping {
+        match hosts.sweep_all_upstreams(&mut chain) {
+            Ok(_) => {
+                logger.lock().unwrap().info(notes!(
+                    "ts",
+                    chrono::Utc::now().to_rfc3339(),
+                    "msg",
+                    "swept all upstreams".to_string()
+                ));
+            }
+            Err(e) => {
+                logger.lock().unwrap().error(notes!(
+                    "ts",
+                    chrono::Utc::now().to_rfc3339(),
+                    "msg",
+                    format!("failed to sweep upstreams: {e}").to_string()
+                ));
+            }
+        }
+    } else {
+        logger.lock().unwrap().info(notes!(
+            "ts",
+            chrono::Utc::now().to_rfc3339(),
+            "msg",
+            "upstream sweeping disabled".to_string(),
+            "chain",
+            ns.name().to_string()
+        ));
+    }
+}
@@ -0,0 +1,370 @@
+// Field-level encryption. Some entries hold fields only certain readers may see. Those fields
+// are encrypted before they are written, so the chain holds, and hashes, only their ciphertext:
+// anyone can still verify the chain, and only holders of the key can read them. This is
+// envelope encryption: each value is encrypted under a key of its own, which is in turn
+// encrypted, or wrapped, under the chain's key. An encrypted value names the key it was wrapped
+// under, so keys can be rotated by encrypting with the new one while still decrypting with the
+// old. Values are bound to the chain and field they were encrypted for, and cannot be moved to
+// another.
+use crate::hexdisplay::{hex, unhex};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hmac::{Hmac, Mac};
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+use sha3::Sha3_256;
+use std::fmt;
+use std::path::Path;
+
+// ENCRYPTION_KEY_HEADER carries a reader's keys with a query, so that the node can evaluate it
+// over encrypted fields. The node uses them for that query only, and never keeps them.
+pub const ENCRYPTION_KEY_HEADER: &str = "X-TIAF-ENCRYPTION-KEY";
+
+const ENCRYPTED_PREFIX: &str = "tiaf-enc:v1:";
+// ENTRY is the field a whole entry is encrypted as.
+const ENTRY: &str = "";
+const NONCE_LEN: usize = 12;
+
+/// EncryptionKey is a chain's key, and the id encrypted values name it by.
+#[derive(Clone)]
+pub struct EncryptionKey {
+    id: String,
+    key: [u8; 32],
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EncryptionKey")
+            .field("id", &self.id)
+            .field("key", &"<redacted>")
+            .finish()
+    }
+}
+
+impl EncryptionKey {
+    // new is the key called id. Ids are letters, digits, '-' and '_'.
+    pub fn new(id: &str, key: [u8; 32]) -> Result<EncryptionKey, String> {
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if id.is_empty() || !id.chars().all(valid) {
+            return Err(format!("{id:?} cannot name an encryption key"));
+        }
+        Ok(EncryptionKey {
+            id: id.to_string(),
+            key,
+        })
+    }
+
+    pub fn generate(id: &str) -> Result<EncryptionKey, String> {
+        EncryptionKey::new(id, rand::random())
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    // parse reads a key as export writes it: its id, a colon, and the key in hex.
+    pub fn parse(s: &str) -> Result<EncryptionKey, String> {
+        let (id, key) = s
+            .trim()
+            .split_once(':')
+            .ok_or_else(|| "an encryption key is its id, a colon, and the key".to_string())?;
+        let key = unhex(key)
+            .and_then(|k| <[u8; 32]>::try_from(k).ok())
+            .ok_or_else(|| format!("encryption key {id} is not 32 bytes of hex"))?;
+        EncryptionKey::new(id, key)
+    }
+
+    pub fn export(&self) -> String {
+        format!("{}:{}", self.id, hex(&self.key))
+    }
+
+    // load reads a key from a file holding it, as export writes it.
+    pub fn load(path: &Path) -> Result<EncryptionKey, String> {
+        let key = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+        EncryptionKey::parse(&key)
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(&self.key))
+    }
+}
+
+/// Encrypted says what a writer encrypts of an entry: all of it, or the named fields of a JSON object.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Encrypted {
+    Entry,
+    Fields(Vec<String>),
+}
+
+/// Encryption is what a client encrypts of the entries it writes, and the keys it encrypts and
+/// decrypts them with. The first key encrypts.
+#[derive(Debug, Clone)]
+pub struct Encryption {
+    pub keys: Vec<EncryptionKey>,
+    pub encrypted: Encrypted,
+}
+
+// is_encrypted says whether value is an encrypted value, rather than plaintext.
+pub fn is_encrypted(value: &str) -> bool {
+    value.starts_with(ENCRYPTED_PREFIX)
+}
+
+/// Keyring is the keys a reader or writer holds for one chain. The first key encrypts; any of
+/// them decrypts.
+#[derive(Debug, Clone)]
+pub struct Keyring {
+    chain: String,
+    keys: Vec<EncryptionKey>,
+}
+
+impl Keyring {
+    pub fn new(chain: &str, keys: Vec<EncryptionKey>) -> Result<Keyring, String> {
+        if keys.is_empty() {
+            return Err("a keyring needs a key".to_string());
+        }
+        Ok(Keyring {
+            chain: chain.to_string(),
+            keys,
+        })
+    }
+
+    // parse reads the keys of an ENCRYPTION_KEY_HEADER: exported keys, separated by commas.
+    pub fn parse(chain: &str, header: &str) -> Result<Keyring, String> {
+        let keys = header
+            .split(',')
+            .map(EncryptionKey::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        Keyring::new(chain, keys)
+    }
+
+    pub fn header(&self) -> String {
+        let keys: Vec<String> = self.keys.iter().map(|k| k.export()).collect();
+        keys.join(",")
+    }
+
+    // encrypt_entry encrypts what encrypted says of entry. With a seed, such as the idempotency
+    // key of the write, encrypting is repeatable, so that a retried write is the same write;
+    // without one, every encryption differs.
+    pub fn encrypt_entry(
+        &self,
+        entry: &str,
+        encrypted: &Encrypted,
+        seed: Option<&str>,
+    ) -> Result<String, String> {
+        let fields = match encrypted {
+            Encrypted::Entry => return Ok(self.encrypt(ENTRY, entry, seed)),
+            Encrypted::Fields(fields) => fields,
+        };
+        let mut object: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(entry).map_err(|e| format!("entry is not an object: {e}"))?;
+        for field in fields {
+            match object.get_mut(field) {
+                Some(serde_json::Value::String(value)) => {
+                    *value = self.encrypt(field, value, seed);
+                }
+                Some(_) => return Err(format!("field {field} is not a string")),
+                None => {}
+            }
+        }
+        serde_json::to_string(&object).map_err(|e| e.to_string())
+    }
+
+    // decrypt_entry decrypts entry, if it was encrypted whole, or else whatever of its fields
+    // were. Values encrypted under keys we do not hold, or that fail to decrypt, are left as
+    // they are.
+    pub fn decrypt_entry(&self, entry: &str) -> String {
+        if is_encrypted(entry) {
+            return self
+                .decrypt(ENTRY, entry)
+                .unwrap_or_else(|_| entry.to_string());
+        }
+        let Ok(mut object) = serde_json::from_str::<serde_json::Map<_, _>>(entry) else {
+            return entry.to_string();
+        };
+        let mut decrypted = false;
+        for (field, value) in object.iter_mut() {
+            if let serde_json::Value::String(s) = value {
+                if let Ok(plain) = self.decrypt(field, s) {
+                    *s = plain;
+                    decrypted = true;
+                }
+            }
+        }
+        match decrypted {
+            true => serde_json::to_string(&object).unwrap_or_else(|_| entry.to_string()),
+            false => entry.to_string(),
+        }
+    }
+
+    // decrypt_fields decrypts the encrypted values of fields in place, as decrypt_entry does.
+    pub fn decrypt_fields<'f>(
+        &self,
+        fields: impl IntoIterator<Item = (&'f String, &'f mut String)>,
+    ) {
+        for (field, value) in fields {
+            if let Ok(plain) = self.decrypt(field, value) {
+                *value = plain;
+            }
+        }
+    }
+
+    fn encrypt(&self, field: &str, plaintext: &str, seed: Option<&str>) -> String {
+        let key = &self.keys[0];
+        let mut rng = match seed {
+            Some(seed) => {
+                let mut mac = <Hmac<Sha3_256> as Mac>::new_from_slice(&key.key).unwrap();
+                mac.update(format!("{}\n{field}\n{seed}\n{plaintext}", self.chain).as_bytes());
+                StdRng::from_seed(mac.finalize().into_bytes().into())
+            }
+            None => StdRng::from_entropy(),
+        };
+        let mut random = |n: usize| {
+            let mut bytes = vec![0; n];
+            rng.fill_bytes(&mut bytes);
+            bytes
+        };
+        let (value_key, value_nonce, key_nonce) =
+            (random(32), random(NONCE_LEN), random(NONCE_LEN));
+        let aad = format!("{}\n{field}", self.chain);
+        // encrypting in memory with a fresh key and nonce of the right lengths cannot fail.
+        let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&value_key))
+            .encrypt(
+                Nonce::from_slice(&value_nonce),
+                Payload {
+                    msg: plaintext.as_bytes(),
+                    aad: aad.as_bytes(),
+                },
+            )
+            .unwrap();
+        let wrapped = key
+            .cipher()
+            .encrypt(
+                Nonce::from_slice(&key_nonce),
+                Payload {
+                    msg: &value_key,
+                    aad: key.id.as_bytes(),
+                },
+            )
+            .unwrap();
+        format!(
+            "{ENCRYPTED_PREFIX}{}:{}{}:{}{}",
+            key.id,
+            hex(&key_nonce),
+            hex(&wrapped),
+            hex(&value_nonce),
+            hex(&ciphertext)
+        )
+    }
+
+    // decrypt decrypts a value encrypted for field.
+    pub fn decrypt(&self, field: &str, value: &str) -> Result<String, String> {
+        let encrypted = value
+            .strip_prefix(ENCRYPTED_PREFIX)
+            .ok_or_else(|| "value is not encrypted".to_string())?;
+        let mut parts = encrypted.split(':');
+        let (Some(id), Some(wrapped), Some(ciphertext), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err("encrypted value is malformed".to_string());
+        };
+        let key = self
+            .keys
+            .iter()
+            .find(|k| k.id == id)
+            .ok_or_else(|| format!("no key {id} to decrypt the value with"))?;
+        let split = |s: &str| {
+            unhex(s)
+                .filter(|b| b.len() > NONCE_LEN)
+                .map(|mut b| (b.drain(..NONCE_LEN).collect::<Vec<u8>>(), b))
+                .ok_or_else(|| "encrypted value is malformed".to_string())
+        };
+        let (key_nonce, wrapped) = split(wrapped)?;
+        let (value_nonce, ciphertext) = split(ciphertext)?;
+        let value_key = key
+            .cipher()
+            .decrypt(
+                Nonce::from_slice(&key_nonce),
+                Payload {
+                    msg: &wrapped,
+                    aad: key.id.as_bytes(),
+                },
+            )
+            .map_err(|_| format!("value does not decrypt with key {id}"))?;
+        if value_key.len() != 32 {
+            return Err("encrypted value is malformed".to_string());
+        }
+        let aad = format!("{}\n{field}", self.chain);
+        let plaintext = ChaCha20Poly1305::new(Key::from_slice(&value_key))
+            .decrypt(
+                Nonce::from_slice(&value_nonce),
+                Payload {
+                    msg: &ciphertext,
+                    aad: aad.as_bytes(),
+                },
+            )
+            .map_err(|_| format!("value was not encrypted for {} {field}", self.chain))?;
+        String::from_utf8(plaintext).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_field_encryption() {
+        let old = EncryptionKey::generate("k1").unwrap();
+        let new = EncryptionKey::generate("k2").unwrap();
+        let writer = Keyring::new("default", vec![new.clone()]).unwrap();
+        let reader = Keyring::new("default", vec![new.clone(), old.clone()]).unwrap();
+        let entry = "{\"name\": \"ada\", \"ssn\": \"078-05-1120\"}";
+        let fields = Encrypted::Fields(vec!["ssn".to_string(), "missing".to_string()]);
+
+        let encrypted = writer.encrypt_entry(entry, &fields, None).unwrap();
+        assert!(!encrypted.contains("078-05-1120"));
+        assert!(encrypted.contains("ada"));
+        let decrypted: serde_json::Value =
+            serde_json::from_str(&reader.decrypt_entry(&encrypted)).unwrap();
+        assert_eq!(decrypted["ssn"], "078-05-1120");
+        assert_ne!(
+            writer.encrypt_entry(entry, &fields, None).unwrap(),
+            encrypted
+        );
+        assert_eq!(
+            writer
+                .encrypt_entry(entry, &fields, Some("retry-1"))
+                .unwrap(),
+            writer
+                .encrypt_entry(entry, &fields, Some("retry-1"))
+                .unwrap()
+        );
+
+        let whole = writer
+            .encrypt_entry(entry, &Encrypted::Entry, None)
+            .unwrap();
+        assert!(is_encrypted(&whole));
+        assert_eq!(reader.decrypt_entry(&whole), entry);
+
+        // without the key, or on another chain, encrypted values stay encrypted.
+        let outsider = Keyring::new("default", vec![old.clone()]).unwrap();
+        assert_eq!(outsider.decrypt_entry(&encrypted), encrypted);
+        let elsewhere = Keyring::new("payments", vec![new.clone()]).unwrap();
+        assert_eq!(elsewhere.decrypt_entry(&whole), whole);
+        assert!(elsewhere.decrypt(ENTRY, &whole).is_err());
+
+        let header = reader.header();
+        assert!(!format!("{reader:?}").contains(&hex(&new.key)));
+        let parsed = Keyring::parse("default", &header).unwrap();
+        assert_eq!(parsed.decrypt_entry(&whole), entry);
+        assert!(Keyring::parse("default", "k1").is_err());
+        assert!(EncryptionKey::new("a:b", [0; 32]).is_err());
+        assert!(writer
+            .encrypt_entry(
+                "{\"ssn\": 7}",
+                &Encrypted::Fields(vec!["ssn".to_string()]),
+                None
+            )
+            .is_err());
+    }
+}
@@ -0,0 +1,178 @@
+// Events are typed records: Rust values stored on the chain inside a tagged envelope,
+//
+//   {"type": "order-placed", "version": 2, "data": {...}}
+//
+// so that a service can append values of its own types and read them back, in order, as a
+// stream of that type. The version lets a type change shape over time: entries written under an
+// older version are upcast to the current one as they are read.
+use crate::block::Block;
+use crate::types::{Hashtype, Time};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// Event is a type that can be stored on the chain.
+pub trait Event: Serialize + DeserializeOwned + 'static {
+    /// TYPE is the tag events of this type are stored under. It must not change.
+    const TYPE: &'static str;
+    /// VERSION is the shape the type serializes as. Raise it when the shape changes, and teach
+    /// upcast to bring the older shapes forward.
+    const VERSION: u32 = 1;
+
+    /// upcast rewrites the data of an event stored under an older version into the shape of
+    /// VERSION. By default no older version can be read.
+    fn upcast(version: u32, data: serde_json::Value) -> Result<serde_json::Value, String> {
+        let _ = data;
+        Err(format!(
+            "{} has no upcast from version {version} to {}",
+            Self::TYPE,
+            Self::VERSION
+        ))
+    }
+}
+
+/// Envelope is an event as it is laid out in a record entry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Envelope {
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub version: u32,
+    pub data: serde_json::Value,
+}
+
+/// Stored is an event read back from the chain, with where it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stored<T> {
+    pub event: T,
+    // version is the version the event was written under, before any upcast.
+    pub version: u32,
+    pub record: Hashtype,
+    pub block: Hashtype,
+    pub timestamp: Time,
+}
+
+// encode wraps event in its envelope, ready to be the entry of a record.
+pub fn encode<T: Event>(event: &T) -> Result<String, String> {
+    let envelope = Envelope {
+        event_type: T::TYPE.to_string(),
+        version: T::VERSION,
+        data: serde_json::to_value(event).map_err(|e| e.to_string())?,
+    };
+    serde_json::to_string(&envelope).map_err(|e| e.to_string())
+}
+
+// decode reads an entry as an event of type T. Entries that are not events of type T are
+// Ok(None); entries tagged as T that cannot be read as T are errors, never skipped.
+pub fn decode<T: Event>(entry: &str) -> Result<Option<(T, u32)>, String> {
+    let envelope: Envelope = match serde_json::from_str(entry) {
+        Ok(envelope) => envelope,
+        Err(_) => return Ok(None),
+    };
+    if envelope.event_type != T::TYPE {
+        return Ok(None);
+    }
+    let data = match envelope.version {
+        v if v == T::VERSION => envelope.data,
+        v if v < T::VERSION => T::upcast(v, envelope.data)?,
+        v => {
+            return Err(format!(
+                "{} version {v} is newer than the supported version {}",
+                T::TYPE,
+                T::VERSION
+            ))
+        }
+    };
+    let event = serde_json::from_value(data)
+        .map_err(|e| format!("failed to read {} event: {e}", T::TYPE))?;
+    Ok(Some((event, envelope.version)))
+}
+
+// events_in streams the events of type T in blocks, in order.
+pub fn events_in<'a, T: Event>(
+    blocks: impl Iterator<Item = &'a Block> + 'a,
+) -> impl Iterator<Item = Result<Stored<T>, String>> + 'a {
+    blocks.flat_map(|block| {
+        block.data.iter().filter_map(move |record| {
+            let decoded = decode::<T>(&record.entry)
+                .map_err(|e| format!("record {}: {e}", record.hash))
+                .transpose()?;
+            Some(decoded.map(|(event, version)| Stored {
+                event,
+                version,
+                record: record.hash.clone(),
+                block: block.hash.clone(),
+                timestamp: record.timestamp,
+            }))
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::Blockchain;
+    use crate::record::Record;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Deposited {
+        account: String,
+        cents: u64,
+    }
+
+    impl Event for Deposited {
+        const TYPE: &'static str = "deposited";
+        const VERSION: u32 = 2;
+
+        // Version 1 recorded whole dollars.
+        fn upcast(version: u32, mut data: serde_json::Value) -> Result<serde_json::Value, String> {
+            match version {
+                1 => {
+                    let dollars = data["dollars"].as_u64().ok_or("no dollars")?;
+                    data["cents"] = (dollars * 100).into();
+                    Ok(data)
+                }
+                _ => Err(format!("unknown version {version}")),
+            }
+        }
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Closed {
+        account: String,
+    }
+
+    impl Event for Closed {
+        const TYPE: &'static str = "closed";
+    }
+
+    #[test]
+    fn test_events() {
+        let deposit = Deposited {
+            account: "a".to_string(),
+            cents: 250,
+        };
+        let mut chain = Blockchain::new();
+        chain
+            .append_records(vec![
+                Record::new(encode(&deposit).unwrap()),
+                Record::new(r#"{"type": "deposited", "version": 1, "data": {"account": "b", "dollars": 3}}"#.to_string()),
+                Record::new(encode(&Closed { account: "a".to_string() }).unwrap()),
+                Record::new("not an event".to_string()),
+            ])
+            .unwrap();
+
+        let deposits: Vec<Stored<Deposited>> = chain.events().map(Result::unwrap).collect();
+        assert_eq!(deposits.len(), 2);
+        assert_eq!(deposits[0].event, deposit);
+        assert_eq!(deposits[0].version, 2);
+        assert_eq!(deposits[1].event.cents, 300);
+        assert_eq!(deposits[1].version, 1);
+        assert_eq!(deposits[1].block, chain.get(1).unwrap().hash);
+
+        let closed: Vec<Stored<Closed>> = chain.events().map(Result::unwrap).collect();
+        assert_eq!(closed.len(), 1);
+
+        // An event of the right type that cannot be read is reported, not dropped.
+        assert!(decode::<Closed>(r#"{"type": "closed", "version": 1, "data": {}}"#).is_err());
+        assert!(decode::<Closed>(r#"{"type": "closed", "version": 9, "data": {}}"#).is_err());
+    }
+}
@@ -1,17 +1,15 @@
 use crate::fifo::FifoError::{Empty, Full, Illogical};
 
 #[derive(Debug, PartialEq)]
-#[allow(dead_code)]
 pub enum FifoError {
     Full,
     Empty,
     Illogical,
 }
 
-// Fifo will be used as the key mempool structure, the prelim buffer that will be swept
+// Fifo is the key mempool structure, the prelim buffer that is swept
 // into the Blockchain periodically.
 #[derive(Debug)]
-#[allow(dead_code)]
 pub struct Fifo<T>
 where
     T: Clone,
@@ -23,7 +21,6 @@ where
     max_size: usize,
 }
 
-#[allow(dead_code)]
 impl<T> Fifo<T>
 where
     T: Clone,
@@ -70,7 +67,7 @@ where
         if self.length == 0 {
             return Err(Empty);
         }
-        let r = self.records[self.reader].clone();
+        let r = self.records[self.reader].take();
         self.reader = (self.reader + 1) % self.max_size;
         self.length -= 1;
         r.ok_or(Illogical)
@@ -79,6 +76,16 @@ where
     pub fn length(&self) -> usize {
         self.length
     }
+
+    pub fn capacity(&self) -> usize {
+        self.max_size
+    }
+
+    // iter walks the queue from the oldest item to the newest, leaving it untouched.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        (0..self.length)
+            .filter_map(move |i| self.records[(self.reader + i) % self.max_size].as_ref())
+    }
 }
 
 #[cfg(test)]
@@ -95,6 +102,8 @@ mod tests {
         let r2 = fifo.pop().unwrap();
         assert_eq!(r, r2);
         assert_eq!(fifo.length(), 0);
+        // a popped record is no longer held.
+        assert!(fifo.records.iter().all(Option::is_none));
     }
 
     #[test]
@@ -127,6 +136,10 @@ mod tests {
         }
         assert_eq!(fifo.length(), 10);
         assert_eq!(fifo.pop(), Ok(recs[0].clone()));
+        // after wrapping around, iter still runs oldest first.
+        fifo.put(Record::new("10".to_string())).unwrap();
+        let entries: Vec<&str> = fifo.iter().map(|r| r.entry.as_str()).collect();
+        assert_eq!(entries, (1..=10).map(|i| i.to_string()).collect::<Vec<_>>());
     }
 
     #[test]
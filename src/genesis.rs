@@ -0,0 +1,116 @@
+// Chain identity. Two nodes are on the same chain exactly when they began it with the same
+// genesis block. Left to itself, every tiaf chain begins with the same block, so nodes of
+// unrelated clusters would sync with each other. A genesis file names the chain, the
+// authorities that govern it, the records it begins with and when it was created: nodes given
+// the same file begin the same chain, and refuse peers that began another.
+use crate::block::Block;
+use crate::events::{self, Event};
+use crate::record::Record;
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use uuid::Uuid;
+
+// GENESIS_HEADER carries the hash of the genesis block of the chain a peer request is for, and
+// of the chain that answered it.
+pub const GENESIS_HEADER: &str = "X-TIAF-GENESIS";
+
+/// Genesis is a genesis file, or the `[genesis]` section of a chain's config. created is an
+/// RFC 3339 time; authorities are whatever the chain's governance names, such as the public keys
+/// allowed to sign for it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Genesis {
+    pub chain_id: String,
+    pub created: String,
+    #[serde(default)]
+    pub authorities: Vec<String>,
+    // records are the entries of the records the chain begins with.
+    #[serde(default)]
+    pub records: Vec<String>,
+}
+
+/// ChainIdentity is the first record of a chain begun from a genesis file, so that its id and
+/// authorities can be read from the chain itself.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChainIdentity {
+    pub chain_id: String,
+    pub authorities: Vec<String>,
+}
+
+impl Event for ChainIdentity {
+    const TYPE: &'static str = "tiaf.chain-identity";
+}
+
+impl Genesis {
+    // load reads a genesis file, in TOML.
+    pub fn load(path: &Path) -> Result<Genesis, String> {
+        let toml = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read genesis {}: {e}", path.display()))?;
+        toml::from_str(&toml)
+            .map_err(|e| format!("failed to parse genesis {}: {e}", path.display()))
+    }
+
+    // block is the genesis block this describes: the chain's identity, then its initial records,
+    // all made at created.
+    pub fn block(&self) -> Result<Block, String> {
+        if self.chain_id.is_empty() {
+            return Err("genesis has no chain_id".to_string());
+        }
+        let created = DateTime::parse_from_rfc3339(&self.created)
+            .map_err(|e| format!("genesis created: {e}"))?;
+        let secs = u64::try_from(created.timestamp())
+            .map_err(|_| "genesis created before 1970".to_string())?;
+        let identity = events::encode(&ChainIdentity {
+            chain_id: self.chain_id.clone(),
+            authorities: self.authorities.clone(),
+        })?;
+        // records are numbered rather than given random uuids, so that every node makes them alike.
+        let records = std::iter::once(identity)
+            .chain(self.records.iter().cloned())
+            .enumerate()
+            .map(|(i, entry)| Record::at(entry, Uuid::from_u128(i as u128), secs))
+            .collect();
+        Ok(Block::genesis_from(records, secs * 1000))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::Blockchain;
+
+    #[test]
+    fn test_genesis() {
+        let genesis = Genesis {
+            chain_id: "acme".to_string(),
+            created: "2026-01-01T00:00:00Z".to_string(),
+            authorities: vec!["ops".to_string()],
+            records: vec!["{\"charter\": \"v1\"}".to_string()],
+        };
+        let a = Blockchain::from_genesis(&genesis).unwrap();
+        let b = Blockchain::from_genesis(&genesis).unwrap();
+        assert_eq!(a.genesis_hash(), b.genesis_hash());
+        assert_ne!(a.genesis_hash(), Blockchain::new().genesis_hash());
+        assert_eq!(a.length(), 1);
+        a.full_validate().unwrap();
+
+        let identities: Vec<_> = a.events::<ChainIdentity>().collect();
+        assert_eq!(identities.len(), 1);
+        let identity = &identities[0].as_ref().unwrap().event;
+        assert_eq!(identity.chain_id, "acme");
+        assert_eq!(identity.authorities, vec!["ops"]);
+
+        let other = Genesis {
+            chain_id: "other".to_string(),
+            ..genesis.clone()
+        };
+        let c = Blockchain::from_genesis(&other).unwrap();
+        assert_ne!(a.genesis_hash(), c.genesis_hash());
+
+        let undated = Genesis {
+            created: "yesterday".to_string(),
+            ..genesis
+        };
+        assert!(undated.block().is_err());
+    }
+}
@@ -0,0 +1,174 @@
+use crate::block::Block;
+use crate::pratt::Value;
+use crate::query_chain::value_of;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Bound;
+
+/// Location is where a record sits in the chain: the index of its block, and its position
+/// within that block. Locations order the way the chain does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Location {
+    pub block: u64,
+    pub record: usize,
+}
+
+/// FieldIndex maps every value seen for one entry field to the records holding that value.
+/// Values are read the way the query language reads them, so `"5"` is indexed as the number 5.
+// It is serialized as a list of values and their locations, since JSON keys are strings only.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(
+    from = "Vec<(Value, Vec<Location>)>",
+    into = "Vec<(Value, Vec<Location>)>"
+)]
+pub struct FieldIndex {
+    entries: BTreeMap<Value, Vec<Location>>,
+}
+
+impl From<Vec<(Value, Vec<Location>)>> for FieldIndex {
+    fn from(entries: Vec<(Value, Vec<Location>)>) -> FieldIndex {
+        FieldIndex {
+            entries: entries.into_iter().collect(),
+        }
+    }
+}
+
+impl From<FieldIndex> for Vec<(Value, Vec<Location>)> {
+    fn from(index: FieldIndex) -> Vec<(Value, Vec<Location>)> {
+        index.entries.into_iter().collect()
+    }
+}
+
+impl FieldIndex {
+    fn insert(&mut self, value: Value, at: Location) {
+        self.entries.entry(value).or_default().push(at);
+    }
+
+    pub fn get(&self, value: &Value) -> BTreeSet<Location> {
+        self.entries
+            .get(value)
+            .map(|locations| locations.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    // range collects the records whose value lies between lower and upper. Only values of the
+    // same kind as the bounds are considered: numbers never fall in a range of strings.
+    pub fn range(&self, lower: Bound<&Value>, upper: Bound<&Value>) -> BTreeSet<Location> {
+        let kind = match (lower, upper) {
+            (Bound::Included(v) | Bound::Excluded(v), _)
+            | (_, Bound::Included(v) | Bound::Excluded(v)) => v,
+            (Bound::Unbounded, Bound::Unbounded) => {
+                return self.entries.values().flatten().copied().collect()
+            }
+        };
+        self.entries
+            .range::<Value, _>((lower, upper))
+            .filter(|(v, _)| std::mem::discriminant(*v) == std::mem::discriminant(kind))
+            .flat_map(|(_, locations)| locations.iter().copied())
+            .collect()
+    }
+
+    // distinct_values is the number of different values the field has been seen with.
+    pub fn distinct_values(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+/// Indexes holds a chain's secondary indexes, keyed by the entry field each one covers.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Indexes {
+    fields: BTreeMap<String, FieldIndex>,
+}
+
+impl Indexes {
+    pub fn fields(&self) -> Vec<String> {
+        self.fields.keys().cloned().collect()
+    }
+
+    pub fn get(&self, field: &str) -> Option<&FieldIndex> {
+        self.fields.get(field)
+    }
+
+    pub fn contains(&self, field: &str) -> bool {
+        self.fields.contains_key(field)
+    }
+
+    // declare starts indexing field, filling the new index from blocks already in the chain.
+    // Declaring a field that is already indexed does nothing.
+    pub(crate) fn declare<'a>(
+        &mut self,
+        field: &str,
+        blocks: impl Iterator<Item = (u64, &'a Block)>,
+    ) {
+        if self.contains(field) {
+            return;
+        }
+        let mut index = FieldIndex::default();
+        for (at, block) in blocks {
+            index_block(&mut index, field, at, block);
+        }
+        self.fields.insert(field.to_string(), index);
+    }
+
+    pub(crate) fn remove(&mut self, field: &str) {
+        self.fields.remove(field);
+    }
+
+    // add_block indexes the records of a block just appended to the chain at position at.
+    pub(crate) fn add_block(&mut self, at: u64, block: &Block) {
+        for (field, index) in self.fields.iter_mut() {
+            index_block(index, field, at, block);
+        }
+    }
+}
+
+// Records that are not structured, or lack the field, are left out of the index.
+fn index_block(index: &mut FieldIndex, field: &str, at: u64, block: &Block) {
+    for (i, record) in block.data.iter().enumerate() {
+        if let Ok(kv) = record.structured_entry() {
+            if let Some(v) = kv.fields().get(field) {
+                index.insert(
+                    value_of(v),
+                    Location {
+                        block: at,
+                        record: i,
+                    },
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::Record;
+
+    #[test]
+    fn test_field_index() {
+        let records = ["3", "10", "x", "10"]
+            .iter()
+            .map(|v| Record::new(format!("{{\"n\": {v:?}}}")))
+            .collect();
+        let block = Block::new(1, "prev".to_string(), records);
+        let mut indexes = Indexes::default();
+        indexes.declare("n", [(1, &block)].into_iter());
+        let n = indexes.get("n").unwrap();
+
+        let at = |record| Location { block: 1, record };
+        assert_eq!(n.distinct_values(), 3);
+        assert_eq!(n.get(&Value::Num(10)), BTreeSet::from([at(1), at(3)]));
+        assert_eq!(
+            n.range(Bound::Excluded(&Value::Num(3)), Bound::Unbounded),
+            BTreeSet::from([at(1), at(3)])
+        );
+        // "x" sorts before every number, but is no number and so is in no numeric range.
+        assert_eq!(
+            n.range(Bound::Unbounded, Bound::Included(&Value::Num(3))),
+            BTreeSet::from([at(0)])
+        );
+
+        indexes.add_block(2, &block);
+        assert_eq!(indexes.get("n").unwrap().get(&Value::Num(3)).len(), 2);
+    }
+}
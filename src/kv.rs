@@ -0,0 +1,167 @@
+use crate::block::Block;
+use crate::chain::Blockchain;
+use crate::index::Location;
+use crate::types::{Hashtype, Time};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+fn default_tombstone_field() -> String {
+    "deleted".to_string()
+}
+
+/// KvConfig says how records are read as key-value upserts: the entry field holding the key,
+/// and the field that, set to `true`, marks a record as deleting its key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KvConfig {
+    pub key_field: String,
+    #[serde(default = "default_tombstone_field")]
+    pub tombstone_field: String,
+}
+
+impl KvConfig {
+    pub fn new(key_field: &str) -> KvConfig {
+        KvConfig {
+            key_field: key_field.to_string(),
+            tombstone_field: default_tombstone_field(),
+        }
+    }
+}
+
+/// KvEntry is one version of a key: the entry that set it, or the tombstone that deleted it,
+/// along with the record and block it came from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KvEntry {
+    pub key: String,
+    // fields is the whole entry of the record. It is None when the record is a tombstone.
+    pub fields: Option<BTreeMap<String, String>>,
+    pub record: Hashtype,
+    pub block: Hashtype,
+    pub timestamp: Time,
+}
+
+/// KvView is the chain seen as a key-value store: for every key, where each of its versions
+/// sits in the chain, oldest first. Versions are read from the chain when asked for, so the view
+/// holds locations rather than copies of entries.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KvView {
+    config: KvConfig,
+    keys: HashMap<String, Vec<Location>>,
+}
+
+impl KvView {
+    pub fn new(config: KvConfig) -> KvView {
+        KvView {
+            config,
+            keys: HashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &KvConfig {
+        &self.config
+    }
+
+    // len is the number of keys ever written, deleted or not.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    // add_block records the versions written by a block appended to the chain at position at.
+    // Records that are not structured, or have no key, are not upserts and are passed over.
+    pub(crate) fn add_block(&mut self, at: u64, block: &Block) {
+        for (i, record) in block.data.iter().enumerate() {
+            if let Ok(kv) = record.structured_entry() {
+                if let Some(key) = kv.fields().get(&self.config.key_field) {
+                    self.keys.entry(key.clone()).or_default().push(Location {
+                        block: at,
+                        record: i,
+                    });
+                }
+            }
+        }
+    }
+
+    // history is every version of key in chain, oldest first.
+    pub fn history(&self, chain: &Blockchain, key: &str) -> Vec<KvEntry> {
+        let locations = match self.keys.get(key) {
+            Some(locations) => locations,
+            None => return vec![],
+        };
+        locations
+            .iter()
+            .filter_map(|at| self.entry(chain, key, *at))
+            .collect()
+    }
+
+    // get is the current value of key: None when it was never written, or was last deleted.
+    pub fn get(&self, chain: &Blockchain, key: &str) -> Option<KvEntry> {
+        let at = self.keys.get(key)?.last()?;
+        self.entry(chain, key, *at).filter(|e| e.fields.is_some())
+    }
+
+    fn entry(&self, chain: &Blockchain, key: &str, at: Location) -> Option<KvEntry> {
+        let block = chain.get(at.block)?;
+        let record = block.data.get(at.record)?;
+        let fields = record.structured_entry().ok()?.pairs();
+        let deleted = fields
+            .get(&self.config.tombstone_field)
+            .is_some_and(|v| v == "true");
+        Some(KvEntry {
+            key: key.to_string(),
+            fields: if deleted {
+                None
+            } else {
+                Some(fields.into_iter().collect())
+            },
+            record: record.hash.clone(),
+            block: block.hash.clone(),
+            timestamp: record.timestamp,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::Record;
+
+    #[test]
+    fn test_kv_view() {
+        let mut chain = Blockchain::new();
+        chain.set_kv(Some(KvConfig::new("id")));
+        let put = |id: &str, v: &str| Record::new(format!(r#"{{"id": "{id}", "v": "{v}"}}"#));
+        let delete = |id: &str| Record::new(format!(r#"{{"id": "{id}", "deleted": "true"}}"#));
+
+        chain
+            .append_records(vec![
+                put("a", "1"),
+                put("b", "1"),
+                Record::new("x".to_string()),
+            ])
+            .unwrap();
+        chain
+            .append_records(vec![put("a", "2"), delete("b")])
+            .unwrap();
+
+        let view = chain.kv().unwrap();
+        assert_eq!(view.len(), 2);
+        let a = view.get(&chain, "a").unwrap();
+        assert_eq!(a.fields.unwrap()["v"], "2");
+        assert_eq!(a.block, chain.get(2).unwrap().hash);
+        assert_eq!(a.record, chain.get(2).unwrap().data[0].hash);
+
+        assert_eq!(view.get(&chain, "b"), None);
+        let history = view.history(&chain, "b");
+        assert_eq!(history.len(), 2);
+        assert!(history[0].fields.is_some());
+        assert!(history[1].fields.is_none());
+        assert!(view.history(&chain, "c").is_empty());
+
+        // A view set up over an existing chain is built from it.
+        chain.set_kv(Some(KvConfig::new("v")));
+        assert_eq!(chain.kv().unwrap().history(&chain, "1").len(), 2);
+    }
+}
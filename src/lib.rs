@@ -3,10 +3,23 @@ extern crate rouille;
 extern crate core;
 
 pub mod chain;
+pub mod cluster;
+pub mod credentials;
+pub mod daemons;
+pub mod encryption;
+pub mod events;
 mod fifo;
+pub mod genesis;
 pub mod hexdisplay;
+pub mod index;
+pub mod kv;
+pub mod namespace;
 pub mod peers;
 pub mod record;
+pub mod redaction;
+pub mod schema;
+pub mod signing;
+pub mod snapshot;
 pub mod types;
 
 #[macro_use]
@@ -14,7 +27,12 @@ pub mod woody;
 pub use woody::Attributes;
 
 pub mod mempool;
+pub mod policy;
+pub mod quota;
+pub mod receipts;
 pub mod server;
+pub mod subscribe;
+pub mod tls;
 
 pub mod block;
 
@@ -1,10 +1,54 @@
+use crate::fifo::Fifo;
 use crate::record::Record;
+use crate::types::{Hashtype, Time};
+use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+use uuid::Uuid;
+
+/// Eviction is what a full pool does with a new record.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Eviction {
+    // Reject refuses the new record.
+    #[default]
+    Reject,
+    // DropOldest makes room by dropping the record that has been pending longest. That record's
+    // write was already acknowledged, so it is lost unless it reached a peer; evictions are
+    // counted in statistics.
+    DropOldest,
+}
+
+/// Pending is a record waiting in the pool, with when it was admitted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pending {
+    pub record: Record,
+    pub admitted: Time,
+    // client is who submitted the record, when that is known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client: Option<String>,
+}
 
 /// MemPool is a locally-unique group of Records that gets shared across different nodes.
+/// Records are kept in the order they arrived, so blocks are sealed in that order too.
+///
+/// Records can be given a time to live, after which they are dropped unsealed, so that a node
+/// that cannot seal does not hold on to them, and push them to peers, forever.
 pub struct MemPool {
-    data: HashSet<Record>,
-    bound: u32,
+    queue: Fifo<Pending>,
+    // hashes holds the hash of every record in queue, to keep out dupes.
+    hashes: HashSet<Hashtype>,
+    ttl: Option<Duration>,
+    eviction: Eviction,
+    // dropped counts the records that left the pool unsealed.
+    dropped: Dropped,
+}
+
+/// Dropped counts the acknowledged records a pool dropped without sealing them, by why.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Dropped {
+    pub expired: u64,
+    pub evicted: u64,
 }
 
 #[derive(Debug, PartialEq)]
@@ -12,37 +56,175 @@ pub enum MemPoolError {
     Full,
 }
 
+fn now() -> Time {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
 impl MemPool {
     pub fn new(max_size: usize) -> MemPool {
         MemPool {
-            data: HashSet::new(),
-            bound: max_size as u32,
+            queue: Fifo::new(max_size),
+            hashes: HashSet::new(),
+            ttl: None,
+            eviction: Eviction::Reject,
+            dropped: Dropped::default(),
         }
     }
 
+    // with_retention sets how long records may stay pending, and what happens when the pool is
+    // full.
+    pub fn with_retention(mut self, ttl: Option<Duration>, eviction: Eviction) -> MemPool {
+        self.ttl = ttl;
+        self.eviction = eviction;
+        self
+    }
+
     pub fn length(&self) -> usize {
-        self.data.len()
+        self.queue.length()
+    }
+
+    // capacity is the most records the pool holds before refusing more.
+    pub fn capacity(&self) -> usize {
+        self.queue.capacity()
+    }
+
+    // dropped is how many records have expired or been evicted since the pool was made.
+    pub fn dropped(&self) -> Dropped {
+        self.dropped
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.length() >= self.capacity()
     }
 
     pub fn contains(&self, r: &Record) -> bool {
-        self.data.contains(r)
+        self.contains_hash(&r.hash)
+    }
+
+    pub fn contains_hash(&self, hash: &Hashtype) -> bool {
+        self.hashes.contains(hash)
+    }
+
+    // record_with_uuid is the oldest pending record with the given uuid.
+    pub fn record_with_uuid(&self, uuid: &Uuid) -> Option<&Record> {
+        self.contents().find(|r| &r.uuid == uuid)
+    }
+
+    // contents walks the pending records, oldest first.
+    pub fn contents(&self) -> impl Iterator<Item = &Record> {
+        self.queue.iter().map(|p| &p.record)
+    }
+
+    // pending walks the pending records along with when they were admitted, oldest first.
+    pub fn pending(&self) -> impl Iterator<Item = &Pending> {
+        self.queue.iter()
+    }
+
+    // puts unique r into self. dupes are ignored, even when the pool is full, since they are
+    // already pending. When the pool is full and evicts, the evicted record is returned.
+    pub fn put(&mut self, r: Record) -> Result<Option<Record>, MemPoolError> {
+        self.put_at(r, now(), None)
+    }
+
+    // put_from puts r into self as put does, noting which client submitted it.
+    pub fn put_from(&mut self, r: Record, client: &str) -> Result<Option<Record>, MemPoolError> {
+        self.put_at(r, now(), Some(client.to_string()))
+    }
+
+    // pending_from is how many of the pending records client submitted.
+    pub fn pending_from(&self, client: &str) -> usize {
+        self.queue
+            .iter()
+            .filter(|p| p.client.as_deref() == Some(client))
+            .count()
+    }
+
+    fn put_at(
+        &mut self,
+        r: Record,
+        admitted: Time,
+        client: Option<String>,
+    ) -> Result<Option<Record>, MemPoolError> {
+        if self.contains(&r) {
+            return Ok(None);
+        }
+        let evicted = match (self.is_full(), self.eviction) {
+            (false, _) => None,
+            (true, Eviction::Reject) => return Err(MemPoolError::Full),
+            (true, Eviction::DropOldest) => {
+                self.dropped.evicted += 1;
+                self.take(1).pop()
+            }
+        };
+        let hash = r.hash.clone();
+        self.queue
+            .put(Pending {
+                record: r,
+                admitted,
+                client,
+            })
+            .map_err(|_| MemPoolError::Full)?;
+        self.hashes.insert(hash);
+        Ok(evicted)
+    }
+
+    // take removes up to n of the oldest records, in arrival order.
+    pub fn take(&mut self, n: usize) -> Vec<Record> {
+        let mut taken = vec![];
+        while taken.len() < n {
+            match self.queue.pop() {
+                Ok(p) => {
+                    self.hashes.remove(&p.record.hash);
+                    taken.push(p.record);
+                }
+                Err(_) => break,
+            }
+        }
+        taken
+    }
+
+    // expire drops the records that have outlived the time to live, returning them.
+    pub fn expire(&mut self) -> Vec<Record> {
+        self.expire_at(now())
     }
 
-    pub fn contents(&self) -> &HashSet<Record> {
-        &self.data
+    fn expire_at(&mut self, now: Time) -> Vec<Record> {
+        let ttl = match self.ttl {
+            Some(ttl) => ttl.as_secs(),
+            None => return vec![],
+        };
+        // records are admitted in order, so the expired ones are all at the front.
+        let expired = self
+            .queue
+            .iter()
+            .take_while(|p| p.admitted.saturating_add(ttl) <= now)
+            .count();
+        self.dropped.expired += expired as u64;
+        self.take(expired)
     }
 
-    // puts unique r into self. dupes are ignored.
-    pub fn put(&mut self, r: Record) -> Result<(), MemPoolError> {
-        if self.data.len() as u32 >= self.bound {
-            return Err(MemPoolError::Full);
+    // purge drops the pending records with the given hashes, returning them.
+    pub fn purge(&mut self, hashes: &[Hashtype]) -> Vec<Record> {
+        let mut purged = vec![];
+        for p in self.queue.drain() {
+            if hashes.contains(&p.record.hash) {
+                self.hashes.remove(&p.record.hash);
+                purged.push(p.record);
+            } else {
+                // the queue was just drained, so there is room for everything it held.
+                _ = self.queue.put(p);
+            }
         }
-        self.data.insert(r);
-        Ok(())
+        purged
     }
 
-    pub fn reset(&mut self) -> HashSet<Record> {
-        self.data.drain().collect()
+    // reset empties the pool, returning everything that was pending in arrival order.
+    pub fn reset(&mut self) -> Vec<Record> {
+        self.hashes.clear();
+        self.queue.drain().into_iter().map(|p| p.record).collect()
     }
 }
 
@@ -65,20 +247,25 @@ mod tests {
     #[test]
     fn test_mempool_full() {
         let mut mempool = MemPool::new(10);
-        for i in 0..10 {
+        let first = Record::new("first".to_string());
+        mempool.put(first.clone()).unwrap();
+        for i in 1..10 {
             let r = Record::new(i.to_string());
             mempool.put(r).unwrap();
         }
         assert_eq!(mempool.length(), 10);
+        assert!(mempool.is_full());
         let r = Record::new("test".to_string());
         assert_eq!(mempool.put(r), Err(MemPoolError::Full));
+        // a record already pending is still accepted.
+        assert_eq!(mempool.put(first), Ok(None));
     }
 
     #[test]
     fn test_mempool_empty() {
         let mut mempool = MemPool::new(10);
         assert_eq!(mempool.length(), 0);
-        assert_eq!(mempool.reset(), HashSet::<Record>::new());
+        assert_eq!(mempool.reset(), Vec::<Record>::new());
     }
 
     #[test]
@@ -92,8 +279,28 @@ mod tests {
         }
 
         assert_eq!(mempool.length(), 10);
-        let gotten: HashSet<Record> = recs.drain(0..).collect();
-        assert_eq!(mempool.reset(), gotten)
+        assert_eq!(mempool.reset(), recs)
+    }
+
+    #[test]
+    fn test_mempool_take() {
+        let mut mempool = MemPool::new(10);
+        let recs: Vec<Record> = (0..5).map(|i| Record::new(i.to_string())).collect();
+        for r in recs.iter() {
+            mempool.put(r.clone()).unwrap();
+        }
+        assert_eq!(mempool.take(2), recs[0..2]);
+        assert!(!mempool.contains(&recs[0]));
+        assert_eq!(mempool.length(), 3);
+        assert_eq!(
+            mempool.contents().collect::<Vec<_>>(),
+            recs[2..].iter().collect::<Vec<_>>()
+        );
+        // a taken record may come back; one still pending is a dupe.
+        mempool.put(recs[0].clone()).unwrap();
+        mempool.put(recs[2].clone()).unwrap();
+        assert_eq!(mempool.take(10), [&recs[2..], &recs[0..1]].concat());
+        assert!(mempool.take(1).is_empty());
     }
 
     #[test]
@@ -109,4 +316,36 @@ mod tests {
         mempool.reset();
         assert_eq!(mempool.length(), 0);
     }
+
+    #[test]
+    fn test_mempool_retention() {
+        let mut mempool =
+            MemPool::new(3).with_retention(Some(Duration::from_secs(10)), Eviction::DropOldest);
+        let recs: Vec<Record> = (0..4).map(|i| Record::new(i.to_string())).collect();
+        for (i, r) in recs[..3].iter().enumerate() {
+            mempool.put_at(r.clone(), 100 + i as Time, None).unwrap();
+        }
+        // full, so the oldest makes way.
+        assert_eq!(
+            mempool.put_at(recs[3].clone(), 103, Some("a".to_string())),
+            Ok(Some(recs[0].clone()))
+        );
+        assert!(!mempool.contains(&recs[0]));
+
+        assert!(mempool.expire_at(110).is_empty());
+        assert_eq!(mempool.expire_at(112), recs[1..3]);
+        assert_eq!(
+            mempool.dropped(),
+            Dropped {
+                expired: 2,
+                evicted: 1
+            }
+        );
+        assert_eq!(mempool.pending().next().unwrap().admitted, 103);
+        assert_eq!(mempool.pending_from("a"), 1);
+
+        mempool.put_at(recs[0].clone(), 120, None).unwrap();
+        assert_eq!(mempool.purge(&[recs[3].hash.clone()]), recs[3..]);
+        assert_eq!(mempool.contents().collect::<Vec<_>>(), vec![&recs[0]]);
+    }
 }
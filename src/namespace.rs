@@ -0,0 +1,570 @@
+// Named chains. A node hosts the default chain, served under /api/v1, and any number of named
+// chains besides, each served under /api/v1/chains/{name}. Every chain has a genesis, mempool,
+// peers and access policy of its own; the node's admin keys hold on all of them. With a data
+// directory, each chain keeps its config and blocks in a directory of its own, and named chains
+// are hosted again when the node restarts.
+use crate::block::Block;
+use crate::chain::Blockchain;
+use crate::credentials::{Access, KeyConfig, Keys, Scope};
+use crate::genesis::Genesis;
+use crate::kv::KvConfig;
+use crate::mempool::{Eviction, MemPool};
+use crate::peers::{Downstreams, PeerAuth, ReadHost, Upstreams, WriteHost};
+use crate::policy::BlockPolicy;
+use crate::quota::{Limiter, RateLimitConfig};
+use crate::receipts::Receipts;
+use crate::snapshot::{Snapshot, SnapshotKey};
+use crate::types::Hashtype;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+/// DEFAULT is the name of the chain served under /api/v1.
+pub const DEFAULT: &str = "default";
+
+/// NamespaceConfig is how one chain runs. The default chain's is the top level of the server
+/// config; a named chain's is given when it is created.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NamespaceConfig {
+    // Nodes to push records to, and to sync blocks from. Peers are reached at the chain of the
+    // same name.
+    #[serde(default)]
+    pub downstreams: Vec<String>,
+    #[serde(default)]
+    pub upstreams: Vec<String>,
+    // Entry fields to keep secondary indexes on, for faster queries.
+    #[serde(default)]
+    pub indexed_fields: Vec<String>,
+    // Reads records as upserts keyed by an entry field, to serve the current value of a key.
+    #[serde(default)]
+    pub kv: Option<KvConfig>,
+    // Records the mempool holds between sweeps; writes beyond it are refused until the next one.
+    #[serde(default = "default_mempool_size")]
+    pub mempool_size: usize,
+    // How long a record may wait to be sealed before it is dropped. Unset, records wait forever.
+    #[serde(default)]
+    pub mempool_ttl_secs: Option<u64>,
+    // What a full mempool does with a new record: "reject" it, or "drop_oldest" to make room.
+    // drop_oldest loses writes that were already acknowledged, so it suits only data that is
+    // fine to lose under load; evictions show in statistics as pool_dropped.
+    #[serde(default)]
+    pub mempool_eviction: Eviction,
+    // When pending records are sealed into blocks, and how large blocks get.
+    #[serde(default)]
+    pub block_policy: BlockPolicy,
+    // Limits on how fast, and how much, any one client may write. Unset, writes are not limited.
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+    // Keys with narrower scopes: read, write, peer or admin. They hold on this chain only.
+    #[serde(default)]
+    pub api_keys: Vec<KeyConfig>,
+    // What requests presenting no key may do. Leave out write and peer to accept records only
+    // from holders of keys, or make it empty to lock the chain down entirely. On a node with a
+    // cluster secret or a client CA, peer is left to members of the cluster whatever this says.
+    #[serde(default = "default_anonymous_scopes")]
+    pub anonymous_scopes: Vec<Scope>,
+    // What the chain begins with. Nodes refuse peers whose chain began differently, so every
+    // node of a cluster must be given the same genesis. Unset, the chain begins as every tiaf
+    // chain of its name does.
+    #[serde(default)]
+    pub genesis: Option<Genesis>,
+    // Public keys, in hex, of the admins who may redact records. A redacted record keeps its
+    // hash, but its entry is gone from every node that accepts the redaction.
+    #[serde(default)]
+    pub redaction_keys: Vec<String>,
+    // Public keys, in hex, of the nodes whose snapshots of the chain may be taken up in place
+    // of replaying it.
+    #[serde(default)]
+    pub snapshot_keys: Vec<String>,
+}
+
+fn default_mempool_size() -> usize {
+    8
+}
+
+fn default_anonymous_scopes() -> Vec<Scope> {
+    vec![Scope::Read, Scope::Write, Scope::Peer]
+}
+
+impl Default for NamespaceConfig {
+    fn default() -> NamespaceConfig {
+        NamespaceConfig {
+            downstreams: vec![],
+            upstreams: vec![],
+            indexed_fields: vec![],
+            kv: None,
+            mempool_size: default_mempool_size(),
+            mempool_ttl_secs: None,
+            mempool_eviction: Eviction::default(),
+            block_policy: BlockPolicy::default(),
+            rate_limit: None,
+            api_keys: vec![],
+            anonymous_scopes: default_anonymous_scopes(),
+            genesis: None,
+            redaction_keys: vec![],
+            snapshot_keys: vec![],
+        }
+    }
+}
+
+// valid_name is whether name may name a chain: lowercase letters, digits, '-' and '_', starting
+// with a letter or digit, so that it is safe in a path and a url alike.
+fn valid_name(name: &str) -> bool {
+    name.len() <= 64
+        && name.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+/// Namespace is one chain a node hosts, and everything serving it takes.
+pub struct Namespace {
+    name: String,
+    config: NamespaceConfig,
+    genesis: Hashtype,
+    pub blockchain: Arc<RwLock<Blockchain>>,
+    pub mem_pool: Arc<RwLock<MemPool>>,
+    pub downstreams: Arc<RwLock<Downstreams>>,
+    pub upstreams: Arc<RwLock<Upstreams>>,
+    pub access: Access,
+    pub limiter: Mutex<Limiter>,
+    pub receipts: Mutex<Receipts>,
+    store: Option<Store>,
+    snapshot_key: Option<SnapshotKey>,
+}
+
+impl Namespace {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn config(&self) -> &NamespaceConfig {
+        &self.config
+    }
+
+    // genesis is the hash of the chain's genesis block, which its peers must share.
+    pub fn genesis(&self) -> &Hashtype {
+        &self.genesis
+    }
+
+    // snapshot is the chain as it is now, signed with the node's snapshot key.
+    pub fn snapshot(&self) -> Result<Snapshot, String> {
+        let key = self
+            .snapshot_key
+            .as_ref()
+            .ok_or("this node has no snapshot key")?;
+        let state = self.blockchain.read().unwrap().state();
+        Snapshot::take(key, &self.name, &state)
+    }
+
+    // import takes up snapshot in place of the chain we hold, which must be a beginning of it.
+    // It is true when the snapshot was taken up, and false when the chain already reaches its
+    // tip, as it does when a node bootstrapped from the snapshot restarts. Indexes and the
+    // key-value view are brought in line with our config; the blocks we are missing are stored.
+    pub fn import(&self, snapshot: &Snapshot) -> Result<bool, String> {
+        if snapshot.chain != self.name || snapshot.genesis != self.genesis {
+            return Err(format!("snapshot is of another chain than {}", self.name));
+        }
+        let state = snapshot.open(&self.config.snapshot_keys)?;
+        let mut chain = self.blockchain.write().unwrap();
+        if chain.length() >= snapshot.length {
+            return Ok(false);
+        }
+        if state.blocks[chain.length() as usize - 1].hash != *chain.tip() {
+            return Err("snapshot does not extend the chain we hold".to_string());
+        }
+        let mut restored = Blockchain::restore(state, &self.config.redaction_keys)?;
+        restored.set_indexes(&self.config.indexed_fields);
+        if restored.kv().map(|kv| kv.config()) != self.config.kv.as_ref() {
+            restored.set_kv(self.config.kv.clone());
+        }
+        *chain = restored;
+        match &self.store {
+            Some(store) => store.flush(&chain).map(|_| true),
+            None => Ok(true),
+        }
+    }
+
+    // flush stores the blocks sealed since it last ran, if the chain is stored at all.
+    pub fn flush(&self) -> Result<(), String> {
+        match &self.store {
+            Some(store) => store.flush(&self.blockchain.read().unwrap()),
+            None => Ok(()),
+        }
+    }
+}
+
+// Store is where a chain is kept: config.json, and blocks.jsonl, which holds a block a line and
+// grows as the chain does. It is rewritten whole only when a record is redacted, so that the
+// entry is gone from disk too.
+struct Store {
+    dir: PathBuf,
+    // persisted is how many blocks blocks.jsonl holds, and how many of their records are redacted.
+    persisted: Mutex<(u64, u64)>,
+}
+
+impl Store {
+    fn new(dir: PathBuf) -> Store {
+        Store {
+            dir,
+            persisted: Mutex::new((0, 0)),
+        }
+    }
+
+    fn blocks_file(&self) -> PathBuf {
+        self.dir.join("blocks.jsonl")
+    }
+
+    // save_config stores config, readable by the node's user alone. Keys are stored as the
+    // files they are in, never inline, so no secret is kept with the chain.
+    fn save_config(&self, config: &NamespaceConfig) -> Result<(), String> {
+        if let Some(k) = config.api_keys.iter().find(|k| k.key.is_some()) {
+            return Err(format!(
+                "key {} of a stored chain must be given as key_file, not inline",
+                k.name
+            ));
+        }
+        std::fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("failed to create {}: {e}", self.dir.display()))?;
+        let json = serde_json::to_string_pretty(config).map_err(|e| e.to_string())?;
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options
+            .open(self.dir.join("config.json"))
+            .and_then(|mut file| file.write_all(json.as_bytes()))
+            .map_err(|e| format!("failed to write config of {}: {e}", self.dir.display()))
+    }
+
+    fn load_config(dir: &Path) -> Result<NamespaceConfig, String> {
+        let json = std::fs::read_to_string(dir.join("config.json"))
+            .map_err(|e| format!("failed to read config of {}: {e}", dir.display()))?;
+        serde_json::from_str(&json)
+            .map_err(|e| format!("failed to parse config of {}: {e}", dir.display()))
+    }
+
+    // load appends the stored blocks to chain, which holds only its genesis block.
+    fn load(&self, chain: &mut Blockchain) -> Result<(), String> {
+        let file = self.blocks_file();
+        let stored = match std::fs::read_to_string(&file) {
+            Ok(stored) => stored,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(format!("failed to read {}: {e}", file.display())),
+        };
+        let blocks = stored
+            .lines()
+            .map(serde_json::from_str::<Block>)
+            .collect::<Result<Vec<Block>, _>>()
+            .map_err(|e| format!("failed to parse {}: {e}", file.display()))?;
+        let Some((genesis, rest)) = blocks.split_first() else {
+            return Ok(());
+        };
+        if Some(genesis) != chain.get(0) {
+            return Err(format!("{} belongs to another chain", file.display()));
+        }
+        if !rest.is_empty() {
+            chain.append_blocks(rest.to_vec())?;
+        }
+        chain.validate()?;
+        *self.persisted.lock().unwrap() = (blocks.len() as u64, chain.redactions());
+        Ok(())
+    }
+
+    fn flush(&self, chain: &Blockchain) -> Result<(), String> {
+        let mut persisted = self.persisted.lock().unwrap();
+        if persisted.1 < chain.redactions() {
+            return self.rewrite(chain, &mut persisted);
+        }
+        if persisted.0 >= chain.length() {
+            return Ok(());
+        }
+        let mut lines = String::new();
+        for block in chain.blocks().skip(persisted.0 as usize) {
+            lines.push_str(&serde_json::to_string(block).map_err(|e| e.to_string())?);
+            lines.push('\n');
+        }
+        std::fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("failed to create {}: {e}", self.dir.display()))?;
+        let file = self.blocks_file();
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&file)
+            .and_then(|mut f| f.write_all(lines.as_bytes()))
+            .map_err(|e| format!("failed to write {}: {e}", file.display()))?;
+        persisted.0 = chain.length();
+        Ok(())
+    }
+
+    // rewrite replaces blocks.jsonl with the chain as it is now, by way of a temporary file, so
+    // that the file is never half written.
+    fn rewrite(&self, chain: &Blockchain, persisted: &mut (u64, u64)) -> Result<(), String> {
+        let mut lines = String::new();
+        for block in chain.blocks() {
+            lines.push_str(&serde_json::to_string(block).map_err(|e| e.to_string())?);
+            lines.push('\n');
+        }
+        std::fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("failed to create {}: {e}", self.dir.display()))?;
+        let file = self.blocks_file();
+        let rewritten = self.dir.join("blocks.jsonl.new");
+        std::fs::write(&rewritten, lines)
+            .and_then(|_| std::fs::rename(&rewritten, &file))
+            .map_err(|e| format!("failed to write {}: {e}", file.display()))?;
+        *persisted = (chain.length(), chain.redactions());
+        Ok(())
+    }
+}
+
+/// Namespaces is every chain a node hosts.
+pub struct Namespaces {
+    chains: RwLock<BTreeMap<String, Arc<Namespace>>>,
+    admins: Keys,
+    idempotency_window: Duration,
+    peer_auth: PeerAuth,
+    data_dir: Option<PathBuf>,
+    snapshot_key: Option<SnapshotKey>,
+}
+
+impl Namespaces {
+    // new hosts no chains yet. admins are the keys that hold every scope on every chain.
+    pub fn new(admins: Keys, idempotency_window: Duration) -> Namespaces {
+        Namespaces {
+            chains: RwLock::new(BTreeMap::new()),
+            admins,
+            idempotency_window,
+            peer_auth: PeerAuth::default(),
+            data_dir: None,
+            snapshot_key: None,
+        }
+    }
+
+    // with_peer_auth has every chain show its peers it is a member of their cluster.
+    pub fn with_peer_auth(mut self, auth: PeerAuth) -> Namespaces {
+        self.peer_auth = auth;
+        self
+    }
+
+    // with_data_dir keeps every chain under dir/chains.
+    pub fn with_data_dir(mut self, dir: Option<PathBuf>) -> Namespaces {
+        self.data_dir = dir;
+        self
+    }
+
+    // with_snapshot_key has every chain sign the snapshots it is asked for with key.
+    pub fn with_snapshot_key(mut self, key: Option<SnapshotKey>) -> Namespaces {
+        self.snapshot_key = key;
+        self
+    }
+
+    fn dir_of(&self, name: &str) -> Option<PathBuf> {
+        self.data_dir
+            .as_ref()
+            .map(|dir| dir.join("chains").join(name))
+    }
+
+    // open hosts the chain called name, with the blocks stored of it.
+    pub fn open(&self, name: &str, config: NamespaceConfig) -> Result<Arc<Namespace>, String> {
+        let mut chains = self.chains.write().unwrap();
+        if chains.contains_key(name) {
+            return Err(format!("chain {name} already exists"));
+        }
+        let namespace = Arc::new(self.build(name, config, false)?);
+        chains.insert(name.to_string(), namespace.clone());
+        Ok(namespace)
+    }
+
+    // create hosts a new named chain, storing its config so that it is hosted again on restart.
+    pub fn create(&self, name: &str, config: NamespaceConfig) -> Result<Arc<Namespace>, String> {
+        if !valid_name(name) {
+            return Err(format!(
+                "chain name {name:?} must be lowercase letters, digits, '-' and '_'"
+            ));
+        }
+        if name == DEFAULT {
+            return Err(format!("chain {name} already exists"));
+        }
+        let mut chains = self.chains.write().unwrap();
+        if chains.contains_key(name) {
+            return Err(format!("chain {name} already exists"));
+        }
+        let namespace = Arc::new(self.build(name, config, true)?);
+        chains.insert(name.to_string(), namespace.clone());
+        Ok(namespace)
+    }
+
+    // load_all hosts every named chain stored in the data directory.
+    pub fn load_all(&self) -> Result<Vec<Arc<Namespace>>, String> {
+        let dir = match &self.data_dir {
+            Some(dir) => dir.join("chains"),
+            None => return Ok(vec![]),
+        };
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(format!("failed to read {}: {e}", dir.display())),
+        };
+        let mut names: Vec<String> = entries
+            .flatten()
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| valid_name(name) && name != DEFAULT)
+            .filter(|name| dir.join(name).join("config.json").exists())
+            .collect();
+        names.sort();
+        names
+            .iter()
+            .map(|name| self.open(name, Store::load_config(&dir.join(name))?))
+            .collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Namespace>> {
+        self.chains.read().unwrap().get(name).cloned()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.chains.read().unwrap().keys().cloned().collect()
+    }
+
+    fn build(&self, name: &str, config: NamespaceConfig, new: bool) -> Result<Namespace, String> {
+        let access = Access::new(
+            self.admins.and(Keys::load(&config.api_keys)?)?,
+            &config.anonymous_scopes,
+        );
+        let mut blockchain = match &config.genesis {
+            Some(genesis) => Blockchain::from_genesis(genesis)?,
+            None if name == DEFAULT => Blockchain::new(),
+            None => Blockchain::named(name),
+        };
+        blockchain.set_redaction_keys(&config.redaction_keys)?;
+        if let Some(rate_limit) = &config.rate_limit {
+            rate_limit.validate()?;
+        }
+        for key in &config.snapshot_keys {
+            crate::signing::public_key(key)?;
+        }
+        blockchain.set_indexes(&config.indexed_fields);
+        blockchain.set_kv(config.kv.clone());
+
+        let store = self.dir_of(name).map(Store::new);
+        if let Some(store) = &store {
+            if new {
+                store.save_config(&config)?;
+            } else {
+                store.load(&mut blockchain)?;
+            }
+        }
+
+        let genesis = blockchain.genesis_hash().clone();
+        let peer_auth = PeerAuth {
+            namespace: (name != DEFAULT).then(|| name.to_string()),
+            genesis: Some(genesis.clone()),
+            ..self.peer_auth.clone()
+        };
+        Ok(Namespace {
+            name: name.to_string(),
+            genesis,
+            blockchain: Arc::new(RwLock::new(blockchain)),
+            mem_pool: Arc::new(RwLock::new(
+                MemPool::new(config.mempool_size).with_retention(
+                    config.mempool_ttl_secs.map(Duration::from_secs),
+                    config.mempool_eviction,
+                ),
+            )),
+            downstreams: Arc::new(RwLock::new(
+                Downstreams::new(config.downstreams.iter().map(WriteHost::new).collect())
+                    .with_auth(peer_auth.clone()),
+            )),
+            upstreams: Arc::new(RwLock::new(
+                Upstreams::new(config.upstreams.iter().map(ReadHost::new).collect())
+                    .with_auth(peer_auth),
+            )),
+            access,
+            limiter: Mutex::new(Limiter::new(config.rate_limit.clone())),
+            receipts: Mutex::new(Receipts::new(self.idempotency_window)),
+            store,
+            snapshot_key: self.snapshot_key.clone(),
+            config,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::Record;
+
+    #[test]
+    fn test_names() {
+        assert!(valid_name("payments"));
+        assert!(valid_name("team-2_ledger"));
+        assert!(!valid_name(""));
+        assert!(!valid_name("-x"));
+        assert!(!valid_name("Payments"));
+        assert!(!valid_name("a/b"));
+        assert!(!valid_name(".."));
+        assert!(!valid_name(&"a".repeat(65)));
+    }
+
+    #[test]
+    fn test_stored_chains_are_hosted_again() {
+        let dir = std::env::temp_dir().join(format!("tiaf-chains-{}", uuid::Uuid::new_v4()));
+        let namespaces = || {
+            Namespaces::new(Keys::default(), Duration::from_secs(60))
+                .with_data_dir(Some(dir.clone()))
+        };
+
+        let node = namespaces();
+        let config = NamespaceConfig {
+            indexed_fields: vec!["status".to_string()],
+            anonymous_scopes: vec![Scope::Read],
+            ..NamespaceConfig::default()
+        };
+        let payments = node.create("payments", config).unwrap();
+        assert!(node.create("payments", NamespaceConfig::default()).is_err());
+        assert!(node.create(DEFAULT, NamespaceConfig::default()).is_err());
+        assert!(node.create("Bad Name", NamespaceConfig::default()).is_err());
+        for batch in 0..3 {
+            let records = vec![Record::new(format!("{{\"status\":\"{batch}\"}}"))];
+            payments
+                .blockchain
+                .write()
+                .unwrap()
+                .append_records(records)
+                .unwrap();
+            payments.flush().unwrap();
+        }
+        let default = node.open(DEFAULT, NamespaceConfig::default()).unwrap();
+        default.flush().unwrap();
+        assert_eq!(node.names(), vec![DEFAULT, "payments"]);
+
+        let restarted = namespaces();
+        let loaded = restarted.load_all().unwrap();
+        assert_eq!(loaded.len(), 1);
+        let payments2 = restarted.get("payments").unwrap();
+        assert_eq!(
+            *payments2.blockchain.read().unwrap(),
+            *payments.blockchain.read().unwrap()
+        );
+        assert_eq!(payments2.config().indexed_fields, vec!["status"]);
+        assert_eq!(payments2.access.anonymous(), &[Scope::Read]);
+        assert_eq!(
+            payments2.blockchain.read().unwrap().indexes().fields(),
+            vec!["status"]
+        );
+
+        // a chain stored under another's name is refused.
+        std::fs::copy(
+            dir.join("chains/payments/blocks.jsonl"),
+            dir.join("chains/default/blocks.jsonl"),
+        )
+        .unwrap();
+        assert!(namespaces()
+            .open(DEFAULT, NamespaceConfig::default())
+            .is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,9 +1,37 @@
 // TODO: rename file to network or something beyond just peer
-use crate::api::{TiafClient, TiafDownstreams, TiafPartialChain, TiafUpstreams};
+use crate::api::{TiafClient, TiafDownstreams, TiafUpstreams};
+use crate::block::Block;
 use crate::chain::Blockchain;
+use crate::cluster::ClusterKey;
 use crate::record::Record;
+use crate::tls::ClientTls;
+use crate::types::Hashtype;
 use crate::{notes, woody, Attributes};
 
+/// PeerAuth is how a node shows its peers it belongs to their cluster: by signing with the
+/// cluster secret, by presenting a certificate, or both. It also names the chain peers are
+/// reached at; unset, that is their default chain. With a genesis, peers whose chain began
+/// with another are refused.
+#[derive(Debug, Clone, Default)]
+pub struct PeerAuth {
+    pub cluster: Option<ClusterKey>,
+    pub tls: Option<ClientTls>,
+    pub namespace: Option<String>,
+    pub genesis: Option<Hashtype>,
+}
+
+impl PeerAuth {
+    // client is a client for the node at url, carrying our credentials.
+    pub fn client(&self, url: &str) -> Result<TiafClient, String> {
+        // admin key set to false. Peers are not admins.
+        TiafClient::new(url.to_string(), None)
+            .with_cluster(self.cluster.clone())
+            .with_namespace(self.namespace.clone())
+            .with_genesis(self.genesis.clone())
+            .with_tls(self.tls.clone())
+    }
+}
+
 #[derive(Clone)]
 pub struct WriteHost {
     url: String,
@@ -22,20 +50,36 @@ impl WriteHost {
     pub fn url(&self) -> String {
         self.url.clone()
     }
-    pub fn notify_host(&mut self, r: &Record) -> Result<(), String> {
-        // admin key set to false. Peers are not admins.
-        let client = TiafClient::new(self.url.clone(), None);
+    // notify_host hands r to the host, showing it we are a member of its cluster.
+    pub fn notify_host(&mut self, r: &Record, auth: &PeerAuth) -> Result<(), String> {
+        let client = auth.client(&self.url)?;
         client.put_record(r)?;
         self.latest_hash = Some(r.hash.clone());
         self.last_pushed = Some(std::time::Instant::now());
         Ok(())
     }
+
+    // push_block hands the host a block sealed here without passing through the pool.
+    pub fn push_block(&mut self, block: &Block, auth: &PeerAuth) -> Result<(), String> {
+        let client = auth.client(&self.url)?;
+        client.put_block(block)?;
+        self.latest_hash = Some(block.hash.clone());
+        self.last_pushed = Some(std::time::Instant::now());
+        Ok(())
+    }
 }
 
+// The most sealed blocks kept waiting for a downstream that cannot be reached.
+const MAX_UNPUSHED_BLOCKS: usize = 64;
+
 #[derive(Clone)]
 pub struct Downstreams {
     hosts: Vec<WriteHost>,
     pub sweeping: bool,
+    auth: PeerAuth,
+    // sealed are blocks sealed here outside the pool, such as atomic batches, that some
+    // downstream has yet to take. Their records were never pending, so they are pushed whole.
+    sealed: Vec<Block>,
 }
 
 impl Downstreams {
@@ -52,6 +96,8 @@ impl Downstreams {
         Downstreams {
             hosts,
             sweeping: api.sweeping,
+            auth: PeerAuth::default(),
+            sealed: vec![],
         }
     }
 
@@ -59,8 +105,57 @@ impl Downstreams {
         Downstreams {
             hosts: p,
             sweeping: false,
+            auth: PeerAuth::default(),
+            sealed: vec![],
         }
     }
+
+    // reconfigured is other, the downstreams an admin set, keeping our credentials and the
+    // blocks not yet pushed.
+    pub fn reconfigured(&self, other: Downstreams) -> Downstreams {
+        Downstreams {
+            auth: self.auth.clone(),
+            sealed: self.sealed.clone(),
+            ..other
+        }
+    }
+
+    // hand_over queues block to be pushed to every downstream. Past MAX_UNPUSHED_BLOCKS, the
+    // oldest are given up on and returned.
+    pub fn hand_over(&mut self, block: Block) -> Vec<Block> {
+        self.sealed.push(block);
+        let excess = self.sealed.len().saturating_sub(MAX_UNPUSHED_BLOCKS);
+        self.sealed.drain(..excess).collect()
+    }
+
+    // push_sealed pushes the queued blocks to every downstream, keeping those some downstream
+    // did not take to try again. It returns the errors met along the way.
+    pub fn push_sealed(&mut self) -> Vec<String> {
+        let mut errors = vec![];
+        let sealed = std::mem::take(&mut self.sealed);
+        for block in sealed {
+            let mut pushed = true;
+            for host in self.hosts.iter_mut() {
+                if let Err(e) = host.push_block(&block, &self.auth) {
+                    errors.push(format!("{}: {e}", host.url));
+                    pushed = false;
+                }
+            }
+            if !pushed {
+                self.sealed.push(block);
+            }
+        }
+        errors
+    }
+
+    pub fn with_auth(mut self, auth: PeerAuth) -> Downstreams {
+        self.auth = auth;
+        self
+    }
+
+    pub fn auth(&self) -> &PeerAuth {
+        &self.auth
+    }
     pub fn downstreams(&self) -> Vec<WriteHost> {
         self.hosts.to_vec()
     }
@@ -87,6 +182,7 @@ impl ReadHost {
 pub struct Upstreams {
     hosts: Vec<ReadHost>,
     pub sweeping: bool,
+    auth: PeerAuth,
 }
 
 impl Upstreams {
@@ -103,6 +199,7 @@ impl Upstreams {
         Upstreams {
             hosts,
             sweeping: api.sweeping,
+            auth: PeerAuth::default(),
         }
     }
 
@@ -110,9 +207,20 @@ impl Upstreams {
         Upstreams {
             hosts,
             sweeping: false,
+            auth: PeerAuth::default(),
         }
     }
 
+    // with_auth has sweeps show upstreams we are members, and accept blocks only from members.
+    pub fn with_auth(mut self, auth: PeerAuth) -> Upstreams {
+        self.auth = auth;
+        self
+    }
+
+    pub fn auth(&self) -> &PeerAuth {
+        &self.auth
+    }
+
     pub fn upstreams(&self) -> Vec<ReadHost> {
         self.hosts.to_vec()
     }
@@ -129,26 +237,24 @@ impl Upstreams {
         self.hosts.retain(|p| p.url != peer.url);
     }
 
-    fn get_upstream(url: &str) -> Result<TiafPartialChain, String> {
-        match reqwest::blocking::get(url) {
-            Ok(resp) => match resp.json::<TiafPartialChain>() {
-                Ok(chain) => Ok(chain),
-                Err(e) => Err(format!("failed to parse json: {e}")),
-            },
-            Err(e) => Err(format!("failed to get peer: {e}")),
-        }
-    }
-
     /// sweep_all_peers will sweep all upstreams and update the chain if a longer chain is found.
     /// This does not relate to the mempool.
     pub fn sweep_all_upstreams(&mut self, chain: &mut Blockchain) -> Result<(), String> {
-        let logger = woody::new(woody::Level::Info).lock().unwrap();
+        // the logger is shared by the whole node, so it is locked only to log.
+        let logger = woody::new(woody::Level::Info);
         for host in &mut self.hosts {
             // get blocks of hashes from peer and compare list of hashes to existing chain.
             // if longer, request blocks from peer to glom on starting from the hash that wasn't seen.
             // TODO: work out proper api for this one.
-            let url = host.url.clone() + "/api/v1/chain";
-            let other_chain = Upstreams::get_upstream(url.as_str())?;
+            let other_chain = self.auth.client(&host.url)?.get_peer_chain()?;
+            if other_chain
+                .partial_blocks
+                .first()
+                .is_some_and(|b| &b.hash != chain.genesis_hash())
+            {
+                return Err(format!("upstream {} is on another chain", host.url));
+            }
+            adopt_redactions(chain, &other_chain.partial_blocks);
             if other_chain.total_length > chain.length() {
                 let starting_idx = other_chain
                     .partial_blocks
@@ -157,18 +263,24 @@ impl Upstreams {
 
                 match starting_idx {
                     Some(idx) => {
-                        logger.info(notes!(
+                        logger.lock().unwrap().info(notes!(
                             "msg",
                             format!("found starting index: {idx}").to_string()
                         ));
                         chain.append_blocks(other_chain.partial_blocks[idx..].to_vec())?;
                     }
                     None => {
-                        logger.warn(notes!("msg", "no starting index found".to_string()));
+                        logger
+                            .lock()
+                            .unwrap()
+                            .warn(notes!("msg", "no starting index found".to_string()));
                     }
                 }
                 if starting_idx.is_none() {
-                    logger.warn(notes!("msg", "no starting index found".to_string()));
+                    logger
+                        .lock()
+                        .unwrap()
+                        .warn(notes!("msg", "no starting index found".to_string()));
                     continue;
                 }
             }
@@ -181,6 +293,63 @@ impl Upstreams {
         }
         Ok(())
     }
+
+    // catch_up appends the blocks each upstream sealed after our tip, as a node does once it
+    // has taken up a snapshot rather than replaying the chain. Upstreams behind us have nothing
+    // we have not seen.
+    pub fn catch_up(&mut self, chain: &mut Blockchain) -> Result<(), String> {
+        for host in &mut self.hosts {
+            let since = self
+                .auth
+                .client(&host.url)?
+                .get_peer_chain_since(chain.tip())?;
+            let blocks: Vec<Block> = since
+                .partial_blocks
+                .into_iter()
+                .filter(|b| !chain.block_seen(&b.hash))
+                .collect();
+            if !blocks.is_empty() {
+                chain.append_blocks(blocks)?;
+            }
+            host.latest_hash = Some(chain.tip().clone());
+            host.last_swept = Some(std::time::Instant::now());
+        }
+        Ok(())
+    }
+}
+
+// adopt_redactions redacts the records of chain that are redacted in blocks, an upstream's copy
+// of the chain, so that redactions spread as blocks do. Redactions chain does not accept are
+// passed over.
+fn adopt_redactions(chain: &mut Blockchain, blocks: &[Block]) {
+    let logger = woody::new(woody::Level::Info);
+    for (i, block) in blocks.iter().enumerate() {
+        let Some(ours) = chain.get(i as u64) else {
+            break;
+        };
+        if ours.hash != block.hash {
+            break;
+        }
+        let adopted: Vec<_> = block
+            .data
+            .iter()
+            .zip(&ours.data)
+            .filter_map(
+                |(theirs, ours)| match (&theirs.redaction, &ours.redaction) {
+                    (Some(redaction), None) => Some((theirs.hash.clone(), redaction.clone())),
+                    _ => None,
+                },
+            )
+            .collect();
+        for (hash, redaction) in adopted {
+            if let Err(e) = chain.redact(&hash, redaction) {
+                logger.lock().unwrap().warn(notes!(
+                    "msg",
+                    format!("passed over redaction from upstream: {e}").to_string()
+                ));
+            }
+        }
+    }
 }
 
 #[cfg(test)]
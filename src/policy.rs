@@ -0,0 +1,150 @@
+use crate::chain::Blockchain;
+use crate::mempool::MemPool;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+fn default_max_latency_secs() -> u64 {
+    15
+}
+
+fn default_min_records() -> usize {
+    1
+}
+
+fn default_seal_on_demand() -> bool {
+    true
+}
+
+/// BlockPolicy says when pending records are sealed into a block, and how big a block may get.
+///
+/// A block is sealed as soon as the pool holds a full block's worth of records, by count or by
+/// bytes of entry. Short of that, whatever is pending is sealed once max_latency has passed since
+/// the last seal, provided at least min_records are waiting. Raising max_latency or min_records
+/// makes fewer, larger blocks; lowering them gets records onto the chain sooner.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockPolicy {
+    // max_records is the most records in one block. None leaves it to the size of the pool.
+    #[serde(default)]
+    pub max_records: Option<usize>,
+    // max_bytes is the most bytes of entry in one block. A single larger record is still sealed,
+    // alone.
+    #[serde(default)]
+    pub max_bytes: Option<usize>,
+    #[serde(default = "default_max_latency_secs")]
+    pub max_latency_secs: u64,
+    #[serde(default = "default_min_records")]
+    pub min_records: usize,
+    // seal_on_demand lets an admin seal a block at any time.
+    #[serde(default = "default_seal_on_demand")]
+    pub seal_on_demand: bool,
+}
+
+impl Default for BlockPolicy {
+    fn default() -> BlockPolicy {
+        BlockPolicy {
+            max_records: None,
+            max_bytes: None,
+            max_latency_secs: default_max_latency_secs(),
+            min_records: default_min_records(),
+            seal_on_demand: default_seal_on_demand(),
+        }
+    }
+}
+
+impl BlockPolicy {
+    pub fn max_latency(&self) -> Duration {
+        Duration::from_secs(self.max_latency_secs)
+    }
+
+    // block_len is how many of the oldest pending records fit in the next block.
+    fn block_len(&self, pool: &MemPool) -> usize {
+        let max_records = self.max_records.unwrap_or(usize::MAX).max(1);
+        let max_bytes = self.max_bytes.unwrap_or(usize::MAX);
+        let mut bytes = 0;
+        let mut n = 0;
+        for r in pool.contents().take(max_records) {
+            bytes += r.entry.len();
+            if n > 0 && bytes > max_bytes {
+                break;
+            }
+            n += 1;
+        }
+        n
+    }
+
+    // full_block is whether the pool holds more than fits in a block, or exactly a block.
+    fn full_block(&self, pool: &MemPool) -> bool {
+        let n = self.block_len(pool);
+        n > 0 && (n < pool.length() || self.max_records.is_some_and(|max| n >= max))
+    }
+
+    // seal seals the oldest pending records that fit into one block, returning how many were
+    // sealed. Records leave the pool only once they are on the chain, so a failed seal loses
+    // none of them.
+    pub fn seal(&self, chain: &mut Blockchain, pool: &mut MemPool) -> Result<usize, String> {
+        let n = self.block_len(pool);
+        if n == 0 {
+            return Ok(0);
+        }
+        let records = pool.contents().take(n).cloned().collect();
+        chain.append_new_records(records)?;
+        pool.take(n);
+        Ok(n)
+    }
+
+    // sweep seals every block that is due, given how long it has been since the last seal, and
+    // returns the number of blocks sealed. Once latency is due, everything pending is sealed.
+    pub fn sweep(
+        &self,
+        chain: &mut Blockchain,
+        pool: &mut MemPool,
+        since_last_seal: Duration,
+    ) -> Result<usize, String> {
+        let latency_due =
+            since_last_seal >= self.max_latency() && pool.length() >= self.min_records;
+        let mut blocks = 0;
+        while pool.length() > 0 && (latency_due || self.full_block(pool)) {
+            self.seal(chain, pool)?;
+            blocks += 1;
+        }
+        Ok(blocks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::Record;
+
+    #[test]
+    fn test_block_policy() {
+        let policy = BlockPolicy {
+            max_records: Some(3),
+            max_bytes: Some(10),
+            min_records: 2,
+            ..BlockPolicy::default()
+        };
+        let mut chain = Blockchain::new();
+        let mut pool = MemPool::new(10);
+        let early = Duration::from_secs(1);
+        let late = policy.max_latency();
+
+        pool.put(Record::new("a".to_string())).unwrap();
+        assert_eq!(policy.sweep(&mut chain, &mut pool, late), Ok(0));
+
+        for entry in ["b", "c", "dddddddddd", "e"] {
+            pool.put(Record::new(entry.to_string())).unwrap();
+        }
+        // a, b, c make a full block by count; dddddddddd and e do not fit in 10 bytes together.
+        assert_eq!(policy.sweep(&mut chain, &mut pool, early), Ok(2));
+        assert_eq!(chain.get(1).unwrap().data.len(), 3);
+        assert_eq!(chain.get(2).unwrap().data[0].entry, "dddddddddd");
+        assert_eq!(pool.length(), 1);
+
+        assert_eq!(policy.sweep(&mut chain, &mut pool, late), Ok(0));
+        pool.put(Record::new("f".to_string())).unwrap();
+        assert_eq!(policy.sweep(&mut chain, &mut pool, late), Ok(1));
+        assert_eq!(pool.length(), 0);
+        assert_eq!(chain.length(), 4);
+    }
+}
@@ -1,27 +1,135 @@
 use crate::pratt::ExpressionError::{
-    DidntGetRightParen, RanOutOfTokens, UnboundVariable, UnexpectedToken, UnsupportedOperation,
+    DidntGetRightParen, InvalidEscape, NumberTooLarge, Overflow, RanOutOfTokens, UnboundVariable,
+    UnexpectedCharacter, UnexpectedToken, UnsupportedOperation, UnterminatedString,
 };
 use core::fmt;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::str::FromStr;
 
+/// Span is a byte range of the source text a token or error came from.
+#[derive(Eq, Debug, PartialEq, Clone, Copy)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    // line_column is the 1-based line and column of the start of the span in source.
+    pub fn line_column(&self, source: &str) -> (usize, usize) {
+        let before = &source[..self.start.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+        (line, column)
+    }
+
+    // excerpt is the source line the span starts on, with carets under the span beneath it.
+    pub fn excerpt(&self, source: &str) -> String {
+        let (line, column) = self.line_column(source);
+        let text = source.lines().nth(line - 1).unwrap_or("");
+        let start = self.start.min(source.len());
+        let end = self.end.clamp(start, source.len());
+        let width = source[start..end]
+            .split('\n')
+            .next()
+            .unwrap_or("")
+            .chars()
+            .count()
+            .max(1);
+        format!("{text}\n{}{}", " ".repeat(column - 1), "^".repeat(width))
+    }
+}
+
 #[derive(Eq, Debug, PartialEq)]
 pub enum ExpressionError {
-    UnexpectedToken(Token),
+    UnexpectedToken {
+        found: Token,
+        span: Span,
+        expected: Vec<&'static str>,
+    },
     UnboundVariable(String),
     UnsupportedOperation,
-    RanOutOfTokens,
-    DidntGetRightParen,
+    RanOutOfTokens {
+        span: Span,
+        expected: Vec<&'static str>,
+    },
+    DidntGetRightParen {
+        open: Span,
+        span: Span,
+    },
+    UnexpectedCharacter(char, Span),
+    UnterminatedString(Span),
+    NumberTooLarge(String, Span),
+    InvalidEscape(char, Span),
+    Overflow,
+}
+
+// What may start an expression, for errors raised where one was wanted.
+const EXPRESSION_START: [&str; 6] = ["number", "string", "identifier", "(", "-", "!"];
+
+impl ExpressionError {
+    // span is where in the source the error was found. Evaluation errors have no span.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            UnexpectedToken { span, .. }
+            | RanOutOfTokens { span, .. }
+            | DidntGetRightParen { span, .. }
+            | UnexpectedCharacter(_, span)
+            | UnterminatedString(span)
+            | NumberTooLarge(_, span)
+            | InvalidEscape(_, span) => Some(*span),
+            UnboundVariable(_) | UnsupportedOperation | Overflow => None,
+        }
+    }
+
+    // expected lists what the parser would have accepted at the error.
+    pub fn expected(&self) -> Vec<&'static str> {
+        match self {
+            UnexpectedToken { expected, .. } | RanOutOfTokens { expected, .. } => expected.clone(),
+            DidntGetRightParen { .. } => vec![")"],
+            _ => vec![],
+        }
+    }
+
+    // render formats the error against the source it came from, compiler style:
+    //
+    //   unexpected token `)` at line 1, column 6
+    //   x == )
+    //        ^
+    //   expected one of: number, string, identifier, (, -, !
+    pub fn render(&self, source: &str) -> String {
+        let mut out = self.to_string();
+        if let Some(span) = self.span() {
+            let (line, column) = span.line_column(source);
+            out.push_str(&format!(" at line {line}, column {column}\n"));
+            out.push_str(&span.excerpt(source));
+        }
+        let expected = self.expected();
+        if !expected.is_empty() {
+            out.push_str(&format!("\nexpected one of: {}", expected.join(", ")));
+        }
+        out
+    }
 }
 
 impl fmt::Display for ExpressionError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            UnexpectedToken(t) => write!(f, "Unexpected token: {t:?}"),
+            UnexpectedToken { found, .. } => write!(f, "unexpected token `{found}`"),
             UnboundVariable(v) => write!(f, "Unbound variable: {v}"),
             UnsupportedOperation => write!(f, "Unsupported operation"),
-            RanOutOfTokens => write!(f, "Ran out of tokens"),
-            DidntGetRightParen => write!(f, "Didn't get right paren"),
+            RanOutOfTokens { .. } => write!(f, "unexpected end of input"),
+            DidntGetRightParen { .. } => write!(f, "unclosed `(`"),
+            UnexpectedCharacter(c, _) => write!(f, "unexpected character `{c}`"),
+            UnterminatedString(_) => write!(f, "unterminated string"),
+            NumberTooLarge(n, _) => write!(f, "number {n} is too large"),
+            InvalidEscape(c, _) => write!(f, "invalid escape `\\{c}` in string"),
+            Overflow => write!(f, "arithmetic overflow"),
         }
     }
 }
@@ -40,6 +148,13 @@ pub enum Token {
     ParenClose,
     NotEquals,
     Not,
+    Comma,
+    Less,
+    LessEquals,
+    Greater,
+    GreaterEquals,
+    And,
+    Or,
 }
 
 impl fmt::Display for Token {
@@ -48,20 +163,34 @@ impl fmt::Display for Token {
             Token::Plus => write!(f, "+"),
             Token::Minus => write!(f, "-"),
             Token::Multiply => write!(f, "*"),
-            Token::Equals => write!(f, "="),
+            Token::Equals => write!(f, "=="),
             Token::Num(n) => write!(f, "{n}"),
             Token::Var(v) => write!(f, "{v}"),
-            Token::Str(s) => write!(f, "{s}"),
+            Token::Str(s) => write!(f, "{s:?}"),
             Token::Bool(b) => write!(f, "{b}"),
             Token::ParenOpen => write!(f, "("),
             Token::ParenClose => write!(f, ")"),
             Token::NotEquals => write!(f, "!="),
             Token::Not => write!(f, "!"),
+            Token::Comma => write!(f, ","),
+            Token::Less => write!(f, "<"),
+            Token::LessEquals => write!(f, "<="),
+            Token::Greater => write!(f, ">"),
+            Token::GreaterEquals => write!(f, ">="),
+            Token::And => write!(f, "&&"),
+            Token::Or => write!(f, "||"),
         }
     }
 }
 
-#[derive(Clone)]
+/// Spanned is a token along with where in the source it was read from.
+#[derive(Eq, Debug, PartialEq, Clone)]
+pub struct Spanned {
+    pub token: Token,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ASTNode {
     Num(i32),
     Str(String),
@@ -73,14 +202,36 @@ pub enum ASTNode {
     Eq(Box<ASTNode>, Box<ASTNode>),
     Not(Box<ASTNode>),
     NotEq(Box<ASTNode>, Box<ASTNode>),
+    Lt(Box<ASTNode>, Box<ASTNode>),
+    Le(Box<ASTNode>, Box<ASTNode>),
+    Gt(Box<ASTNode>, Box<ASTNode>),
+    Ge(Box<ASTNode>, Box<ASTNode>),
+    And(Box<ASTNode>, Box<ASTNode>),
+    Or(Box<ASTNode>, Box<ASTNode>),
 }
 
 pub struct Parser {
     tokens: Vec<Token>,
+    // spans[i] is where tokens[i] came from.
+    spans: Vec<Span>,
+    // end is where the input stops, for errors about running out of it.
+    end: Span,
     pos: usize,
 }
 
 impl Parser {
+    // new builds a parser over lexed tokens, whose spans its errors report. end is where the
+    // source stops.
+    pub fn new(tokens: Vec<Spanned>, end: Span) -> Parser {
+        let (tokens, spans) = tokens.into_iter().map(|t| (t.token, t.span)).unzip();
+        Parser {
+            tokens,
+            spans,
+            end,
+            pos: 0,
+        }
+    }
+
     pub fn parse(&mut self, precedence: i32) -> Result<ASTNode, ExpressionError> {
         let mut result = match self.get_next_token() {
             Some(Token::Minus) if precedence < 30 => {
@@ -115,13 +266,21 @@ impl Parser {
                 Ok(r)
             }
             Some(Token::ParenOpen) if precedence < 50 => {
+                let open = self.span();
                 self.consume_operator();
                 let subexpr = self.parse(0)?;
-                self.expect_paren_close()?;
+                self.expect_paren_close(open)?;
                 Ok(subexpr)
             }
-            Some(t) => Err(ExpressionError::UnexpectedToken(t.clone())),
-            None => Err(ExpressionError::RanOutOfTokens),
+            Some(t) => Err(UnexpectedToken {
+                found: t.clone(),
+                span: self.span(),
+                expected: EXPRESSION_START.to_vec(),
+            }),
+            None => Err(RanOutOfTokens {
+                span: self.end,
+                expected: EXPRESSION_START.to_vec(),
+            }),
         }?;
 
         loop {
@@ -146,6 +305,30 @@ impl Parser {
                     self.consume_operator();
                     result = ASTNode::NotEq(Box::new(result), Box::new(self.parse(10)?));
                 }
+                Some(Token::Less) if precedence < 15 => {
+                    self.consume_operator();
+                    result = ASTNode::Lt(Box::new(result), Box::new(self.parse(15)?));
+                }
+                Some(Token::LessEquals) if precedence < 15 => {
+                    self.consume_operator();
+                    result = ASTNode::Le(Box::new(result), Box::new(self.parse(15)?));
+                }
+                Some(Token::Greater) if precedence < 15 => {
+                    self.consume_operator();
+                    result = ASTNode::Gt(Box::new(result), Box::new(self.parse(15)?));
+                }
+                Some(Token::GreaterEquals) if precedence < 15 => {
+                    self.consume_operator();
+                    result = ASTNode::Ge(Box::new(result), Box::new(self.parse(15)?));
+                }
+                Some(Token::And) if precedence < 6 => {
+                    self.consume_operator();
+                    result = ASTNode::And(Box::new(result), Box::new(self.parse(6)?));
+                }
+                Some(Token::Or) if precedence < 4 => {
+                    self.consume_operator();
+                    result = ASTNode::Or(Box::new(result), Box::new(self.parse(4)?));
+                }
                 _ => break,
             }
         }
@@ -153,17 +336,32 @@ impl Parser {
         Ok(result)
     }
 
+    // finish rejects any tokens left over after a complete expression.
+    pub fn finish(&self) -> Result<(), ExpressionError> {
+        match self.get_next_token() {
+            Some(t) => Err(UnexpectedToken {
+                found: t.clone(),
+                span: self.span(),
+                expected: vec!["an operator", "end of input"],
+            }),
+            None => Ok(()),
+        }
+    }
+
     fn consume_operator(&mut self) {
         self.pos += 1;
     }
 
-    fn expect_paren_close(&mut self) -> Result<(), ExpressionError> {
+    fn expect_paren_close(&mut self, open: Span) -> Result<(), ExpressionError> {
         match self.get_next_token() {
             Some(Token::ParenClose) => {
                 self.consume_operator();
                 Ok(())
             }
-            _ => Err(DidntGetRightParen),
+            _ => Err(DidntGetRightParen {
+                open,
+                span: self.span(),
+            }),
         }
     }
 
@@ -174,9 +372,15 @@ impl Parser {
             None
         }
     }
+
+    // span is where the next token sits, or the end of input when there is none.
+    fn span(&self) -> Span {
+        self.spans.get(self.pos).copied().unwrap_or(self.end)
+    }
 }
 
-#[derive(Eq, Debug, PartialEq, Clone)]
+// Values order by variant first (strings, then numbers, then booleans), then by content.
+#[derive(Eq, Debug, PartialEq, PartialOrd, Ord, Clone, Serialize, Deserialize)]
 pub enum Value {
     Str(String),
     Num(i32),
@@ -194,148 +398,295 @@ impl fmt::Display for Value {
 }
 
 pub fn eval(ast: ASTNode, environment: &HashMap<String, Value>) -> Result<Value, ExpressionError> {
+    evaluate(&ast, &|name: &str| environment.get(name).cloned())
+}
+
+// evaluate walks ast without consuming it, resolving variables through lookup. This lets a
+// parsed expression be evaluated over and over against different sources of variables.
+pub fn evaluate<F>(ast: &ASTNode, lookup: &F) -> Result<Value, ExpressionError>
+where
+    F: Fn(&str) -> Option<Value>,
+{
     match ast {
-        ASTNode::Num(num) => Ok(Value::Num(num)),
-        ASTNode::Str(s) => Ok(Value::Str(s)),
-        ASTNode::Bool(b) => Ok(Value::Bool(b)),
-        ASTNode::Var(name) => match environment.get(&name) {
-            Some(val) => Ok(val.clone()),
-            None => Err(ExpressionError::UnboundVariable(name)),
+        ASTNode::Num(num) => Ok(Value::Num(*num)),
+        ASTNode::Str(s) => Ok(Value::Str(s.clone())),
+        ASTNode::Bool(b) => Ok(Value::Bool(*b)),
+        ASTNode::Var(name) => match lookup(name) {
+            Some(val) => Ok(val),
+            None => Err(ExpressionError::UnboundVariable(name.clone())),
         },
-        ASTNode::Add(lhs, rhs) => {
-            let lhs = eval(*lhs, environment)?;
-            let rhs = eval(*rhs, environment)?;
-            match (lhs, rhs) {
-                (Value::Num(l), Value::Num(r)) => Ok(Value::Num(l + r)),
-                _ => Err(ExpressionError::UnsupportedOperation),
-            }
-        }
-        ASTNode::Sub(lhs, rhs) => {
-            let lhs = eval(*lhs, environment)?;
-            let rhs = eval(*rhs, environment)?;
-            match (lhs, rhs) {
-                (Value::Num(l), Value::Num(r)) => Ok(Value::Num(l - r)),
-                _ => Err(ExpressionError::UnsupportedOperation),
-            }
-        }
-        ASTNode::Mul(lhs, rhs) => {
-            let lhs = eval(*lhs, environment)?;
-            let rhs = eval(*rhs, environment)?;
-            match (lhs, rhs) {
-                (Value::Num(l), Value::Num(r)) => Ok(Value::Num(l * r)),
-                _ => Err(ExpressionError::UnsupportedOperation),
-            }
-        }
-        ASTNode::Eq(lhs, rhs) => {
-            let lhs = eval(*lhs, environment)?;
-            let rhs = eval(*rhs, environment)?;
-            match (lhs, rhs) {
-                (Value::Num(l), Value::Num(r)) => Ok(Value::Bool(l == r)),
-                (Value::Str(l), Value::Str(r)) => Ok(Value::Bool(l == r)),
-                (Value::Bool(l), Value::Bool(r)) => Ok(Value::Bool(l == r)),
+        ASTNode::Add(lhs, rhs) => match (evaluate(lhs, lookup)?, evaluate(rhs, lookup)?) {
+            (Value::Num(l), Value::Num(r)) => l.checked_add(r).map(Value::Num).ok_or(Overflow),
+            _ => Err(ExpressionError::UnsupportedOperation),
+        },
+        ASTNode::Sub(lhs, rhs) => match (evaluate(lhs, lookup)?, evaluate(rhs, lookup)?) {
+            (Value::Num(l), Value::Num(r)) => l.checked_sub(r).map(Value::Num).ok_or(Overflow),
+            _ => Err(ExpressionError::UnsupportedOperation),
+        },
+        ASTNode::Mul(lhs, rhs) => match (evaluate(lhs, lookup)?, evaluate(rhs, lookup)?) {
+            (Value::Num(l), Value::Num(r)) => l.checked_mul(r).map(Value::Num).ok_or(Overflow),
+            _ => Err(ExpressionError::UnsupportedOperation),
+        },
+        ASTNode::Eq(lhs, rhs) => match (evaluate(lhs, lookup)?, evaluate(rhs, lookup)?) {
+            (Value::Num(l), Value::Num(r)) => Ok(Value::Bool(l == r)),
+            (Value::Str(l), Value::Str(r)) => Ok(Value::Bool(l == r)),
+            (Value::Bool(l), Value::Bool(r)) => Ok(Value::Bool(l == r)),
+            _ => Err(ExpressionError::UnsupportedOperation),
+        },
+        ASTNode::NotEq(lhs, rhs) => match (evaluate(lhs, lookup)?, evaluate(rhs, lookup)?) {
+            (Value::Num(l), Value::Num(r)) => Ok(Value::Bool(l != r)),
+            (Value::Str(l), Value::Str(r)) => Ok(Value::Bool(l != r)),
+            (Value::Bool(l), Value::Bool(r)) => Ok(Value::Bool(l != r)),
+            _ => Err(ExpressionError::UnsupportedOperation),
+        },
+        ASTNode::Not(child) => match evaluate(child, lookup)? {
+            Value::Bool(n) => Ok(Value::Bool(!n)),
+            _ => Err(ExpressionError::UnsupportedOperation),
+        },
+        ASTNode::Lt(lhs, rhs) => compare(lhs, rhs, lookup, Ordering::is_lt),
+        ASTNode::Le(lhs, rhs) => compare(lhs, rhs, lookup, Ordering::is_le),
+        ASTNode::Gt(lhs, rhs) => compare(lhs, rhs, lookup, Ordering::is_gt),
+        ASTNode::Ge(lhs, rhs) => compare(lhs, rhs, lookup, Ordering::is_ge),
+        // && and || short-circuit: the right side is not evaluated when the left decides.
+        ASTNode::And(lhs, rhs) => match evaluate(lhs, lookup)? {
+            Value::Bool(false) => Ok(Value::Bool(false)),
+            Value::Bool(true) => match evaluate(rhs, lookup)? {
+                Value::Bool(r) => Ok(Value::Bool(r)),
                 _ => Err(ExpressionError::UnsupportedOperation),
-            }
-        }
-        ASTNode::NotEq(lhs, rhs) => {
-            let lhs = eval(*lhs, environment)?;
-            let rhs = eval(*rhs, environment)?;
-            match (lhs, rhs) {
-                (Value::Num(l), Value::Num(r)) => Ok(Value::Bool(l != r)),
-                (Value::Str(l), Value::Str(r)) => Ok(Value::Bool(l != r)),
-                (Value::Bool(l), Value::Bool(r)) => Ok(Value::Bool(l != r)),
+            },
+            _ => Err(ExpressionError::UnsupportedOperation),
+        },
+        ASTNode::Or(lhs, rhs) => match evaluate(lhs, lookup)? {
+            Value::Bool(true) => Ok(Value::Bool(true)),
+            Value::Bool(false) => match evaluate(rhs, lookup)? {
+                Value::Bool(r) => Ok(Value::Bool(r)),
                 _ => Err(ExpressionError::UnsupportedOperation),
+            },
+            _ => Err(ExpressionError::UnsupportedOperation),
+        },
+    }
+}
+
+// compare orders two numbers or two strings. Mixed or boolean operands do not compare.
+fn compare<F>(
+    lhs: &ASTNode,
+    rhs: &ASTNode,
+    lookup: &F,
+    test: fn(Ordering) -> bool,
+) -> Result<Value, ExpressionError>
+where
+    F: Fn(&str) -> Option<Value>,
+{
+    match (evaluate(lhs, lookup)?, evaluate(rhs, lookup)?) {
+        (Value::Num(l), Value::Num(r)) => Ok(Value::Bool(test(l.cmp(&r)))),
+        (Value::Str(l), Value::Str(r)) => Ok(Value::Bool(test(l.cmp(&r)))),
+        _ => Err(ExpressionError::UnsupportedOperation),
+    }
+}
+
+// fold_constants evaluates every subexpression that references no variables, so that work is
+// done once rather than per evaluation. Type errors in constant subexpressions surface here.
+pub fn fold_constants(ast: ASTNode) -> Result<ASTNode, ExpressionError> {
+    let folded = match ast {
+        ASTNode::Add(l, r) => ASTNode::Add(fold_box(*l)?, fold_box(*r)?),
+        ASTNode::Sub(l, r) => ASTNode::Sub(fold_box(*l)?, fold_box(*r)?),
+        ASTNode::Mul(l, r) => ASTNode::Mul(fold_box(*l)?, fold_box(*r)?),
+        ASTNode::Eq(l, r) => ASTNode::Eq(fold_box(*l)?, fold_box(*r)?),
+        ASTNode::NotEq(l, r) => ASTNode::NotEq(fold_box(*l)?, fold_box(*r)?),
+        ASTNode::Lt(l, r) => ASTNode::Lt(fold_box(*l)?, fold_box(*r)?),
+        ASTNode::Le(l, r) => ASTNode::Le(fold_box(*l)?, fold_box(*r)?),
+        ASTNode::Gt(l, r) => ASTNode::Gt(fold_box(*l)?, fold_box(*r)?),
+        ASTNode::Ge(l, r) => ASTNode::Ge(fold_box(*l)?, fold_box(*r)?),
+        ASTNode::And(l, r) => ASTNode::And(fold_box(*l)?, fold_box(*r)?),
+        ASTNode::Or(l, r) => ASTNode::Or(fold_box(*l)?, fold_box(*r)?),
+        ASTNode::Not(c) => ASTNode::Not(fold_box(*c)?),
+        leaf => return Ok(leaf),
+    };
+    if !variables(&folded).is_empty() {
+        return Ok(folded);
+    }
+    match evaluate(&folded, &|_: &str| None)? {
+        Value::Num(n) => Ok(ASTNode::Num(n)),
+        Value::Str(s) => Ok(ASTNode::Str(s)),
+        Value::Bool(b) => Ok(ASTNode::Bool(b)),
+    }
+}
+
+fn fold_box(ast: ASTNode) -> Result<Box<ASTNode>, ExpressionError> {
+    fold_constants(ast).map(Box::new)
+}
+
+// variables lists the distinct variable names ast refers to, in order of first appearance.
+pub fn variables(ast: &ASTNode) -> Vec<String> {
+    fn walk(ast: &ASTNode, out: &mut Vec<String>) {
+        match ast {
+            ASTNode::Var(name) => {
+                if !out.contains(name) {
+                    out.push(name.clone());
+                }
             }
-        }
-        ASTNode::Not(child) => {
-            let val = eval(*child, environment)?;
-            match val {
-                Value::Bool(n) => Ok(Value::Bool(!n)),
-                _ => Err(ExpressionError::UnsupportedOperation),
+            ASTNode::Add(l, r)
+            | ASTNode::Sub(l, r)
+            | ASTNode::Mul(l, r)
+            | ASTNode::Eq(l, r)
+            | ASTNode::NotEq(l, r)
+            | ASTNode::Lt(l, r)
+            | ASTNode::Le(l, r)
+            | ASTNode::Gt(l, r)
+            | ASTNode::Ge(l, r)
+            | ASTNode::And(l, r)
+            | ASTNode::Or(l, r) => {
+                walk(l, out);
+                walk(r, out);
             }
+            ASTNode::Not(c) => walk(c, out),
+            ASTNode::Num(_) | ASTNode::Str(_) | ASTNode::Bool(_) => {}
         }
     }
+    let mut out = vec![];
+    walk(ast, &mut out);
+    out
 }
 
 pub fn lex(input: &str) -> Result<Vec<Token>, ExpressionError> {
-    let mut tokens = Vec::new();
-    let mut chars = input.chars().peekable();
+    Ok(lex_spanned(input)?.into_iter().map(|t| t.token).collect())
+}
 
-    while let Some(&c) = chars.peek() {
+// lex_spanned lexes input, recording the span of every token.
+pub fn lex_spanned(input: &str) -> Result<Vec<Spanned>, ExpressionError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    let mut push = |token: Token, start: usize, end: usize| {
+        tokens.push(Spanned {
+            token,
+            span: Span::new(start, end),
+        })
+    };
+
+    while let Some(&(start, c)) = chars.peek() {
         match c {
             '+' => {
-                tokens.push(Token::Plus);
+                push(Token::Plus, start, start + 1);
                 chars.next();
             }
             '-' => {
-                tokens.push(Token::Minus);
+                push(Token::Minus, start, start + 1);
                 chars.next();
             }
             '*' => {
-                tokens.push(Token::Multiply);
+                push(Token::Multiply, start, start + 1);
                 chars.next();
             }
             '=' => {
                 chars.next();
-                if let Some(&'=') = chars.peek() {
+                if let Some(&(_, '=')) = chars.peek() {
                     chars.next();
-                    tokens.push(Token::Equals);
+                    push(Token::Equals, start, start + 2);
                 } else {
-                    return Err(ExpressionError::UnexpectedToken(Token::Var(c.to_string())));
+                    return Err(UnexpectedCharacter(c, Span::new(start, start + 1)));
                 }
             }
             '!' => {
                 chars.next();
-                if let Some(&'=') = chars.peek() {
+                if let Some(&(_, '=')) = chars.peek() {
                     chars.next();
-                    tokens.push(Token::NotEquals);
+                    push(Token::NotEquals, start, start + 2);
                 } else {
-                    tokens.push(Token::Not);
+                    push(Token::Not, start, start + 1);
+                }
+            }
+            '<' | '>' => {
+                chars.next();
+                let equals = matches!(chars.peek(), Some(&(_, '=')));
+                if equals {
+                    chars.next();
+                }
+                let token = match (c, equals) {
+                    ('<', false) => Token::Less,
+                    ('<', true) => Token::LessEquals,
+                    (_, false) => Token::Greater,
+                    (_, true) => Token::GreaterEquals,
+                };
+                push(token, start, start + if equals { 2 } else { 1 });
+            }
+            '&' | '|' => {
+                chars.next();
+                match chars.peek() {
+                    Some(&(_, d)) if d == c => {
+                        chars.next();
+                        let token = if c == '&' { Token::And } else { Token::Or };
+                        push(token, start, start + 2);
+                    }
+                    _ => return Err(UnexpectedCharacter(c, Span::new(start, start + 1))),
                 }
             }
             '(' => {
-                tokens.push(Token::ParenOpen);
+                push(Token::ParenOpen, start, start + 1);
                 chars.next();
             }
             ')' => {
-                tokens.push(Token::ParenClose);
+                push(Token::ParenClose, start, start + 1);
+                chars.next();
+            }
+            ',' => {
+                push(Token::Comma, start, start + 1);
                 chars.next();
             }
-            ' ' => {
+            ' ' | '\t' | '\n' | '\r' => {
                 chars.next();
-            } // Ignore spaces
+            } // Ignore whitespace
             '0'..='9' => {
                 let mut num = String::new();
-                while let Some('0'..='9') = chars.peek() {
-                    num.push(chars.next().unwrap());
+                let mut end = start;
+                while let Some(&(i, d @ '0'..='9')) = chars.peek() {
+                    num.push(d);
+                    end = i + 1;
+                    chars.next();
+                }
+                match i32::from_str(&num) {
+                    Ok(n) => push(Token::Num(n), start, end),
+                    Err(_) => return Err(NumberTooLarge(num, Span::new(start, end))),
                 }
-                let num = i32::from_str(&num).unwrap(); // This unwrap can be replaced by better error handling
-                tokens.push(Token::Num(num));
             }
             '\"' => {
                 let mut str = String::new();
                 chars.next(); // Skip the initial quote
-                while let Some(&c) = chars.peek() {
-                    if c == '\"' {
-                        chars.next();
-                        break;
-                    } else {
-                        str.push(chars.next().unwrap());
+                let end = loop {
+                    match chars.next() {
+                        Some((i, '\"')) => break i + 1,
+                        Some((i, '\\')) => match chars.next() {
+                            Some((_, '\"')) => str.push('\"'),
+                            Some((_, '\\')) => str.push('\\'),
+                            Some((_, 'n')) => str.push('\n'),
+                            Some((j, e)) => {
+                                return Err(InvalidEscape(e, Span::new(i, j + e.len_utf8())))
+                            }
+                            None => return Err(UnterminatedString(Span::new(start, input.len()))),
+                        },
+                        Some((_, c)) => str.push(c),
+                        None => return Err(UnterminatedString(Span::new(start, input.len()))),
                     }
-                }
-                tokens.push(Token::Str(str));
+                };
+                push(Token::Str(str), start, end);
             }
             _ => {
                 if c.is_alphabetic() {
                     let mut var = String::new();
-                    while let Some(c) = chars.peek() {
+                    let mut end = start;
+                    while let Some(&(i, c)) = chars.peek() {
                         if !c.is_alphanumeric() {
                             break;
                         }
-                        var.push(chars.next().unwrap());
+                        var.push(c);
+                        end = i + c.len_utf8();
+                        chars.next();
                     }
-                    tokens.push(Token::Var(var));
+                    push(Token::Var(var), start, end);
                 } else {
-                    return Err(ExpressionError::UnexpectedToken(Token::Var(c.to_string())));
+                    return Err(UnexpectedCharacter(
+                        c,
+                        Span::new(start, start + c.len_utf8()),
+                    ));
                 }
             }
         }
@@ -345,9 +696,17 @@ pub fn lex(input: &str) -> Result<Vec<Token>, ExpressionError> {
 }
 
 pub fn lex_parse(input: String) -> Result<ASTNode, ExpressionError> {
-    let tokens = lex(&input)?;
-    let mut parser = Parser { tokens, pos: 0 };
-    parser.parse(0)
+    let end = Span::new(input.len(), input.len());
+    parse_spanned(lex_spanned(&input)?, end)
+}
+
+// parse_spanned parses a complete expression out of lexed tokens; end is where the source
+// stops. Leftover tokens are an error.
+pub fn parse_spanned(tokens: Vec<Spanned>, end: Span) -> Result<ASTNode, ExpressionError> {
+    let mut parser = Parser::new(tokens, end);
+    let ast = parser.parse(0)?;
+    parser.finish()?;
+    Ok(ast)
 }
 
 #[cfg(test)]
@@ -357,29 +716,29 @@ mod tests {
     #[test]
     fn test_eval_str_eq() {
         let ast = lex_parse("\"hello\" == \"hello\"".to_string()).unwrap();
-        let mut environment = HashMap::<String, Value>::new();
-        assert_eq!(eval(ast, &mut environment).unwrap(), Value::Bool(true));
+        let environment = HashMap::<String, Value>::new();
+        assert_eq!(eval(ast, &environment).unwrap(), Value::Bool(true));
     }
 
     #[test]
     fn test_eval_str_eq_not() {
         let ast = lex_parse("\"hello\" == \"work\"".to_string()).unwrap();
-        let mut environment = HashMap::<String, Value>::new();
-        assert_eq!(eval(ast, &mut environment).unwrap(), Value::Bool(false));
+        let environment = HashMap::<String, Value>::new();
+        assert_eq!(eval(ast, &environment).unwrap(), Value::Bool(false));
     }
 
     #[test]
     fn test_eval_str_ne() {
         let ast = lex_parse("\"hello\" != \"world\"".to_string()).unwrap();
-        let mut environment = HashMap::<String, Value>::new();
-        assert_eq!(eval(ast, &mut environment).unwrap(), Value::Bool(true));
+        let environment = HashMap::<String, Value>::new();
+        assert_eq!(eval(ast, &environment).unwrap(), Value::Bool(true));
     }
 
     #[test]
     fn test_eval_parens() {
         let ast = lex_parse("(1 + 2) * 3 == ((9))".to_string()).unwrap();
-        let mut environment = HashMap::<String, Value>::new();
-        assert_eq!(eval(ast, &mut environment).unwrap(), Value::Bool(true));
+        let environment = HashMap::<String, Value>::new();
+        assert_eq!(eval(ast, &environment).unwrap(), Value::Bool(true));
     }
 
     #[test]
@@ -416,18 +775,14 @@ mod tests {
         );
     }
 
+    fn parser(source: &str) -> Parser {
+        let end = Span::new(source.len(), source.len());
+        Parser::new(lex_spanned(source).unwrap(), end)
+    }
+
     #[test]
     fn test_parse_math() {
-        let mut parser = Parser {
-            tokens: vec![
-                Token::Num(1),
-                Token::Plus,
-                Token::Num(2),
-                Token::Multiply,
-                Token::Num(3),
-            ],
-            pos: 0,
-        };
+        let mut parser = parser("1 + 2 * 3");
 
         let ast = parser.parse(0).unwrap();
         assert_eq!(
@@ -438,10 +793,7 @@ mod tests {
 
     #[test]
     fn test_parse_minus() {
-        let mut parser = Parser {
-            tokens: lex("110 - 100").unwrap(),
-            pos: 0,
-        };
+        let mut parser = parser("110 - 100");
 
         let ast = parser.parse(0).unwrap();
         assert_eq!(
@@ -452,14 +804,7 @@ mod tests {
 
     #[test]
     fn test_parse_string_eq() {
-        let mut parser = Parser {
-            tokens: vec![
-                Token::Str("hello".to_string()),
-                Token::Equals,
-                Token::Str("world".to_string()),
-            ],
-            pos: 0,
-        };
+        let mut parser = parser("\"hello\" == \"world\"");
         let ast = parser.parse(0).unwrap();
         assert_eq!(
             eval(ast, &HashMap::<String, Value>::new()).unwrap(),
@@ -469,16 +814,7 @@ mod tests {
 
     #[test]
     fn test_parse_string_neq() {
-        let mut parser = Parser {
-            tokens: vec![
-                Token::ParenOpen,
-                Token::Num(1),
-                Token::Minus,
-                Token::Num(2),
-                Token::ParenClose,
-            ],
-            pos: 0,
-        };
+        let mut parser = parser("(1 - 2)");
         let ast = parser.parse(0).unwrap();
         assert_eq!(
             eval(ast, &HashMap::<String, Value>::new()).unwrap(),
@@ -486,6 +822,17 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_eval_overflow() {
+        let environment = HashMap::<String, Value>::new();
+        for input in ["2147483647 + 1", "0 - 2147483647 - 2", "65536 * 65536"] {
+            let ast = lex_parse(input.to_string()).unwrap();
+            assert_eq!(eval(ast, &environment), Err(Overflow), "{input}");
+        }
+        let ast = lex_parse("2147483647 + 1 == 0".to_string()).unwrap();
+        assert_eq!(fold_constants(ast), Err(Overflow));
+    }
+
     #[test]
     fn test_eval_with_environment() {
         let mut environment = HashMap::new();
@@ -506,4 +853,117 @@ mod tests {
         let ast_sub = ASTNode::Sub(Box::new(ast_y), Box::new(ast_x));
         assert_eq!(eval(ast_sub, &environment).unwrap(), Value::Num(5));
     }
+
+    #[test]
+    fn test_trailing_tokens() {
+        assert_eq!(
+            lex_parse("1 2".to_string()).err(),
+            Some(UnexpectedToken {
+                found: Token::Num(2),
+                span: Span::new(2, 3),
+                expected: vec!["an operator", "end of input"],
+            })
+        );
+        assert_eq!(
+            lex("a, b").unwrap(),
+            vec![
+                Token::Var("a".to_string()),
+                Token::Comma,
+                Token::Var("b".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fold_constants() {
+        let ast = fold_constants(lex_parse("x == (1 + 2) * 3".to_string()).unwrap()).unwrap();
+        assert_eq!(
+            ast,
+            ASTNode::Eq(
+                Box::new(ASTNode::Var("x".to_string())),
+                Box::new(ASTNode::Num(9))
+            )
+        );
+        assert_eq!(variables(&ast), vec!["x".to_string()]);
+        assert_eq!(
+            fold_constants(lex_parse("x == \"a\" + 1".to_string()).unwrap()).err(),
+            Some(UnsupportedOperation)
+        );
+    }
+
+    #[test]
+    fn test_comparisons_and_logic() {
+        let environment = HashMap::from([
+            ("n".to_string(), Value::Num(5)),
+            ("s".to_string(), Value::Str("m".to_string())),
+        ]);
+        let check = |src: &str| eval(lex_parse(src.to_string()).unwrap(), &environment);
+        assert_eq!(check("n > 4 && n <= 5"), Ok(Value::Bool(true)));
+        assert_eq!(check("n < 5 || s >= \"n\""), Ok(Value::Bool(false)));
+        assert_eq!(check("n + 1 > 5 == 1 < 2"), Ok(Value::Bool(true)));
+        // && binds tighter than ||.
+        assert_eq!(check("n == 5 || n == 1 && n == 2"), Ok(Value::Bool(true)));
+        // The right side is never evaluated once the left has decided.
+        assert_eq!(check("n == 5 || missing == 1"), Ok(Value::Bool(true)));
+        assert_eq!(check("n < s"), Err(UnsupportedOperation));
+        assert_eq!(
+            lex("a & b").err(),
+            Some(UnexpectedCharacter('&', Span::new(2, 3)))
+        );
+    }
+
+    #[test]
+    fn test_lex_errors() {
+        assert_eq!(
+            lex("x == \"open").err(),
+            Some(UnterminatedString(Span::new(5, 10)))
+        );
+        assert_eq!(
+            lex("x == 99999999999").err(),
+            Some(NumberTooLarge("99999999999".to_string(), Span::new(5, 16)))
+        );
+        assert_eq!(
+            lex("\"a\\qb\"").err(),
+            Some(InvalidEscape('q', Span::new(2, 4)))
+        );
+        assert_eq!(
+            lex(r#""say \"hi\"\\\n""#).unwrap(),
+            vec![Token::Str("say \"hi\"\\\n".to_string())]
+        );
+        assert_eq!(
+            lex("x = 1").err(),
+            Some(UnexpectedCharacter('=', Span::new(2, 3)))
+        );
+    }
+
+    #[test]
+    fn test_parse_error_spans() {
+        let source = "x == 1 +\n  (y == )";
+        let err = lex_parse(source.to_string()).unwrap_err();
+        assert_eq!(err.span(), Some(Span::new(17, 18)));
+        assert_eq!(
+            err.render(source),
+            "unexpected token `)` at line 2, column 9\n  (y == )\n        ^\n\
+             expected one of: number, string, identifier, (, -, !"
+        );
+
+        let err = lex_parse("(x == 1".to_string()).unwrap_err();
+        assert_eq!(
+            err,
+            DidntGetRightParen {
+                open: Span::new(0, 1),
+                span: Span::new(7, 7)
+            }
+        );
+        assert_eq!(err.expected(), vec![")"]);
+
+        let err = lex_parse("x ==".to_string()).unwrap_err();
+        assert_eq!(err.span(), Some(Span::new(4, 4)));
+
+        // spans are byte offsets, so they land on the token past wide characters too.
+        let source = "\"héllo\" == )";
+        let err = lex_parse(source.to_string()).unwrap_err();
+        assert_eq!(err.span(), Some(Span::new(12, 13)));
+        assert_eq!(err.span().unwrap().line_column(source), (1, 12));
+    }
 }
@@ -1,8 +1,22 @@
 use crate::chain::Blockchain;
+use crate::encryption::{is_encrypted, Keyring};
+use crate::hexdisplay::{hex, unhex};
+use crate::index::{Indexes, Location};
 use crate::pratt;
-use crate::record::Record;
-use std::collections::HashMap;
+use crate::pratt::Token;
+use crate::record::{KVRecord, Record};
+use crate::types::Hashtype;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::ops::Bound;
 use std::str::FromStr;
+
+/// A query without an explicit limit returns at most DEFAULT_LIMIT rows per page.
+pub const DEFAULT_LIMIT: usize = 1000;
+
 /** Let us say that we impose a structure where a given queryable record has a list of keys and values **/
 pub enum QueryableError {}
 
@@ -34,57 +48,1142 @@ where
     }
 }
 
-// The Query struct and implementation handles a side-effect free query language
+// value_of reads a raw entry value the way the query language sees it: numbers, then booleans,
+// then plain strings.
+pub fn value_of(v: &str) -> pratt::Value {
+    if let Ok(i) = i32::from_str(v) {
+        return pratt::Value::Num(i);
+    }
+    if let Ok(b) = bool::from_str(v) {
+        return pratt::Value::Bool(b);
+    }
+    pratt::Value::Str(v.to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum QueryErrorKind {
+    // The query text could not be parsed or compiled.
+    Syntax,
+    // The cursor passed alongside the query is not one the server handed out.
+    Cursor,
+    // The query compiled, but could not be evaluated over the chain.
+    Evaluation,
+    // The query's arithmetic overflowed for some record.
+    Overflow,
+}
+
+/// QueryError is what a caller gets back for a query that cannot be answered.
+/// Syntax errors say where in the query text they were found when that is known.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueryError {
+    pub kind: QueryErrorKind,
+    pub message: String,
+    // 1-based line and column of the error in the query text.
+    #[serde(default)]
+    pub line: Option<usize>,
+    #[serde(default)]
+    pub column: Option<usize>,
+    // The offending line of the query, with a caret line under the error.
+    #[serde(default)]
+    pub excerpt: Option<String>,
+    // What would have been accepted where the error was found.
+    #[serde(default)]
+    pub expected: Vec<String>,
+}
+
+impl QueryError {
+    fn new(kind: QueryErrorKind, message: String) -> QueryError {
+        QueryError {
+            kind,
+            message,
+            line: None,
+            column: None,
+            excerpt: None,
+            expected: vec![],
+        }
+    }
+
+    fn syntax(e: &pratt::ExpressionError, source: &str) -> QueryError {
+        let mut err = QueryError::new(QueryErrorKind::Syntax, e.to_string());
+        if let Some(span) = e.span() {
+            let (line, column) = span.line_column(source);
+            err.line = Some(line);
+            err.column = Some(column);
+            err.excerpt = Some(span.excerpt(source));
+        }
+        err.expected = e.expected().iter().map(|s| s.to_string()).collect();
+        err
+    }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} error: {}", self.kind, self.message)?;
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            write!(f, " at line {line}, column {column}")?;
+        }
+        if let Some(excerpt) = &self.excerpt {
+            write!(f, "\n{excerpt}")?;
+        }
+        if !self.expected.is_empty() {
+            write!(f, "\nexpected one of: {}", self.expected.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Plan is a query predicate compiled once: parsed, constant-folded, and ready to be evaluated
+/// against any number of records without going back to the query text.
+#[derive(Debug, Clone)]
+pub struct Plan {
+    predicate: Option<pratt::ASTNode>,
+}
+
+impl Plan {
+    pub fn compile(predicate: Option<pratt::ASTNode>) -> Result<Plan, String> {
+        let predicate = match predicate {
+            Some(ast) => pratt::fold_constants(ast).map_err(|e| e.to_string())?,
+            None => return Ok(Plan { predicate: None }),
+        };
+        if let pratt::ASTNode::Num(_) | pratt::ASTNode::Str(_) = predicate {
+            return Err("query predicate is not a boolean".to_string());
+        }
+        Ok(Plan {
+            predicate: Some(predicate),
+        })
+    }
+
+    // matches evaluates the predicate against one record's fields. Fields are converted only
+    // when the predicate reads them. A plan without a predicate matches everything.
+    pub fn matches(
+        &self,
+        fields: &HashMap<String, String>,
+    ) -> Result<bool, pratt::ExpressionError> {
+        let ast = match &self.predicate {
+            Some(ast) => ast,
+            None => return Ok(true),
+        };
+        let lookup = |name: &str| fields.get(name).map(|v| value_of(v));
+        match pratt::evaluate(ast, &lookup)? {
+            pratt::Value::Bool(b) => Ok(b),
+            _ => Err(pratt::ExpressionError::UnsupportedOperation),
+        }
+    }
+
+    // variables names the fields the predicate reads.
+    pub fn variables(&self) -> Vec<String> {
+        self.predicate
+            .as_ref()
+            .map(pratt::variables)
+            .unwrap_or_default()
+    }
+
+    // lookup finds the index lookups that narrow the predicate down, if any. The lookup may
+    // return more records than match, never fewer; the predicate still decides each one.
+    pub fn lookup(&self, indexes: &Indexes) -> Option<Lookup> {
+        self.predicate
+            .as_ref()
+            .and_then(|ast| Lookup::of(ast, indexes))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    // flip is the comparison with its operands swapped: `5 < x` is `x > 5`.
+    fn flip(self) -> Comparison {
+        match self {
+            Comparison::Eq => Comparison::Eq,
+            Comparison::Lt => Comparison::Gt,
+            Comparison::Le => Comparison::Ge,
+            Comparison::Gt => Comparison::Lt,
+            Comparison::Ge => Comparison::Le,
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Comparison::Eq => write!(f, "=="),
+            Comparison::Lt => write!(f, "<"),
+            Comparison::Le => write!(f, "<="),
+            Comparison::Gt => write!(f, ">"),
+            Comparison::Ge => write!(f, ">="),
+        }
+    }
+}
+
+/// Lookup is the part of a predicate that secondary indexes can answer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lookup {
+    Compare {
+        field: String,
+        op: Comparison,
+        value: pratt::Value,
+    },
+    And(Box<Lookup>, Box<Lookup>),
+    Or(Box<Lookup>, Box<Lookup>),
+}
+
+impl Lookup {
+    // of reads a lookup out of a predicate. A conjunction needs only one side indexed, since
+    // the other side is checked against the candidates anyway; a disjunction needs both.
+    fn of(ast: &pratt::ASTNode, indexes: &Indexes) -> Option<Lookup> {
+        use pratt::ASTNode;
+        let (l, r, op) = match ast {
+            ASTNode::And(l, r) => {
+                return match (Lookup::of(l, indexes), Lookup::of(r, indexes)) {
+                    (Some(l), Some(r)) => Some(Lookup::And(Box::new(l), Box::new(r))),
+                    (l, r) => l.or(r),
+                }
+            }
+            ASTNode::Or(l, r) => {
+                return Some(Lookup::Or(
+                    Box::new(Lookup::of(l, indexes)?),
+                    Box::new(Lookup::of(r, indexes)?),
+                ))
+            }
+            ASTNode::Eq(l, r) => (l, r, Comparison::Eq),
+            ASTNode::Lt(l, r) => (l, r, Comparison::Lt),
+            ASTNode::Le(l, r) => (l, r, Comparison::Le),
+            ASTNode::Gt(l, r) => (l, r, Comparison::Gt),
+            ASTNode::Ge(l, r) => (l, r, Comparison::Ge),
+            _ => return None,
+        };
+        let constant = |ast: &ASTNode| match ast {
+            ASTNode::Num(n) => Some(pratt::Value::Num(*n)),
+            ASTNode::Str(s) => Some(pratt::Value::Str(s.clone())),
+            ASTNode::Bool(b) => Some(pratt::Value::Bool(*b)),
+            _ => None,
+        };
+        let (field, op, value) = match (l.as_ref(), r.as_ref()) {
+            (ASTNode::Var(field), other) => (field, op, constant(other)?),
+            (other, ASTNode::Var(field)) => (field, op.flip(), constant(other)?),
+            _ => return None,
+        };
+        if !indexes.contains(field) {
+            return None;
+        }
+        Some(Lookup::Compare {
+            field: field.clone(),
+            op,
+            value,
+        })
+    }
+
+    // locations runs the lookup, giving candidate records in chain order.
+    pub fn locations(&self, indexes: &Indexes) -> BTreeSet<Location> {
+        match self {
+            Lookup::Compare { field, op, value } => {
+                let index = match indexes.get(field) {
+                    Some(index) => index,
+                    None => return BTreeSet::new(),
+                };
+                match op {
+                    Comparison::Eq => index.get(value),
+                    Comparison::Lt => index.range(Bound::Unbounded, Bound::Excluded(value)),
+                    Comparison::Le => index.range(Bound::Unbounded, Bound::Included(value)),
+                    Comparison::Gt => index.range(Bound::Excluded(value), Bound::Unbounded),
+                    Comparison::Ge => index.range(Bound::Included(value), Bound::Unbounded),
+                }
+            }
+            Lookup::And(l, r) => &l.locations(indexes) & &r.locations(indexes),
+            Lookup::Or(l, r) => &l.locations(indexes) | &r.locations(indexes),
+        }
+    }
+
+    // fields names the indexes the lookup reads.
+    pub fn fields(&self) -> Vec<String> {
+        match self {
+            Lookup::Compare { field, .. } => vec![field.clone()],
+            Lookup::And(l, r) | Lookup::Or(l, r) => {
+                let mut fields = l.fields();
+                for field in r.fields() {
+                    if !fields.contains(&field) {
+                        fields.push(field);
+                    }
+                }
+                fields
+            }
+        }
+    }
+}
+
+impl fmt::Display for Lookup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Lookup::Compare {
+                field,
+                op,
+                value: pratt::Value::Str(s),
+            } => write!(f, "{field} {op} {s:?}"),
+            Lookup::Compare { field, op, value } => write!(f, "{field} {op} {value}"),
+            Lookup::And(l, r) => write!(f, "({l}) && ({r})"),
+            Lookup::Or(l, r) => write!(f, "({l}) || ({r})"),
+        }
+    }
+}
+
+/// Explain describes how a query would be run over a chain, without running it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Explain {
+    // access is `full scan`, or the index lookup that finds the candidate records.
+    pub access: String,
+    pub index_used: bool,
+    // indexes names the indexed fields the lookup reads.
+    pub indexes: Vec<String>,
+    // candidates is how many records the predicate would be evaluated against.
+    pub candidates: u64,
+    // fields names the entry fields the predicate reads.
+    pub fields: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderBy {
+    pub field: String,
+    pub descending: bool,
+}
+
+/// ProjectedRow is a record cut down to the fields named in a select list.
+/// The source record hash is always kept so the row can be traced back to the chain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProjectedRow {
+    pub hash: Hashtype,
+    pub fields: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggregateFn {
+    Count,
+    Sum,
+    Min,
+    Max,
+}
+
+/// Aggregate is one aggregate function in a select list. A field of None is `count(*)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Aggregate {
+    pub function: AggregateFn,
+    pub field: Option<String>,
+}
+
+impl Aggregate {
+    // name is the column the aggregate is reported under, e.g. `sum(amount)`.
+    pub fn name(&self) -> String {
+        let function = match self.function {
+            AggregateFn::Count => "count",
+            AggregateFn::Sum => "sum",
+            AggregateFn::Min => "min",
+            AggregateFn::Max => "max",
+        };
+        format!("{function}({})", self.field.as_deref().unwrap_or("*"))
+    }
+}
+
+/// GroupRow is one group of an aggregation query: the values of the group by fields, and the
+/// aggregates computed over the records in the group, keyed by aggregate name.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupRow {
+    pub group: BTreeMap<String, String>,
+    pub aggregates: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum QueryRows {
+    Records(Vec<Record>),
+    Projected(Vec<ProjectedRow>),
+    Groups(Vec<GroupRow>),
+}
+
+/// QueryResult is one page of query output. When next_cursor is set, passing it back with the
+/// same query returns the following page.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueryResult {
+    pub rows: QueryRows,
+    pub next_cursor: Option<String>,
+}
+
+// The Query struct and implementation handles a side-effect free query language.
+//
+// The full form of a query is
+//
+//   select a, b where <predicate> order by a desc limit 10 offset 20
+//
+// where every clause is optional. A bare predicate (`x == "bar"`) is still a complete query.
+//
+// Aggregation queries put count, sum, min or max in the select list and may group:
+//
+//   select status, count(*), sum(amount) where amount != 0 group by status
+//
+// Plain fields selected alongside aggregates must be listed in the group by clause.
+#[derive(Debug, Clone)]
 pub struct Query {
-    input: String,
+    select: Vec<String>,
+    aggregates: Vec<Aggregate>,
+    group_by: Vec<String>,
+    plan: Plan,
+    order_by: Option<OrderBy>,
+    limit: Option<usize>,
+    offset: usize,
+    // keys decrypt encrypted fields, so the query sees them as their writer did.
+    keys: Option<Keyring>,
 }
 
 impl Query {
-    // New query or failure. Does not eval query; simply lexes/parses.
-    pub fn new(input: String) -> Result<Query, String> {
-        pratt::lex_parse(input.clone()).map_err(|e| e.to_string())?;
-        Ok(Query { input })
+    // New query or failure. Does not eval query; lexes, parses and compiles it.
+    pub fn new(input: String) -> Result<Query, QueryError> {
+        Query::compile(&input).map_err(|e| match e {
+            CompileError::Located(e) => QueryError::syntax(&e, &input),
+            CompileError::Invalid(message) => QueryError::new(QueryErrorKind::Syntax, message),
+        })
+    }
+
+    fn compile(input: &str) -> Result<Query, CompileError> {
+        let tokens = pratt::lex_spanned(input)?;
+        let end = pratt::Span::new(input.len(), input.len());
+        let mut clauses = Clauses {
+            tokens,
+            end,
+            pos: 0,
+        };
+        let mut query = Query {
+            select: vec![],
+            aggregates: vec![],
+            group_by: vec![],
+            plan: Plan { predicate: None },
+            order_by: None,
+            limit: None,
+            offset: 0,
+            keys: None,
+        };
+
+        // after is how many of CLAUSES have been passed, and extra what else may come next,
+        // so that leftovers are reported against only what could have followed them.
+        let mut after = 0;
+        let mut extra = vec![];
+        let mut predicate = None;
+        if clauses.keyword("select") {
+            (query.select, query.aggregates) = clauses.select_list()?;
+            if clauses.keyword("where") {
+                predicate = Some(clauses.predicate()?);
+            } else {
+                extra = vec!["where"];
+            }
+        } else {
+            clauses.keyword("where");
+            predicate = Some(clauses.predicate()?);
+        }
+        query.plan = Plan::compile(predicate).map_err(CompileError::Invalid)?;
+
+        if clauses.keyword_pair("group", "by") {
+            query.group_by = clauses.field_list()?;
+            (after, extra) = (1, vec![]);
+        }
+        if clauses.keyword_pair("order", "by") {
+            let field = clauses.field()?;
+            (after, extra) = (2, vec![]);
+            let descending = if clauses.keyword("desc") {
+                true
+            } else if clauses.keyword("asc") {
+                false
+            } else {
+                extra = vec!["asc", "desc"];
+                false
+            };
+            query.order_by = Some(OrderBy { field, descending });
+        }
+        if clauses.keyword("limit") {
+            query.limit = Some(clauses.count()?);
+            (after, extra) = (3, vec![]);
+        }
+        if clauses.keyword("offset") {
+            query.offset = clauses.count()?;
+            (after, extra) = (4, vec![]);
+        }
+        clauses.finish([extra.as_slice(), &CLAUSES[after..], &["end of query"]].concat())?;
+
+        if query.is_aggregate() {
+            if let Some(f) = query.select.iter().find(|f| !query.group_by.contains(f)) {
+                return Err(CompileError::Invalid(format!(
+                    "selected field {f} must appear in group by"
+                )));
+            }
+            if let Some(order) = &query.order_by {
+                if !query.group_by.contains(&order.field) {
+                    return Err(CompileError::Invalid(format!(
+                        "aggregation can only be ordered by a group by field, not {}",
+                        order.field
+                    )));
+                }
+            }
+        }
+        Ok(query)
+    }
+
+    // with_keys has the query decrypt the encrypted fields keys can, before evaluating them.
+    // Indexes hold encrypted values as they are, so a query with keys scans the chain instead.
+    pub fn with_keys(mut self, keys: Option<Keyring>) -> Query {
+        self.keys = keys;
+        self
+    }
+
+    // plain is r with its entry decrypted, if it was encrypted whole and we hold keys, since
+    // it cannot be parsed into fields until it is.
+    fn plain<'r>(&self, r: &'r Record) -> Cow<'r, Record> {
+        if self.keys.is_some() && is_encrypted(&r.entry) {
+            Cow::Owned(self.decrypt(r))
+        } else {
+            Cow::Borrowed(r)
+        }
+    }
+
+    // decrypted is the fields of kv, with what encrypted values our keys decrypt decrypted.
+    fn decrypted<'k>(&self, kv: &'k KVRecord) -> Cow<'k, HashMap<String, String>> {
+        match &self.keys {
+            Some(keys) => {
+                let mut fields = kv.pairs();
+                keys.decrypt_fields(fields.iter_mut());
+                Cow::Owned(fields)
+            }
+            None => Cow::Borrowed(kv.fields()),
+        }
+    }
+
+    pub fn plan(&self) -> &Plan {
+        &self.plan
+    }
+
+    pub fn select(&self) -> &Vec<String> {
+        &self.select
     }
 
-    // The parse function reads the input and returns a closure that will take a HashMap, execute the
-    // logic in the input, and return true or false.
+    pub fn aggregates(&self) -> &Vec<Aggregate> {
+        &self.aggregates
+    }
+
+    pub fn group_by(&self) -> &Vec<String> {
+        &self.group_by
+    }
+
+    // is_aggregate is true when the query returns groups rather than records.
+    pub fn is_aggregate(&self) -> bool {
+        !self.aggregates.is_empty() || !self.group_by.is_empty()
+    }
+
+    pub fn order_by(&self) -> Option<&OrderBy> {
+        self.order_by.as_ref()
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    // The parse function returns a closure that will take a HashMap, execute the predicate
+    // against it, and return true or false. A query without a predicate matches everything.
     pub fn parse(&self) -> impl Fn(HashMap<String, String>) -> Result<bool, String> {
-        let boxed_text = Box::new(self.input.clone());
+        let plan = self.plan.clone();
         move |env: HashMap<String, String>| -> Result<bool, String> {
-            let text = boxed_text.clone();
-            let mut parser_env = HashMap::<String, pratt::Value>::new();
-
-            for (k, v) in env {
-                if i32::from_str(&v)
-                    .map(|i| parser_env.insert(k.clone(), pratt::Value::Num(i)))
-                    .is_ok()
-                {
-                    break;
+            plan.matches(&env).map_err(|e| e.to_string())
+        }
+    }
+
+    // run evaluates the query over chain and returns one page of results.
+    // cursor, when given, takes precedence over the query's own offset.
+    pub fn run(&self, chain: &Blockchain, cursor: Option<&str>) -> Result<QueryResult, QueryError> {
+        let cursor = match cursor {
+            Some(c) => {
+                Some(Cursor::decode(c).map_err(|e| QueryError::new(QueryErrorKind::Cursor, e))?)
+            }
+            None => None,
+        };
+        let limit = self.limit.unwrap_or(DEFAULT_LIMIT);
+        let descending = self.order_by.as_ref().is_some_and(|o| o.descending);
+
+        if self.is_aggregate() {
+            let groups = self.aggregate(chain)?;
+            let order_field = self.order_by.as_ref().map(|o| o.field.as_str());
+            let mark = |g: &GroupRow| Cursor {
+                key: order_field.and_then(|f| g.group.get(f).map(|v| value_of(v))),
+                row: serde_json::to_string(&g.group).unwrap_or_default(),
+            };
+            let start = match &cursor {
+                Some(c) => resume(&groups, c, &mark, |key| {
+                    key_order(key, &c.key, descending).is_gt()
+                }),
+                None => self.offset,
+            };
+            let (page, next_cursor) = paginate(groups, start, limit, mark);
+            return Ok(QueryResult {
+                rows: QueryRows::Groups(page),
+                next_cursor,
+            });
+        }
+
+        let order_field = self.order_by.as_ref().map(|o| o.field.as_str());
+        let mut matched: Vec<(Option<pratt::Value>, &Record)> = vec![];
+        self.for_each_match(chain, |r, fields| {
+            let key = order_field.and_then(|f| fields.get(f).map(|v| value_of(v)));
+            matched.push((key, r));
+            Ok(())
+        })?;
+        if self.order_by.is_some() {
+            sort_keyed(&mut matched, descending);
+        }
+        let mark = |(key, r): &(Option<pratt::Value>, &Record)| Cursor {
+            key: key.clone(),
+            row: r.hash.to_string(),
+        };
+        let start = match &cursor {
+            Some(c) => resume(&matched, c, &mark, |key| {
+                key_order(key, &c.key, descending).is_gt()
+            }),
+            None => self.offset,
+        };
+        let (page, next_cursor) = paginate(matched, start, limit, mark);
+
+        let rows = if self.select.is_empty() {
+            QueryRows::Records(page.into_iter().map(|(_, r)| self.decrypt(r)).collect())
+        } else {
+            QueryRows::Projected(page.into_iter().map(|(_, r)| self.project(r)).collect())
+        };
+        Ok(QueryResult { rows, next_cursor })
+    }
+
+    // explain says how run would reach the records of chain: through an index, or by
+    // scanning every record.
+    pub fn explain(&self, chain: &Blockchain) -> Explain {
+        let fields = self.plan.variables();
+        match self.plan.lookup(chain.indexes()) {
+            Some(lookup) => Explain {
+                access: format!("index lookup {lookup}"),
+                index_used: true,
+                indexes: lookup.fields(),
+                candidates: lookup.locations(chain.indexes()).len() as u64,
+                fields,
+            },
+            None => Explain {
+                access: "full scan".to_string(),
+                index_used: false,
+                indexes: vec![],
+                candidates: chain.blocks().map(|b| b.data.len() as u64).sum(),
+                fields,
+            },
+        }
+    }
+
+    // for_each_match hands every record matching the plan, with its parsed fields, to f, in
+    // chain order. Records whose entry is not structured, or that the predicate cannot be
+    // evaluated against (say, a field it names is missing), do not match. Arithmetic that
+    // overflows fails the whole query, rather than quietly leaving the record out.
+    // When the chain has indexes the predicate can use, only the records they find are read.
+    fn for_each_match<'a, F>(&self, chain: &'a Blockchain, mut f: F) -> Result<(), QueryError>
+    where
+        F: FnMut(&'a Record, &HashMap<String, String>) -> Result<(), String>,
+    {
+        let mut visit = |r: &'a Record| -> Result<(), QueryError> {
+            if let Ok(kv) = self.plain(r).structured_entry() {
+                let fields = self.decrypted(&kv);
+                match self.plan.matches(&fields) {
+                    Ok(true) => {
+                        f(r, &fields).map_err(|e| QueryError::new(QueryErrorKind::Evaluation, e))?
+                    }
+                    Err(e @ pratt::ExpressionError::Overflow) => {
+                        return Err(QueryError::new(QueryErrorKind::Overflow, e.to_string()))
+                    }
+                    _ => {}
+                }
+            }
+            Ok(())
+        };
+        let lookup = match self.keys {
+            Some(_) => None,
+            None => self.plan.lookup(chain.indexes()),
+        };
+        match lookup {
+            Some(lookup) => {
+                for at in lookup.locations(chain.indexes()) {
+                    if let Some(r) = chain.record_at(at) {
+                        visit(r)?;
+                    }
+                }
+            }
+            None => {
+                for block in chain.blocks() {
+                    for r in block.data.iter() {
+                        visit(r)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // aggregate folds every matching record into its group in a single pass over the chain.
+    // Without a group by clause there is exactly one group, even when nothing matched.
+    fn aggregate(&self, chain: &Blockchain) -> Result<Vec<GroupRow>, QueryError> {
+        let mut groups: BTreeMap<Vec<Option<pratt::Value>>, Group> = BTreeMap::new();
+        if self.group_by.is_empty() {
+            groups.insert(vec![], Group::new(BTreeMap::new(), &self.aggregates));
+        }
+
+        self.for_each_match(chain, |_, fields| {
+            let key = self
+                .group_by
+                .iter()
+                .map(|f| fields.get(f).map(|v| value_of(v)))
+                .collect();
+            let group = groups.entry(key).or_insert_with(|| {
+                let values = self
+                    .group_by
+                    .iter()
+                    .filter_map(|f| fields.get(f).map(|v| (f.clone(), v.clone())))
+                    .collect();
+                Group::new(values, &self.aggregates)
+            });
+            group.add(&self.aggregates, fields)
+        })?;
+
+        let mut rows: Vec<GroupRow> = groups
+            .into_values()
+            .map(|g| g.finish(&self.aggregates))
+            .collect();
+        if let Some(order) = &self.order_by {
+            rows.sort_by(|a, b| {
+                let a = a.group.get(&order.field).map(|v| value_of(v));
+                let b = b.group.get(&order.field).map(|v| value_of(v));
+                key_order(&a, &b, order.descending)
+            });
+        }
+        Ok(rows)
+    }
+
+    // matches is true when r satisfies the query's predicate.
+    pub fn matches(&self, r: &Record) -> bool {
+        match self.plain(r).structured_entry() {
+            Ok(kv) => self.plan.matches(&self.decrypted(&kv)) == Ok(true),
+            Err(_) => false,
+        }
+    }
+
+    // decrypt is r with what encrypted values our keys decrypt decrypted. Its hash is of the
+    // entry as encrypted, so a decrypted record no longer validates.
+    fn decrypt(&self, r: &Record) -> Record {
+        match &self.keys {
+            Some(keys) => Record {
+                entry: keys.decrypt_entry(&r.entry),
+                ..r.clone()
+            },
+            None => r.clone(),
+        }
+    }
+
+    // project cuts r down to the fields of the select list.
+    pub fn project(&self, r: &Record) -> ProjectedRow {
+        let pairs = self
+            .plain(r)
+            .structured_entry()
+            .map(|kv| self.decrypted(&kv).into_owned())
+            .unwrap_or_default();
+        let fields = self
+            .select
+            .iter()
+            .filter_map(|f| pairs.get(f).map(|v| (f.clone(), v.clone())))
+            .collect();
+        ProjectedRow {
+            hash: r.hash.clone(),
+            fields,
+        }
+    }
+}
+
+// Rows lacking the ordering field sort after every row that has it, in either direction.
+// The sort is stable, so ties keep chain order.
+fn sort_keyed(rows: &mut [(Option<pratt::Value>, &Record)], descending: bool) {
+    rows.sort_by(|(a, _), (b, _)| key_order(a, b, descending));
+}
+
+fn key_order(a: &Option<pratt::Value>, b: &Option<pratt::Value>, descending: bool) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) if descending => b.cmp(a),
+        (Some(a), Some(b)) => a.cmp(b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+// Accumulator is the running state of one aggregate within one group.
+enum Accumulator {
+    Count(u64),
+    Sum(i64),
+    // Min and Max keep the raw value alongside its parsed form so the result reads as entered.
+    Min(Option<(pratt::Value, String)>),
+    Max(Option<(pratt::Value, String)>),
+}
+
+struct Group {
+    values: BTreeMap<String, String>,
+    accumulators: Vec<Accumulator>,
+}
+
+impl Group {
+    fn new(values: BTreeMap<String, String>, aggregates: &[Aggregate]) -> Group {
+        let accumulators = aggregates
+            .iter()
+            .map(|a| match a.function {
+                AggregateFn::Count => Accumulator::Count(0),
+                AggregateFn::Sum => Accumulator::Sum(0),
+                AggregateFn::Min => Accumulator::Min(None),
+                AggregateFn::Max => Accumulator::Max(None),
+            })
+            .collect();
+        Group {
+            values,
+            accumulators,
+        }
+    }
+
+    // add folds one record into the group. Records lacking an aggregated field are skipped by
+    // that aggregate; summing a value that is not a number is an error.
+    fn add(
+        &mut self,
+        aggregates: &[Aggregate],
+        pairs: &HashMap<String, String>,
+    ) -> Result<(), String> {
+        for (aggregate, acc) in aggregates.iter().zip(self.accumulators.iter_mut()) {
+            let raw = match &aggregate.field {
+                Some(f) => match pairs.get(f) {
+                    Some(v) => v,
+                    None => continue,
+                },
+                None => "",
+            };
+            match acc {
+                Accumulator::Count(n) => *n += 1,
+                Accumulator::Sum(total) => match value_of(raw) {
+                    pratt::Value::Num(n) => *total += n as i64,
+                    _ => return Err(format!("{}: {raw} is not a number", aggregate.name())),
+                },
+                Accumulator::Min(current) => {
+                    let v = value_of(raw);
+                    if current.as_ref().is_none_or(|(c, _)| v < *c) {
+                        *current = Some((v, raw.to_string()));
+                    }
                 }
-                if bool::from_str(&v)
-                    .map(|b| parser_env.insert(k.clone(), pratt::Value::Bool(b)))
-                    .is_ok()
-                {
-                    break;
+                Accumulator::Max(current) => {
+                    let v = value_of(raw);
+                    if current.as_ref().is_none_or(|(c, _)| v > *c) {
+                        *current = Some((v, raw.to_string()));
+                    }
                 }
-                parser_env.insert(k.clone(), pratt::Value::Str(v));
             }
+        }
+        Ok(())
+    }
+
+    fn finish(self, aggregates: &[Aggregate]) -> GroupRow {
+        let aggregates = aggregates
+            .iter()
+            .zip(self.accumulators)
+            .filter_map(|(aggregate, acc)| {
+                let value = match acc {
+                    Accumulator::Count(n) => Some(n.to_string()),
+                    Accumulator::Sum(total) => Some(total.to_string()),
+                    Accumulator::Min(v) | Accumulator::Max(v) => v.map(|(_, raw)| raw),
+                };
+                value.map(|v| (aggregate.name(), v))
+            })
+            .collect();
+        GroupRow {
+            group: self.values,
+            aggregates,
+        }
+    }
+}
 
-            let ast = pratt::lex_parse(text.to_string()).map_err(|e| e.to_string())?;
-            let result = pratt::eval(ast, &parser_env).map_err(|e| e.to_string())?;
-            match result {
-                pratt::Value::Bool(b) => Ok(b),
-                _ => Err("result is not a boolean".to_string()),
+// Cursor marks the last row of a page: its sort key, and the row itself, as a record's hash or
+// a group's values. The next page begins after that row wherever it now sorts, so records
+// written between pages neither repeat nor push rows off the page. Cursors are opaque to
+// callers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Cursor {
+    key: Option<pratt::Value>,
+    row: String,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        hex(serde_json::to_string(self).unwrap_or_default().as_bytes())
+    }
+
+    fn decode(cursor: &str) -> Result<Cursor, String> {
+        unhex(cursor)
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .ok_or_else(|| format!("invalid cursor: {cursor}"))
+    }
+}
+
+// resume is where the page after cursor begins in items: just past its row, or, should the
+// row be gone, at the first row whose key sorts after the cursor's.
+fn resume<T, M, F>(items: &[T], cursor: &Cursor, mark: &M, follows: F) -> usize
+where
+    M: Fn(&T) -> Cursor,
+    F: Fn(&Option<pratt::Value>) -> bool,
+{
+    match items.iter().position(|i| mark(i).row == cursor.row) {
+        Some(at) => at + 1,
+        None => items
+            .iter()
+            .position(|i| follows(&mark(i).key))
+            .unwrap_or(items.len()),
+    }
+}
+
+// paginate cuts the page beginning at start out of items, and a cursor for the next page if
+// anything is left.
+fn paginate<T, M>(items: Vec<T>, start: usize, limit: usize, mark: M) -> (Vec<T>, Option<String>)
+where
+    M: Fn(&T) -> Cursor,
+{
+    let total = items.len();
+    let page: Vec<T> = items.into_iter().skip(start).take(limit).collect();
+    match page.last() {
+        Some(last) if start + page.len() < total => {
+            let next = mark(last).encode();
+            (page, Some(next))
+        }
+        _ => (page, None),
+    }
+}
+
+// CompileError is a failure to compile a query: either at a known place in the text, or a
+// query that reads fine but asks for something that cannot be done.
+enum CompileError {
+    Located(pratt::ExpressionError),
+    Invalid(String),
+}
+
+impl From<pratt::ExpressionError> for CompileError {
+    fn from(e: pratt::ExpressionError) -> Self {
+        CompileError::Located(e)
+    }
+}
+
+const CLAUSES: [&str; 4] = ["group by", "order by", "limit", "offset"];
+
+// Clauses walks the token stream of a query, splitting it into its clauses.
+// Clause keywords are ordinary identifiers to the lexer; they are only keywords in position.
+struct Clauses {
+    tokens: Vec<pratt::Spanned>,
+    // end is where the query text stops.
+    end: pratt::Span,
+    pos: usize,
+}
+
+impl Clauses {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|t| &t.token)
+    }
+
+    fn span(&self) -> pratt::Span {
+        self.tokens
+            .get(self.pos)
+            .map(|t| t.span)
+            .unwrap_or(self.end)
+    }
+
+    // unexpected reports the next token, or the end of the query, as not one of expected.
+    fn unexpected(&self, expected: Vec<&'static str>) -> pratt::ExpressionError {
+        match self.peek() {
+            Some(t) => pratt::ExpressionError::UnexpectedToken {
+                found: t.clone(),
+                span: self.span(),
+                expected,
+            },
+            None => pratt::ExpressionError::RanOutOfTokens {
+                span: self.end,
+                expected,
+            },
+        }
+    }
+
+    fn is_word(&self, at: usize, word: &str) -> bool {
+        matches!(self.tokens.get(at), Some(t) if matches!(&t.token, Token::Var(v) if v.eq_ignore_ascii_case(word)))
+    }
+
+    fn keyword(&mut self, word: &str) -> bool {
+        if self.is_word(self.pos, word) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn keyword_pair(&mut self, first: &str, second: &str) -> bool {
+        if self.is_word(self.pos, first) && self.is_word(self.pos + 1, second) {
+            self.pos += 2;
+            true
+        } else {
+            false
+        }
+    }
+
+    // at_clause is true when the next tokens open one of the trailing clauses.
+    fn at_clause(&self) -> bool {
+        let num_follows = matches!(
+            self.tokens.get(self.pos + 1).map(|t| &t.token),
+            Some(Token::Num(_))
+        );
+        (self.is_word(self.pos, "order") && self.is_word(self.pos + 1, "by"))
+            || (self.is_word(self.pos, "group") && self.is_word(self.pos + 1, "by"))
+            || (self.is_word(self.pos, "limit") && num_follows)
+            || (self.is_word(self.pos, "offset") && num_follows)
+    }
+
+    // finish rejects anything left over once every clause has been read. expected is what could
+    // still have come.
+    fn finish(&self, expected: Vec<&'static str>) -> Result<(), pratt::ExpressionError> {
+        match self.peek() {
+            Some(_) => Err(self.unexpected(expected)),
+            None => Ok(()),
+        }
+    }
+
+    fn field(&mut self) -> Result<String, pratt::ExpressionError> {
+        match self.peek() {
+            Some(Token::Var(v)) => {
+                let v = v.clone();
+                self.pos += 1;
+                Ok(v)
+            }
+            _ => Err(self.unexpected(vec!["field name"])),
+        }
+    }
+
+    fn field_list(&mut self) -> Result<Vec<String>, pratt::ExpressionError> {
+        let mut fields = vec![self.field()?];
+        while let Some(Token::Comma) = self.peek() {
+            self.pos += 1;
+            fields.push(self.field()?);
+        }
+        Ok(fields)
+    }
+
+    // select_list reads plain fields and aggregate calls such as `count(*)` or `sum(amount)`.
+    fn select_list(&mut self) -> Result<(Vec<String>, Vec<Aggregate>), pratt::ExpressionError> {
+        let mut fields = vec![];
+        let mut aggregates = vec![];
+        loop {
+            let name_span = self.span();
+            let name = self.field()?;
+            if let Some(Token::ParenOpen) = self.peek() {
+                aggregates.push(self.aggregate(&name, name_span)?);
+            } else {
+                fields.push(name);
+            }
+            match self.peek() {
+                Some(Token::Comma) => self.pos += 1,
+                _ => return Ok((fields, aggregates)),
+            }
+        }
+    }
+
+    fn aggregate(
+        &mut self,
+        name: &str,
+        name_span: pratt::Span,
+    ) -> Result<Aggregate, pratt::ExpressionError> {
+        let function = match name.to_ascii_lowercase().as_str() {
+            "count" => AggregateFn::Count,
+            "sum" => AggregateFn::Sum,
+            "min" => AggregateFn::Min,
+            "max" => AggregateFn::Max,
+            _ => {
+                return Err(pratt::ExpressionError::UnexpectedToken {
+                    found: Token::Var(name.to_string()),
+                    span: name_span,
+                    expected: vec!["count", "sum", "min", "max"],
+                })
+            }
+        };
+        let open = self.span();
+        self.pos += 1; // the open paren
+        let field = match self.peek() {
+            Some(Token::Multiply) | Some(Token::ParenClose) if function == AggregateFn::Count => {
+                if let Some(Token::Multiply) = self.peek() {
+                    self.pos += 1;
+                }
+                None
+            }
+            _ => Some(self.field()?),
+        };
+        match self.peek() {
+            Some(Token::ParenClose) => {
+                self.pos += 1;
+                Ok(Aggregate { function, field })
+            }
+            _ => Err(pratt::ExpressionError::DidntGetRightParen {
+                open,
+                span: self.span(),
+            }),
+        }
+    }
+
+    fn count(&mut self) -> Result<usize, pratt::ExpressionError> {
+        match self.peek() {
+            Some(Token::Num(n)) if *n >= 0 => {
+                let n = *n as usize;
+                self.pos += 1;
+                Ok(n)
+            }
+            _ => Err(self.unexpected(vec!["number"])),
+        }
+    }
+
+    // predicate takes every token up to the next clause at paren depth zero.
+    fn predicate(&mut self) -> Result<pratt::ASTNode, pratt::ExpressionError> {
+        let start = self.pos;
+        let mut depth = 0;
+        while let Some(t) = self.peek() {
+            match t {
+                Token::ParenOpen => depth += 1,
+                Token::ParenClose => depth -= 1,
+                _ if depth == 0 && self.at_clause() => break,
+                _ => {}
             }
+            self.pos += 1;
         }
+        // The predicate ends where the next clause begins.
+        pratt::parse_spanned(self.tokens[start..self.pos].to_vec(), self.span())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Query;
+    use super::*;
     use std::collections::HashMap;
 
+    fn chain_of(entries: &[&str]) -> Blockchain {
+        let mut chain = Blockchain::new();
+        chain
+            .append_records(entries.iter().map(|e| Record::new(e.to_string())).collect())
+            .unwrap();
+        chain
+    }
+
     #[test]
     fn test_query() {
         let q = Query::new("x == \"bar\"".to_string()).unwrap();
@@ -92,4 +1191,248 @@ mod tests {
         let f = q.parse();
         assert_eq!(f(env), Ok(true));
     }
+
+    #[test]
+    fn test_query_clauses() {
+        let q = Query::new("select a, b where x == 1 order by a desc limit 5 offset 2".to_string())
+            .unwrap();
+        assert_eq!(q.select(), &vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(
+            q.order_by(),
+            Some(&OrderBy {
+                field: "a".to_string(),
+                descending: true
+            })
+        );
+        assert_eq!(q.limit(), Some(5));
+        assert_eq!(q.offset(), 2);
+
+        let q = Query::new("select kind, count(*), sum(n) group by kind".to_string()).unwrap();
+        assert!(q.is_aggregate());
+        assert_eq!(q.group_by(), &vec!["kind".to_string()]);
+        assert_eq!(
+            q.aggregates().iter().map(|a| a.name()).collect::<Vec<_>>(),
+            vec!["count(*)", "sum(n)"]
+        );
+
+        assert!(Query::new("select kind, count() ".to_string()).is_err());
+        assert!(Query::new("select avg(n)".to_string()).is_err());
+        assert!(Query::new("".to_string()).is_err());
+        assert!(Query::new("x == 1 limit".to_string()).is_err());
+        assert!(Query::new("select a where".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_query_paging_and_projection() {
+        let chain = chain_of(&[
+            r#"{"n": "3", "kind": "a"}"#,
+            r#"{"n": "1", "kind": "a"}"#,
+            r#"{"n": "2", "kind": "b"}"#,
+            r#"{"n": "4", "kind": "a"}"#,
+        ]);
+        let q = Query::new("select n where kind == \"a\" order by n limit 2".to_string()).unwrap();
+
+        let first = q.run(&chain, None).unwrap();
+        let QueryRows::Projected(rows) = first.rows else {
+            panic!("expected projected rows");
+        };
+        let ns: Vec<&String> = rows.iter().map(|r| &r.fields["n"]).collect();
+        assert_eq!(ns, vec!["1", "3"]);
+        assert!(!rows[0].fields.contains_key("kind"));
+
+        let cursor = first.next_cursor.unwrap();
+        let second = q.run(&chain, Some(&cursor)).unwrap();
+        let QueryRows::Projected(rows) = second.rows else {
+            panic!("expected projected rows");
+        };
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].fields["n"], "4");
+        assert_eq!(second.next_cursor, None);
+
+        // records written between pages, before or among the rows already seen, shift no row
+        // onto or off the next page.
+        let mut chain = chain;
+        chain
+            .append_records(vec![
+                Record::new(r#"{"n": "0", "kind": "a"}"#.to_string()),
+                Record::new(r#"{"n": "3", "kind": "a"}"#.to_string()),
+            ])
+            .unwrap();
+        let second = q.run(&chain, Some(&cursor)).unwrap();
+        let QueryRows::Projected(rows) = second.rows else {
+            panic!("expected projected rows");
+        };
+        let ns: Vec<&String> = rows.iter().map(|r| &r.fields["n"]).collect();
+        assert_eq!(ns, vec!["3", "4"]);
+    }
+
+    #[test]
+    fn test_query_aggregation() {
+        let chain = chain_of(&[
+            r#"{"n": "3", "kind": "a"}"#,
+            r#"{"n": "1", "kind": "a"}"#,
+            r#"{"n": "2", "kind": "b"}"#,
+            r#"{"n": "4", "kind": "a"}"#,
+            r#"{"kind": "c"}"#,
+            r#"{"n": "5"}"#,
+        ]);
+        let q = Query::new(
+            "select kind, count(*), sum(n), min(n), max(n) group by kind order by kind desc"
+                .to_string(),
+        )
+        .unwrap();
+        let QueryRows::Groups(groups) = q.run(&chain, None).unwrap().rows else {
+            panic!("expected groups");
+        };
+        let kinds = |groups: &[GroupRow]| -> Vec<Option<String>> {
+            groups
+                .iter()
+                .map(|g| g.group.get("kind").cloned())
+                .collect()
+        };
+        let kind = |k: &str| Some(k.to_string());
+        assert_eq!(kinds(&groups), vec![kind("c"), kind("b"), kind("a"), None]);
+        assert_eq!(groups[2].aggregates["count(*)"], "3");
+        assert_eq!(groups[2].aggregates["sum(n)"], "8");
+        assert_eq!(groups[2].aggregates["min(n)"], "1");
+        assert_eq!(groups[2].aggregates["max(n)"], "4");
+        assert_eq!(groups[0].aggregates["sum(n)"], "0");
+        assert!(!groups[0].aggregates.contains_key("min(n)"));
+        // as with records, groups lacking the ordering field sort last in either direction.
+        assert_eq!(groups[3].aggregates["sum(n)"], "5");
+        let q =
+            Query::new("select kind, count(*) group by kind order by kind".to_string()).unwrap();
+        let QueryRows::Groups(groups) = q.run(&chain, None).unwrap().rows else {
+            panic!("expected groups");
+        };
+        assert_eq!(kinds(&groups), vec![kind("a"), kind("b"), kind("c"), None]);
+
+        let q = Query::new("select count(*) where kind == \"z\"".to_string()).unwrap();
+        let QueryRows::Groups(groups) = q.run(&chain, None).unwrap().rows else {
+            panic!("expected groups");
+        };
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].aggregates["count(*)"], "0");
+
+        let q = Query::new("select sum(kind)".to_string()).unwrap();
+        assert!(q.run(&chain, None).is_err());
+    }
+
+    #[test]
+    fn test_query_indexes() {
+        let mut chain = chain_of(&[
+            r#"{"n": "3", "kind": "a"}"#,
+            r#"{"n": "1", "kind": "b"}"#,
+            r#"{"n": "x", "kind": "a"}"#,
+            r#"{"kind": "a"}"#,
+        ]);
+        let queries = [
+            "kind == \"a\"",
+            "n > 1",
+            "2 >= n",
+            "kind == \"a\" && n >= 3",
+            "n == 1 || kind == \"a\"",
+            "n == 1 || !(kind == \"a\")",
+        ];
+        let scanned: Vec<QueryResult> = queries
+            .iter()
+            .map(|q| {
+                Query::new(q.to_string())
+                    .unwrap()
+                    .run(&chain, None)
+                    .unwrap()
+            })
+            .collect();
+
+        chain.set_indexes(&["kind".to_string(), "n".to_string()]);
+        for (q, scanned) in queries.iter().zip(scanned) {
+            let q = Query::new(q.to_string()).unwrap();
+            assert_eq!(q.run(&chain, None).unwrap(), scanned);
+        }
+
+        let explain = Query::new("kind == \"a\" && n >= 3".to_string())
+            .unwrap()
+            .explain(&chain);
+        assert!(explain.index_used);
+        assert_eq!(explain.access, "index lookup (kind == \"a\") && (n >= 3)");
+        assert_eq!(explain.indexes, vec!["kind", "n"]);
+        assert_eq!(explain.candidates, 1);
+
+        // Negation cannot be answered from an index, so the disjunction falls back to a scan.
+        let explain = Query::new("n == 1 || !(kind == \"a\")".to_string())
+            .unwrap()
+            .explain(&chain);
+        assert!(!explain.index_used);
+        assert_eq!(explain.access, "full scan");
+        assert_eq!(explain.candidates, 5);
+    }
+
+    #[test]
+    fn test_query_errors() {
+        let kind = |q: &str| Query::new(q.to_string()).err().map(|e| e.kind);
+        assert_eq!(kind("x =="), Some(QueryErrorKind::Syntax));
+        assert_eq!(kind("1 + 2"), Some(QueryErrorKind::Syntax));
+        assert_eq!(kind("x == \"a\" * 2"), Some(QueryErrorKind::Syntax));
+
+        let chain = chain_of(&[r#"{"x": "1"}"#]);
+        let q = Query::new("x == 1".to_string()).unwrap();
+        assert_eq!(q.plan().variables(), vec!["x".to_string()]);
+        assert_eq!(
+            q.run(&chain, Some("bogus")).err().map(|e| e.kind),
+            Some(QueryErrorKind::Cursor)
+        );
+        let q = Query::new("select sum(x) where x == \"1\"".to_string()).unwrap();
+        assert!(q.run(&chain, None).is_ok());
+
+        // overflow fails the query, whether the arithmetic is constant or per record.
+        assert_eq!(kind("x == 2147483647 + 1"), Some(QueryErrorKind::Syntax));
+        let chain = chain_of(&[r#"{"x": "2147483647"}"#]);
+        let q = Query::new("x + 1 > 0".to_string()).unwrap();
+        assert_eq!(
+            q.run(&chain, None).err().map(|e| e.kind),
+            Some(QueryErrorKind::Overflow)
+        );
+    }
+
+    #[test]
+    fn test_query_error_location() {
+        let e = Query::new("select a, b\nwhere x == \"a\"\norder by".to_string()).unwrap_err();
+        assert_eq!(e.kind, QueryErrorKind::Syntax);
+        assert_eq!((e.line, e.column), (Some(3), Some(9)));
+        assert_eq!(e.expected, vec!["field name".to_string()]);
+
+        let e = Query::new("x == 1 limit 2 bogus".to_string()).unwrap_err();
+        assert_eq!((e.line, e.column), (Some(1), Some(16)));
+        assert_eq!(
+            e.excerpt.as_deref(),
+            Some("x == 1 limit 2 bogus\n               ^^^^^")
+        );
+        assert_eq!(e.expected, vec!["offset", "end of query"]);
+
+        let e = Query::new("select a order by a bogus".to_string()).unwrap_err();
+        assert_eq!(
+            e.expected,
+            vec!["asc", "desc", "limit", "offset", "end of query"]
+        );
+        let e = Query::new("select a 7".to_string()).unwrap_err();
+        assert_eq!(
+            e.expected,
+            vec![
+                "where",
+                "group by",
+                "order by",
+                "limit",
+                "offset",
+                "end of query"
+            ]
+        );
+
+        let e = Query::new("select avg(n)".to_string()).unwrap_err();
+        assert_eq!(e.column, Some(8));
+        assert_eq!(e.expected, vec!["count", "sum", "min", "max"]);
+
+        let e = Query::new("x == \"open".to_string()).unwrap_err();
+        assert_eq!(e.message, "unterminated string");
+        assert_eq!(e.column, Some(6));
+    }
 }
@@ -0,0 +1,262 @@
+// Quotas keep any one client from filling the mempool. Writers are told apart by the API key
+// they were let in with, or failing that by the address they connect from, and each one gets a
+// token bucket limiting how fast it may write, and a cap on how many of its records may be
+// pending at once. Fellow members of the cluster relaying records are not limited.
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+// Past this many clients, those that have been idle long enough to refill are forgotten.
+const MAX_CLIENTS: usize = 4096;
+
+fn default_requests_per_sec() -> f64 {
+    10.0
+}
+
+fn default_burst() -> f64 {
+    20.0
+}
+
+/// RateLimitConfig is the `[rate_limit]` section of the server config.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    // requests_per_sec is the rate writes are allowed at, over time.
+    #[serde(default = "default_requests_per_sec")]
+    pub requests_per_sec: f64,
+    // burst is how many writes may arrive at once after a quiet spell.
+    #[serde(default = "default_burst")]
+    pub burst: f64,
+    // mempool_quota is the most records one client may have pending. Unset, only the size of
+    // the pool limits it.
+    #[serde(default)]
+    pub mempool_quota: Option<usize>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> RateLimitConfig {
+        RateLimitConfig {
+            requests_per_sec: default_requests_per_sec(),
+            burst: default_burst(),
+            mempool_quota: None,
+        }
+    }
+}
+
+impl RateLimitConfig {
+    // validate refuses limits no bucket can keep: a rate that never refills it, or a burst too
+    // small to hold a single write.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.requests_per_sec.is_nan() || self.requests_per_sec <= 0.0 {
+            return Err(format!(
+                "requests_per_sec must be above 0, not {}",
+                self.requests_per_sec
+            ));
+        }
+        if self.burst.is_nan() || self.burst < 1.0 {
+            return Err(format!("burst must be at least 1, not {}", self.burst));
+        }
+        Ok(())
+    }
+}
+
+// client_id names the writer of a request: the name of the key it was let in with, or its
+// address. Only keys the node accepted count, so a client cannot take a fresh identity, and a
+// fresh bucket, by sending a made-up key.
+pub fn client_id(key_name: Option<&str>, addr: IpAddr) -> String {
+    match key_name {
+        Some(name) => format!("key:{name}"),
+        None => format!("ip:{addr}"),
+    }
+}
+
+/// ClientCounters counts what became of one client's writes.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientCounters {
+    pub admitted: u64,
+    pub rate_limited: u64,
+    pub over_quota: u64,
+}
+
+/// Limited is why a write was refused, and how long to wait before trying again.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Limited {
+    RateLimited(Duration),
+    OverQuota,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+struct Client {
+    bucket: Bucket,
+    counters: ClientCounters,
+}
+
+/// Limiter holds the buckets and counters of every client. Without a config nothing is limited,
+/// but writes are still counted.
+pub struct Limiter {
+    config: Option<RateLimitConfig>,
+    clients: HashMap<String, Client>,
+}
+
+impl Limiter {
+    pub fn new(config: Option<RateLimitConfig>) -> Limiter {
+        Limiter {
+            config,
+            clients: HashMap::new(),
+        }
+    }
+
+    // check takes a token from client's bucket, given how many of its records are pending.
+    // A refusal is counted; an allowed write is not counted until it is admitted.
+    pub fn check(&mut self, client: &str, pending: usize) -> Result<(), Limited> {
+        self.check_at(client, pending, 1, Instant::now())
+    }
+
+    // check_writes is check for a request making several writes at once, taking a token for
+    // each. A request may go into debt, so that one larger than the burst is not refused
+    // forever; the client then waits until the debt is paid off.
+    pub fn check_writes(
+        &mut self,
+        client: &str,
+        pending: usize,
+        writes: usize,
+    ) -> Result<(), Limited> {
+        self.check_at(client, pending, writes, Instant::now())
+    }
+
+    fn check_at(
+        &mut self,
+        client: &str,
+        pending: usize,
+        writes: usize,
+        now: Instant,
+    ) -> Result<(), Limited> {
+        let config = match &self.config {
+            Some(config) => config.clone(),
+            None => return Ok(()),
+        };
+        let c = self.client(client, &config, now);
+        if config.mempool_quota.is_some_and(|quota| pending >= quota) {
+            c.counters.over_quota += 1;
+            return Err(Limited::OverQuota);
+        }
+        let elapsed = now
+            .saturating_duration_since(c.bucket.refilled)
+            .as_secs_f64();
+        c.bucket.tokens = (c.bucket.tokens + elapsed * config.requests_per_sec).min(config.burst);
+        c.bucket.refilled = now;
+        if c.bucket.tokens < 1.0 {
+            c.counters.rate_limited += 1;
+            let wait = (1.0 - c.bucket.tokens) / config.requests_per_sec;
+            return Err(Limited::RateLimited(
+                Duration::try_from_secs_f64(wait).unwrap_or(Duration::MAX),
+            ));
+        }
+        c.bucket.tokens -= writes as f64;
+        Ok(())
+    }
+
+    // admitted counts a write of client's that made it into the pool.
+    pub fn admitted(&mut self, client: &str) {
+        let config = self.config.clone().unwrap_or_default();
+        self.client(client, &config, Instant::now())
+            .counters
+            .admitted += 1;
+    }
+
+    pub fn counters(&self) -> BTreeMap<String, ClientCounters> {
+        self.clients
+            .iter()
+            .map(|(id, c)| (id.clone(), c.counters.clone()))
+            .collect()
+    }
+
+    fn client(&mut self, client: &str, config: &RateLimitConfig, now: Instant) -> &mut Client {
+        if self.clients.len() >= MAX_CLIENTS && !self.clients.contains_key(client) {
+            self.forget_idle(config, now);
+        }
+        self.clients
+            .entry(client.to_string())
+            .or_insert_with(|| Client {
+                bucket: Bucket {
+                    tokens: config.burst,
+                    refilled: now,
+                },
+                counters: ClientCounters::default(),
+            })
+    }
+
+    // forget_idle drops the clients whose buckets would be full by now, so that a stream of
+    // one-off addresses cannot grow the limiter without bound.
+    fn forget_idle(&mut self, config: &RateLimitConfig, now: Instant) {
+        self.clients.retain(|_, c| {
+            let elapsed = now
+                .saturating_duration_since(c.bucket.refilled)
+                .as_secs_f64();
+            c.bucket.tokens + elapsed * config.requests_per_sec < config.burst
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limiter() {
+        let mut limiter = Limiter::new(Some(RateLimitConfig {
+            requests_per_sec: 2.0,
+            burst: 2.0,
+            mempool_quota: Some(3),
+        }));
+        let start = Instant::now();
+        assert_eq!(limiter.check_at("a", 0, 1, start), Ok(()));
+        assert_eq!(limiter.check_at("a", 0, 1, start), Ok(()));
+        assert_eq!(
+            limiter.check_at("a", 0, 1, start),
+            Err(Limited::RateLimited(Duration::from_millis(500)))
+        );
+        // other clients have buckets of their own.
+        assert_eq!(limiter.check_at("b", 0, 1, start), Ok(()));
+        assert_eq!(limiter.check_at("b", 3, 1, start), Err(Limited::OverQuota));
+        assert_eq!(
+            limiter.check_at("a", 0, 1, start + Duration::from_millis(500)),
+            Ok(())
+        );
+        // a batch takes a token for each of its writes, going into debt past the burst.
+        assert_eq!(limiter.check_at("c", 0, 5, start), Ok(()));
+        assert_eq!(
+            limiter.check_at("c", 0, 1, start + Duration::from_secs(1)),
+            Err(Limited::RateLimited(Duration::from_secs(1)))
+        );
+        limiter.admitted("a");
+
+        let counters = limiter.counters();
+        assert_eq!(counters["a"].admitted, 1);
+        assert_eq!(counters["a"].rate_limited, 1);
+        assert_eq!(counters["b"].over_quota, 1);
+
+        let mut unlimited = Limiter::new(None);
+        for _ in 0..100 {
+            assert_eq!(unlimited.check("a", 100), Ok(()));
+        }
+        let broken = |requests_per_sec, burst| RateLimitConfig {
+            requests_per_sec,
+            burst,
+            mempool_quota: None,
+        };
+        assert!(broken(0.0, 1.0).validate().is_err());
+        assert!(broken(-1.0, 1.0).validate().is_err());
+        assert!(broken(f64::NAN, 1.0).validate().is_err());
+        assert!(broken(1.0, 0.5).validate().is_err());
+        assert!(broken(0.001, 1.0).validate().is_ok());
+
+        let addr = "127.0.0.1".parse().unwrap();
+        assert_eq!(client_id(Some("writer"), addr), "key:writer");
+        assert_eq!(client_id(None, addr), "ip:127.0.0.1");
+    }
+}
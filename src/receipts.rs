@@ -0,0 +1,136 @@
+// Receipts make writes safe to retry. A client names a write with an idempotency key, and for
+// as long as the window lasts, sending the same write under the same key again gets back the
+// receipt of the first one instead of making a second record.
+use crate::api::TiafReceipt;
+use sha3::{Digest, Sha3_256};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+struct Kept {
+    receipt: TiafReceipt,
+    // digest is of the entry written, to catch a key reused for a different write.
+    digest: Vec<u8>,
+    kept: Instant,
+}
+
+/// Replay is what a key has been used for before.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Replay {
+    // New is a key not seen within the window.
+    New,
+    // Repeat is the same write again, with the receipt it got.
+    Repeat(TiafReceipt),
+    // Conflict is the key used before for a different write.
+    Conflict,
+}
+
+/// Receipts remembers the receipt of every keyed write for a window of time. Keys belong to the
+/// client that sent them, so two clients never collide.
+pub struct Receipts {
+    window: Duration,
+    kept: HashMap<(String, String), Kept>,
+    // order holds keys oldest first, so expired receipts can be dropped from the front.
+    order: VecDeque<(Instant, (String, String))>,
+}
+
+fn digest(entry: &str) -> Vec<u8> {
+    let mut hasher = Sha3_256::new();
+    hasher.update(entry.as_bytes());
+    hasher.finalize().to_vec()
+}
+
+impl Receipts {
+    pub fn new(window: Duration) -> Receipts {
+        Receipts {
+            window,
+            kept: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    // replay looks up what client last wrote under key.
+    pub fn replay(&mut self, client: &str, key: &str, entry: &str) -> Replay {
+        self.replay_at(client, key, entry, Instant::now())
+    }
+
+    fn replay_at(&mut self, client: &str, key: &str, entry: &str, now: Instant) -> Replay {
+        self.expire(now);
+        match self.kept.get(&(client.to_string(), key.to_string())) {
+            None => Replay::New,
+            Some(kept) if kept.digest == digest(entry) => Replay::Repeat(kept.receipt.clone()),
+            Some(_) => Replay::Conflict,
+        }
+    }
+
+    // keep remembers the receipt of a write client made under key.
+    pub fn keep(&mut self, client: &str, key: &str, entry: &str, receipt: TiafReceipt) {
+        self.keep_at(client, key, entry, receipt, Instant::now())
+    }
+
+    fn keep_at(
+        &mut self,
+        client: &str,
+        key: &str,
+        entry: &str,
+        receipt: TiafReceipt,
+        now: Instant,
+    ) {
+        let id = (client.to_string(), key.to_string());
+        self.order.push_back((now, id.clone()));
+        self.kept.insert(
+            id,
+            Kept {
+                receipt,
+                digest: digest(entry),
+                kept: now,
+            },
+        );
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some((at, _)) = self.order.front() {
+            if now.saturating_duration_since(*at) < self.window {
+                break;
+            }
+            let (at, id) = self.order.pop_front().unwrap();
+            // a key kept again since has a later entry in order, and stays.
+            if self.kept.get(&id).is_some_and(|k| k.kept == at) {
+                self.kept.remove(&id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::Record;
+
+    #[test]
+    fn test_receipts() {
+        let mut receipts = Receipts::new(Duration::from_secs(60));
+        let start = Instant::now();
+        let receipt = TiafReceipt::of(&Record::new("a".to_string()), false);
+
+        assert_eq!(receipts.replay_at("c", "k", "a", start), Replay::New);
+        receipts.keep_at("c", "k", "a", receipt.clone(), start);
+        assert_eq!(
+            receipts.replay_at("c", "k", "a", start),
+            Replay::Repeat(receipt.clone())
+        );
+        assert_eq!(receipts.replay_at("c", "k", "b", start), Replay::Conflict);
+        assert_eq!(receipts.replay_at("d", "k", "a", start), Replay::New);
+
+        // kept again part way through the window, the key outlives the first keep.
+        let later = start + Duration::from_secs(30);
+        receipts.keep_at("c", "k", "a", receipt.clone(), later);
+        assert_eq!(
+            receipts.replay_at("c", "k", "a", start + Duration::from_secs(60)),
+            Replay::Repeat(receipt)
+        );
+        assert_eq!(
+            receipts.replay_at("c", "k", "a", later + Duration::from_secs(60)),
+            Replay::New
+        );
+    }
+}
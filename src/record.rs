@@ -1,4 +1,5 @@
 use crate::hexdisplay::HexDisplayExt;
+use crate::redaction::Redaction;
 use crate::types::{Hashtype, Time};
 use serde::{Deserialize, Serialize};
 use sha3::{Digest, Sha3_256};
@@ -23,6 +24,9 @@ pub struct Record {
     pub entry: String,
     // This is the hash of the above.
     pub hash: Hashtype,
+    // Set, the entry has been redacted: it is empty, and the hash is that of the entry as it was.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redaction: Option<Redaction>,
 }
 
 // A KVRecord is a list of key-value pairs: a row in a database table, as it were.
@@ -38,6 +42,11 @@ impl<'a> KVRecord<'a> {
     pub fn pairs(&self) -> HashMap<String, String> {
         self.pairs.clone()
     }
+
+    // fields borrows the pairs, for callers that only need to look.
+    pub fn fields(&self) -> &HashMap<String, String> {
+        &self.pairs
+    }
 }
 
 impl Hash for Record {
@@ -86,22 +95,44 @@ impl Record {
             timestamp: 0,
             entry: bereshit,
             hash: "rec-init".to_string(),
+            redaction: None,
         };
         r.ensure_hash();
         r
     }
 
+    // named_genesis_record begins the chain called name, so that chains of different names
+    // never share blocks.
+    pub fn named_genesis_record(name: &str) -> Record {
+        let mut r = Record::genesis_record();
+        r.entry = format!("{} {name}", r.entry);
+        r.hash = "rec-init".to_string();
+        r.ensure_hash();
+        r
+    }
+
     // New generates a fully hashed record with a proper timestamp.
     pub fn new(data: String) -> Record {
+        Record::with_uuid(data, Uuid::new_v4())
+    }
+
+    // with_uuid is new, for a record whose uuid the client chose.
+    pub fn with_uuid(data: String, uuid: Uuid) -> Record {
         let now = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
             Ok(n) => n.as_secs(),
             Err(_) => panic!("SystemTime before UNIX EPOCH!"),
         };
+        Record::at(data, uuid, now)
+    }
+
+    // at is a record made at timestamp, for records every node must make alike.
+    pub fn at(data: String, uuid: Uuid, timestamp: Time) -> Record {
         let mut r = Record {
-            uuid: Uuid::new_v4(),
-            timestamp: now,
+            uuid,
+            timestamp,
             entry: data,
             hash: "rec-init".to_string(),
+            redaction: None,
         };
         // a valid Record always has a valid hash.
         r.ensure_hash();
@@ -123,6 +154,12 @@ impl Record {
         }
     }
     pub fn validate(&self) -> Result<(), String> {
+        if let Some(redaction) = &self.redaction {
+            if !self.entry.is_empty() {
+                return Err(format!("redacted record {} still has an entry", self.hash));
+            }
+            return redaction.verify(&self.hash);
+        }
         let time_bytes = self.timestamp.to_be_bytes();
         let entry_bytes = self.entry.as_bytes();
         let uuid_bytes = self.uuid.as_bytes().to_vec();
@@ -139,7 +176,25 @@ impl Record {
             Err(format!("record hash mismatch: {} != {}", hash, self.hash))
         }
     }
+    // redact replaces the entry with a tombstone, keeping the hash.
+    pub fn redact(&mut self, redaction: Redaction) -> Result<(), String> {
+        if self.redaction.is_some() {
+            return Err(format!("record {} is already redacted", self.hash));
+        }
+        redaction.verify(&self.hash)?;
+        self.entry = String::new();
+        self.redaction = Some(redaction);
+        Ok(())
+    }
+
+    pub fn is_redacted(&self) -> bool {
+        self.redaction.is_some()
+    }
+
     pub fn structured_entry(&self) -> Result<KVRecord<'_>, String> {
+        if self.is_redacted() {
+            return Err(format!("record {} is redacted", self.hash));
+        }
         let j = serde_json::from_str(&self.entry).map_err(|e| e.to_string())?;
 
         Ok(KVRecord {
@@ -0,0 +1,83 @@
+// Redaction. A legal hold or an erasure request can require a payload to leave the chain, but a
+// record's hash covers its entry, and a block's hash covers the hashes of its records. So a
+// redacted record becomes a tombstone: its entry is emptied, its hash is kept, and it carries a
+// redaction signed by an admin. The block hash still holds, since it only ever covered the
+// record's hash. The signature covers the record's hash and the reason, so it cannot be moved to
+// another record, and a chain accepts tombstones signed by its redaction keys only.
+use crate::signing::PrivateKey;
+use crate::types::{Hashtype, Time};
+use serde::{Deserialize, Serialize};
+
+/// Redaction is why, when and by whom a record's entry was removed. key is the public key of the
+/// admin who signed it, in hex.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Redaction {
+    pub reason: String,
+    pub redacted_at: Time,
+    pub key: String,
+    pub signature: String,
+}
+
+// message is what a redaction of record signs.
+fn message(record: &Hashtype, reason: &str, redacted_at: Time) -> Vec<u8> {
+    format!("tiaf-redaction\n{record}\n{redacted_at}\n{reason}").into_bytes()
+}
+
+/// RedactionKey is an admin's private key for signing redactions. Its public half goes in the
+/// redaction_keys of every node hosting the chain.
+pub type RedactionKey = PrivateKey;
+
+impl Redaction {
+    // sign redacts record, for reason, now, with the admin's key.
+    pub fn sign(key: &RedactionKey, record: &Hashtype, reason: &str) -> Redaction {
+        Redaction::sign_at(key, record, reason, chrono::Utc::now().timestamp() as Time)
+    }
+
+    fn sign_at(
+        key: &RedactionKey,
+        record: &Hashtype,
+        reason: &str,
+        redacted_at: Time,
+    ) -> Redaction {
+        Redaction {
+            reason: reason.to_string(),
+            redacted_at,
+            key: key.public(),
+            signature: key.sign(&message(record, reason, redacted_at)),
+        }
+    }
+
+    // verify checks that the redaction was signed by its key, for record. Whether the key may
+    // redact is for the chain to say.
+    pub fn verify(&self, record: &Hashtype) -> Result<(), String> {
+        let message = message(record, &self.reason, self.redacted_at);
+        crate::signing::verify(&self.key, &message, &self.signature)
+            .map_err(|e| format!("redaction of record {record} is not valid: {e}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redaction_signatures() {
+        let admin = RedactionKey::generate();
+        let record = "ABC".to_string();
+        let redaction = Redaction::sign_at(&admin, &record, "erasure request 17", 1_700_000_000);
+        redaction.verify(&record).unwrap();
+
+        // a signature does not carry over to another record, reason or key.
+        assert!(redaction.verify(&"ABD".to_string()).is_err());
+        let reworded = Redaction {
+            reason: "tidying up".to_string(),
+            ..redaction.clone()
+        };
+        assert!(reworded.verify(&record).is_err());
+        let rekeyed = Redaction {
+            key: RedactionKey::generate().public(),
+            ..redaction
+        };
+        assert!(rekeyed.verify(&record).is_err());
+    }
+}
@@ -0,0 +1,213 @@
+// Schemas let operators hold entries to a shape. A schema is a JSON Schema registered under a
+// name and version, and an entry opts in by naming one in its `$schema` field:
+//
+//   {"$schema": "payment@2", "amount": 120, "payee": "acme"}
+//
+// Tagged entries that do not conform are refused on ingest; untagged entries are not checked.
+// The tag lives inside the entry so that it travels with the record to every peer.
+use crate::block::Block;
+use crate::events::{self, Event};
+use jsonschema::JSONSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// SCHEMA_FIELD is the entry field naming the schema an entry conforms to.
+pub const SCHEMA_FIELD: &str = "$schema";
+
+/// SchemaDefined records the registration of a schema on the chain, so there is an audit
+/// trail of every shape entries were ever held to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SchemaDefined {
+    pub name: String,
+    pub version: u32,
+    pub schema: serde_json::Value,
+}
+
+impl Event for SchemaDefined {
+    const TYPE: &'static str = "tiaf.schema-defined";
+}
+
+impl SchemaDefined {
+    // id is how entries refer to the schema: `name@version`.
+    pub fn id(&self) -> String {
+        format!("{}@{}", self.name, self.version)
+    }
+}
+
+/// SchemaError is why an entry was refused.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchemaError {
+    pub schema: String,
+    pub errors: Vec<String>,
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "entry does not conform to {}: {}",
+            self.schema,
+            self.errors.join("; ")
+        )
+    }
+}
+
+struct Registered {
+    definition: SchemaDefined,
+    compiled: JSONSchema,
+}
+
+/// SchemaRegistry holds the schemas entries may be checked against, keyed by id.
+#[derive(Default)]
+pub struct SchemaRegistry {
+    schemas: BTreeMap<String, Registered>,
+}
+
+impl fmt::Debug for SchemaRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.schemas.keys()).finish()
+    }
+}
+
+impl SchemaRegistry {
+    pub fn new() -> SchemaRegistry {
+        SchemaRegistry::default()
+    }
+
+    // register adds a schema. A schema, once registered, cannot be changed: registering the
+    // same id again succeeds only with the same schema, and returns false.
+    pub fn register(&mut self, definition: SchemaDefined) -> Result<bool, String> {
+        let id = definition.id();
+        if let Some(existing) = self.schemas.get(&id) {
+            if existing.definition.schema == definition.schema {
+                return Ok(false);
+            }
+            return Err(format!("schema {id} is already registered differently"));
+        }
+        let compiled = JSONSchema::compile(&definition.schema)
+            .map_err(|e| format!("schema {id} is not a valid JSON Schema: {e}"))?;
+        self.schemas.insert(
+            id,
+            Registered {
+                definition,
+                compiled,
+            },
+        );
+        Ok(true)
+    }
+
+    // add_block registers the schemas defined in block, so that a chain's schemas are rebuilt
+    // as its blocks are loaded or synced. Should the chain define an id twice, the first
+    // definition stands.
+    pub fn add_block(&mut self, block: &Block) {
+        for record in &block.data {
+            if let Ok(Some((definition, _))) = events::decode::<SchemaDefined>(&record.entry) {
+                let _ = self.register(definition);
+            }
+        }
+    }
+
+    // unregister takes back a registration that could not be recorded on the chain.
+    pub(crate) fn unregister(&mut self, id: &str) {
+        self.schemas.remove(id);
+    }
+
+    pub fn ids(&self) -> Vec<String> {
+        self.schemas.keys().cloned().collect()
+    }
+
+    pub fn get(&self, id: &str) -> Option<&SchemaDefined> {
+        self.schemas.get(id).map(|r| &r.definition)
+    }
+
+    // validate checks an entry against the schema it names, if it names one. The schema field
+    // itself is not part of what is checked.
+    pub fn validate(&self, entry: &str) -> Result<(), SchemaError> {
+        let mut value: serde_json::Value = match serde_json::from_str(entry) {
+            Ok(value) => value,
+            Err(_) => return Ok(()),
+        };
+        let id = match value.as_object_mut().and_then(|o| o.remove(SCHEMA_FIELD)) {
+            Some(serde_json::Value::String(id)) => id,
+            Some(other) => {
+                return Err(SchemaError {
+                    schema: other.to_string(),
+                    errors: vec![format!("{SCHEMA_FIELD} must be a string")],
+                })
+            }
+            None => return Ok(()),
+        };
+        let registered = match self.schemas.get(&id) {
+            Some(registered) => registered,
+            None => {
+                return Err(SchemaError {
+                    schema: id,
+                    errors: vec!["no such schema is registered".to_string()],
+                })
+            }
+        };
+        registered.compiled.validate(&value).map_err(|errors| {
+            let errors = errors
+                .map(|e| match e.instance_path.to_string() {
+                    path if path.is_empty() => e.to_string(),
+                    path => format!("{path}: {e}"),
+                })
+                .collect();
+            SchemaError {
+                schema: id.clone(),
+                errors,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_schema_registry() {
+        let payment = SchemaDefined {
+            name: "payment".to_string(),
+            version: 1,
+            schema: json!({
+                "type": "object",
+                "properties": {"amount": {"type": "integer", "minimum": 0}},
+                "required": ["amount"],
+                "additionalProperties": false
+            }),
+        };
+        let mut registry = SchemaRegistry::new();
+        assert_eq!(registry.register(payment.clone()), Ok(true));
+        assert_eq!(registry.register(payment.clone()), Ok(false));
+        let mut changed = payment.clone();
+        changed.schema = json!({"type": "string"});
+        assert!(registry.register(changed).is_err());
+        let mut broken = payment;
+        broken.version = 2;
+        broken.schema = json!({"type": 12});
+        assert!(registry.register(broken).is_err());
+        assert_eq!(registry.ids(), vec!["payment@1"]);
+
+        assert_eq!(
+            registry.validate(r#"{"$schema": "payment@1", "amount": 3}"#),
+            Ok(())
+        );
+        assert_eq!(registry.validate(r#"{"amount": "anything"}"#), Ok(()));
+        assert_eq!(registry.validate("not json"), Ok(()));
+
+        let e = registry
+            .validate(r#"{"$schema": "payment@1", "amount": -3}"#)
+            .unwrap_err();
+        assert_eq!(e.schema, "payment@1");
+        assert_eq!(e.errors.len(), 1);
+        assert!(e.errors[0].starts_with("/amount: "));
+
+        let e = registry
+            .validate(r#"{"$schema": "payment@9"}"#)
+            .unwrap_err();
+        assert_eq!(e.errors, vec!["no such schema is registered"]);
+    }
+}
@@ -1,261 +1,1272 @@
+use crate::block::Block;
 use crate::chain::Blockchain;
+use crate::cluster::{ClusterKey, NODE_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+use crate::credentials::{Access, Denied, Scope};
+use crate::daemons;
+use crate::encryption::{Keyring, ENCRYPTION_KEY_HEADER};
+use crate::genesis::GENESIS_HEADER;
+use crate::namespace::{Namespace, Namespaces, DEFAULT};
 use crate::record::Record;
-use crate::woody::Attributes;
+use crate::woody::{Attributes, Logger};
 
-use crate::mempool::MemPool;
-use crate::notes;
+use crate::mempool::{MemPool, MemPoolError};
 use crate::query_chain;
 use crate::woody;
 
 use crate::api;
-use crate::api::{AdminKey, TiafBoringResponse, TiafDownstreams, TiafUpstreams};
+use crate::api::{TiafBoringResponse, TiafDownstreams, TiafUpstreams};
+use crate::events;
 use crate::peers::{Downstreams, Upstreams};
-use query_chain::Queryable;
+use crate::policy::BlockPolicy;
+use crate::quota::{self, Limited, Limiter};
+use crate::receipts::{Receipts, Replay};
+use crate::schema::{SchemaDefined, SchemaError};
+use crate::subscribe::{Subscription, LONGEST_WAIT};
+use crate::tls::{self, Origins, TlsConfig};
+use query_chain::{QueryError, QueryErrorKind};
 use rouille::{Request, Response};
+use std::io::Read;
+use std::net::IpAddr;
 use std::ops::Deref;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 
-fn auth(request: &Request, admin_key: AdminKey) -> Result<(), Response> {
-    request
-        .header("X-TIAF-ADMIN-KEY")
-        .and_then(|k| if admin_key.eq_str(k) { Some(()) } else { None })
-        .ok_or_else(|| {
-            rouille::Response::json(&TiafBoringResponse::Error("invalid admin key".to_string()))
-                .with_status_code(401)
+// ROUTE_SCOPES is the scope each route needs: a method, or "*" for any, and a path, which ends
+// in "*" to match every path it begins. The first match wins; a route with no scope is open to
+// anyone, and a route not listed needs admin.
+// Paths are those within a chain, so /record is /api/v1/record on the default chain and
+// /api/v1/chains/{name}/record on a named one.
+const ROUTE_SCOPES: &[(&str, &str, Option<Scope>)] = &[
+    ("OPTIONS", "*", None),
+    ("POST", "/record", Some(Scope::Peer)),
+    ("POST", "/block", Some(Scope::Peer)),
+    ("POST", "/chain/compare", Some(Scope::Read)),
+    ("POST", "/data*", Some(Scope::Write)),
+    ("*", "/admin/*", Some(Scope::Admin)),
+    ("GET", "/*", Some(Scope::Read)),
+];
+
+fn route_scope(method: &str, path: &str) -> Option<Scope> {
+    ROUTE_SCOPES
+        .iter()
+        .find(|(m, p, _)| {
+            (*m == "*" || *m == method)
+                && match p.strip_suffix('*') {
+                    Some(prefix) => path.starts_with(prefix),
+                    None => path == *p,
+                }
         })
+        .map_or(Some(Scope::Admin), |(_, _, scope)| *scope)
 }
 
-#[allow(clippy::too_many_arguments)]
-pub fn launch_server(
-    node_id: String,
-    ip: String,
-    port: u16,
-    admin_key: AdminKey,
-    blockchain: Arc<RwLock<Blockchain>>,
-    mem_pool: Arc<RwLock<MemPool>>,
-    downstreams: Arc<RwLock<Downstreams>>,
-    upstreams: Arc<RwLock<Upstreams>>,
-) {
-    let logger = woody::new(woody::Level::Info);
+// namespaced finds the chain a request is for: the named chain its path begins
+// /api/v1/chains/{name} with, or else the default chain, when its path begins /api/v1. The
+// request is given back with that much of its path taken off.
+fn namespaced(namespaces: &Namespaces, request: &Request) -> Option<(Arc<Namespace>, Request)> {
+    let url = request.url();
+    let (name, prefix) = match url.strip_prefix("/api/v1/chains/") {
+        Some(rest) => {
+            let name = rest.split('/').next().unwrap_or_default();
+            (name.to_string(), format!("/api/v1/chains/{name}"))
+        }
+        None => (DEFAULT.to_string(), "/api/v1".to_string()),
+    };
+    // a prefix with escapes in it cannot be taken off.
+    if !request.raw_url().starts_with(&prefix) {
+        return None;
+    }
+    let ns = namespaces.get(&name)?;
+    Some((ns, request.remove_prefix(&prefix)?))
+}
 
-    let endpoint = format!("{ip}:{port}");
+// log_access logs who may use ns. Only the names of keys are ever logged.
+fn log_access(logger: &Mutex<Logger>, ns: &Namespace, in_cluster: bool) {
+    let access = &ns.access;
+    if access.keys().is_empty() {
+        logger.lock().unwrap().warn(notes!(
+            "ts",
+            chrono::Utc::now().to_rfc3339(),
+            "msg",
+            "no keys are configured, so only anonymous requests are served".to_string(),
+            "chain",
+            ns.name().to_string()
+        ));
+    } else {
+        logger.lock().unwrap().info(notes!(
+            "ts",
+            chrono::Utc::now().to_rfc3339(),
+            "msg",
+            "keys loaded".to_string(),
+            "chain",
+            ns.name().to_string(),
+            "names",
+            access.keys().names().join(",")
+        ));
+    }
+    let anonymous: Vec<String> = access
+        .anonymous()
+        .iter()
+        .filter(|s| !(in_cluster && **s == Scope::Peer))
+        .map(|s| s.to_string())
+        .collect();
     logger.lock().unwrap().info(notes!(
         "ts",
         chrono::Utc::now().to_rfc3339(),
         "msg",
-        "starting http server".to_string(),
-        "endpoint",
-        endpoint.clone()
+        "anonymous requests allowed".to_string(),
+        "chain",
+        ns.name().to_string(),
+        "scopes",
+        anonymous.join(",")
     ));
+}
 
-    rouille::start_server(endpoint, move |request: &Request| {
-        use std::time::Instant;
+// authorize refuses a request its keys do not allow: 401 if it has no key we accept, 403 if
+// its key lacks the scope. A fellow member of the cluster may hand over records and read, and
+// in a cluster, only members and holders of peer keys may.
+fn authorize(
+    request: &Request,
+    access: &Access,
+    peer: bool,
+    in_cluster: bool,
+) -> Result<(), Response> {
+    let scope = match route_scope(request.method(), &request.url()) {
+        Some(scope) => scope,
+        None => return Ok(()),
+    };
+    if peer && matches!(scope, Scope::Peer | Scope::Read) {
+        return Ok(());
+    }
+    let presented = [
+        request.header("X-TIAF-ADMIN-KEY"),
+        request.header("X-TIAF-API-KEY"),
+    ];
+    let checked = if in_cluster {
+        access.check_in_cluster(&presented, scope)
+    } else {
+        access.check(&presented, scope)
+    };
+    checked.map_err(|denied| match denied {
+        Denied::Unauthenticated => rouille::Response::json(&TiafBoringResponse::Error(
+            "missing or invalid key".to_string(),
+        ))
+        .with_status_code(401),
+        Denied::Forbidden(scope) => rouille::Response::json(&TiafBoringResponse::Error(format!(
+            "key lacks the {scope} scope"
+        )))
+        .with_status_code(403),
+    })
+}
 
-        let start = Instant::now();
-        let ts = chrono::Utc::now();
+// client_of names who a request is from, for quotas: the first key it presents that we accept,
+// or its address.
+fn client_of(request: &Request, access: &Access, remote: IpAddr) -> String {
+    let name = [
+        request.header("X-TIAF-API-KEY"),
+        request.header("X-TIAF-ADMIN-KEY"),
+    ]
+    .into_iter()
+    .flatten()
+    .find_map(|key| access.keys().verify(key));
+    quota::client_id(name, remote)
+}
 
-        let mut result = router!(request,
-            (GET) (/) => {
-                rouille::Response::text("index")
-            },
+fn unauthenticated(why: String) -> Response {
+    rouille::Response::json(&TiafBoringResponse::Error(why)).with_status_code(401)
+}
 
-            (GET) (/healthz) => {
-                rouille::Response::text("OK")
-            },
+// Peer is a request signed by a fellow member of the cluster. Checking the signature reads the
+// body, so the request is served from a copy holding it.
+struct Peer {
+    request: Request,
+    node: String,
+    signature: String,
+}
 
-            (GET) (/api/v1/chain) => {
-                let b = blockchain.read().unwrap();
-                rouille::Response::json(b.deref())
-            },
+// peer_signed checks the signature of a request that has one.
+fn peer_signed(request: &Request, cluster: Option<&ClusterKey>) -> Result<Option<Peer>, String> {
+    let signature = match request.header(SIGNATURE_HEADER) {
+        Some(signature) => signature.to_string(),
+        None => return Ok(None),
+    };
+    let cluster = cluster.ok_or("this node is in no cluster")?;
+    let node = request
+        .header(NODE_HEADER)
+        .ok_or("signed request names no node")?;
+    let timestamp = request
+        .header(TIMESTAMP_HEADER)
+        .ok_or("signed request has no time")?;
+    let mut body = vec![];
+    if let Some(data) = request.data() {
+        data.take(MAX_BATCH_BYTES + 1)
+            .read_to_end(&mut body)
+            .map_err(|e| format!("failed to read body: {e}"))?;
+    }
+    if body.len() as u64 > MAX_BATCH_BYTES {
+        return Err(format!(
+            "a peer request may be at most {MAX_BATCH_BYTES} bytes"
+        ));
+    }
+    cluster.verify_request(
+        request.method(),
+        request.raw_url(),
+        node,
+        timestamp,
+        &signature,
+        &body,
+    )?;
+    let headers = request
+        .headers()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    let (addr, method, url) = (*request.remote_addr(), request.method(), request.raw_url());
+    let copy = if request.is_secure() {
+        Request::fake_https_from(addr, method, url, headers, body)
+    } else {
+        Request::fake_http_from(addr, method, url, headers, body)
+    };
+    Ok(Some(Peer {
+        request: copy,
+        node: node.to_string(),
+        signature,
+    }))
+}
 
-            (GET) (/api/v1/chain/tail/{n: u64}) => {
-                let b= blockchain.read().unwrap();
-                let blocks = b.tail(n);
-                let nublocks = blocks.iter()
-                .map(|b| (**b).clone()).collect();
-                let response = api::TiafPartialChain{
-                    partial_blocks: nublocks,
-                    total_length: b.length(),
-                };
-                rouille::Response::json(&response)
-            },
+// sign_response signs our answer to a peer, so it knows the answer came from a member.
+fn sign_response(result: &mut Response, cluster: &ClusterKey, request_signature: &str) {
+    // an upgraded connection streams, and is left unsigned.
+    if result.upgrade.is_some() {
+        return;
+    }
+    let data = std::mem::replace(&mut result.data, rouille::ResponseBody::empty());
+    let mut body = vec![];
+    if let Err(e) = data.into_reader_and_size().0.read_to_end(&mut body) {
+        *result = rouille::Response::json(&TiafBoringResponse::Error(format!(
+            "failed to sign response: {e}"
+        )))
+        .with_status_code(500);
+        return;
+    }
+    let signature = cluster.sign_response(request_signature, result.status_code, &body);
+    result.data = rouille::ResponseBody::from_data(body);
+    result
+        .headers
+        .push((SIGNATURE_HEADER.into(), signature.into()));
+}
 
-            (GET) (/api/v1/chain/since/{hash: String}) => {
-                let b = blockchain.read().unwrap();
-                let blocks = b.since(&hash);
-                match blocks {
-                    Ok(blocks) => {
-                        let nublocks = blocks.iter()
-                        .map(|b| (**b).clone()).collect();
-                        let response = api::TiafPartialChain{
-                            partial_blocks: nublocks,
-                            total_length: b.length()
-                        };
-                        rouille::Response::json(&response)
-                    },
-                    Err(e) => {
-                        logger.lock().unwrap().error(notes!("ts", chrono::Utc::now().to_rfc3339(), "error", e.to_string()));
-                        rouille::Response::json(&TiafBoringResponse::Error(e)).with_status_code(500)
-                    }
+// query_error reports a failed query: 400 for queries and cursors we cannot read, or whose
+// arithmetic overflows, 422 for queries that read fine but cannot be evaluated.
+fn query_error(e: QueryError) -> Response {
+    let code = match e.kind {
+        QueryErrorKind::Syntax | QueryErrorKind::Cursor | QueryErrorKind::Overflow => 400,
+        QueryErrorKind::Evaluation => 422,
+    };
+    rouille::Response::json(&e).with_status_code(code)
+}
+
+// schema_error refuses an entry that does not conform to the schema it names.
+fn schema_error(e: SchemaError) -> Response {
+    rouille::Response::json(&e).with_status_code(422)
+}
+
+// pool_full refuses a write the pool has no room for. The write was not taken, and the client
+// should send it again later: by the time the block policy's latency has passed, the pool will
+// have been sealed.
+fn pool_full(e: MemPoolError, policy: &BlockPolicy) -> Response {
+    let MemPoolError::Full = e;
+    rouille::Response::json(&TiafBoringResponse::Error("mempool is full".to_string()))
+        .with_status_code(503)
+        .with_additional_header("Retry-After", policy.max_latency_secs.max(1).to_string())
+}
+
+// limited refuses a write from a client that is over its rate or its share of the pool.
+fn limited(l: Limited, policy: &BlockPolicy) -> Response {
+    let (msg, retry_after) = match l {
+        Limited::RateLimited(wait) => ("rate limited", wait.as_secs_f64().ceil() as u64),
+        Limited::OverQuota => ("mempool quota exceeded", policy.max_latency_secs),
+    };
+    rouille::Response::json(&TiafBoringResponse::Error(msg.to_string()))
+        .with_status_code(429)
+        .with_additional_header("Retry-After", retry_after.max(1).to_string())
+}
+
+// unsealed checks a record a peer hands over for pooling. Only sealed records are redacted, so a
+// redacted record is refused, lest it be sealed here under a key the chain does not trust.
+fn unsealed(r: &Record) -> Result<(), String> {
+    if r.is_redacted() {
+        return Err(format!(
+            "record {} is redacted and cannot be pooled",
+            r.hash
+        ));
+    }
+    r.validate()
+}
+
+// admit puts r into the pool, first dropping the records that have expired. Records that leave
+// the pool unsealed are logged, with why.
+fn admit(
+    mp: &mut MemPool,
+    r: Record,
+    client: Option<&str>,
+    logger: &Mutex<Logger>,
+) -> Result<(), MemPoolError> {
+    log_dropped(logger, mp.expire(), "expired");
+    let evicted = match client {
+        Some(client) => mp.put_from(r, client)?,
+        None => mp.put(r)?,
+    };
+    if let Some(evicted) = evicted {
+        log_dropped(logger, vec![evicted], "evicted from a full mempool");
+    }
+    Ok(())
+}
+
+// Refusal is why a write was not taken.
+enum Refusal {
+    Schema(SchemaError),
+    KeyReused,
+    Limited(Limited),
+    Full(MemPoolError),
+}
+
+impl Refusal {
+    fn status(&self) -> u16 {
+        match self {
+            Refusal::Schema(_) => 422,
+            Refusal::KeyReused => 409,
+            Refusal::Limited(_) => 429,
+            Refusal::Full(_) => 503,
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            Refusal::Schema(e) => e.to_string(),
+            Refusal::KeyReused => {
+                "idempotency key or uuid was already used for a different entry".to_string()
+            }
+            Refusal::Limited(Limited::RateLimited(_)) => "rate limited".to_string(),
+            Refusal::Limited(Limited::OverQuota) => "mempool quota exceeded".to_string(),
+            Refusal::Full(_) => "mempool is full".to_string(),
+        }
+    }
+
+    fn response(self, policy: &BlockPolicy) -> Response {
+        match self {
+            Refusal::Schema(e) => schema_error(e),
+            Refusal::Limited(l) => limited(l, policy),
+            Refusal::Full(e) => pool_full(e, policy),
+            Refusal::KeyReused => {
+                rouille::Response::json(&TiafBoringResponse::Error(self.message()))
+                    .with_status_code(self.status())
+            }
+        }
+    }
+}
+
+// Ingest is everything a write from a client touches on its way into the pool.
+struct Ingest<'a> {
+    blockchain: &'a RwLock<Blockchain>,
+    mem_pool: &'a RwLock<MemPool>,
+    downstreams: &'a RwLock<Downstreams>,
+    limiter: &'a Mutex<Limiter>,
+    receipts: &'a Mutex<Receipts>,
+    logger: &'a Mutex<Logger>,
+}
+
+impl Ingest<'_> {
+    // submit takes one write from client into the pool, returning its receipt. A write repeated
+    // under the same key gets the first one's receipt, so long as its record is still pending or
+    // sealed, and one repeating a client-chosen uuid gets the receipt of the record that has it.
+    fn submit(
+        &self,
+        client: &str,
+        key: Option<String>,
+        put: api::RecordPut,
+    ) -> Result<api::TiafReceipt, Refusal> {
+        self.blockchain
+            .read()
+            .unwrap()
+            .schemas()
+            .validate(&put.data)
+            .map_err(Refusal::Schema)?;
+
+        // the pool and the receipts stay locked from looking the write up until its receipt is
+        // kept, so that tries of one write racing each other make one record between them.
+        let mut mp = self.mem_pool.write().unwrap();
+        let mut receipts = self.receipts.lock().unwrap();
+        let key = key.or(put.uuid.map(|uuid| format!("uuid:{uuid}")));
+        match key
+            .as_ref()
+            .map(|key| receipts.replay(client, key, &put.data))
+        {
+            Some(Replay::Conflict) => return Err(Refusal::KeyReused),
+            Some(Replay::Repeat(receipt)) => {
+                let kept = self.blockchain.read().unwrap().record_seen(&receipt.record)
+                    || mp.contains_hash(&receipt.record);
+                if kept {
+                    return Ok(api::TiafReceipt {
+                        duplicate: true,
+                        ..receipt
+                    });
                 }
-            },
+            }
+            Some(Replay::New) | None => {}
+        }
+        // a uuid is kept on the record itself, so it holds for writes from other clients, from
+        // peers, and from before the window or a restart.
+        if let Some(uuid) = put.uuid {
+            let b = self.blockchain.read().unwrap();
+            if let Some(r) = b.record_with_uuid(&uuid).or(mp.record_with_uuid(&uuid)) {
+                if r.entry != put.data && !r.is_redacted() {
+                    return Err(Refusal::KeyReused);
+                }
+                return Ok(api::TiafReceipt::of(r, true));
+            }
+        }
 
-            (POST) (/api/v1/chain/compare) => {
-                let body: Blockchain = try_or_400!(rouille::input::json_input(request));
-                let b = blockchain.read().unwrap();
-                let result = b.compare_other_chain(&body);
+        self.limiter
+            .lock()
+            .unwrap()
+            .check(client, mp.pending_from(client))
+            .map_err(Refusal::Limited)?;
+        let r = match put.uuid {
+            Some(uuid) => Record::with_uuid(put.data, uuid),
+            None => Record::new(put.data),
+        };
+        let receipt = api::TiafReceipt::of(&r, false);
+        let keyed = key.map(|key| (key, r.entry.clone()));
+        if let Err(e) = admit(&mut mp, r, Some(client), self.logger) {
+            self.logger.lock().unwrap().warn(notes!(
+                "ts",
+                chrono::Utc::now().to_rfc3339(),
+                "msg",
+                "mempool full, data refused".to_string()
+            ));
+            return Err(Refusal::Full(e));
+        }
+        self.limiter.lock().unwrap().admitted(client);
+        if let Some((key, entry)) = keyed {
+            receipts.keep(client, &key, &entry, receipt.clone());
+        }
+        Ok(receipt)
+    }
 
-                logger.lock().unwrap().info(notes!("result", format!("{:?}", result)));
-                rouille::Response::json(&api::TiafCompareResult{ result })
-            },
-            (OPTIONS) (/api/v1/chain/compare) => {
-                rouille::Response::json(&TiafBoringResponse::Ok)
-            },
+    // submit_block writes every entry of puts into one new block, bypassing the pool, or writes
+    // none of them. On refusal, the result of each entry says why. Each entry written counts as
+    // a write against client's limits, and the block is handed to downstreams, since its records
+    // were never pending for them to be pushed.
+    fn submit_block(
+        &self,
+        client: &str,
+        puts: Vec<api::RecordPut>,
+    ) -> Result<api::TiafBatchResult, (u16, api::TiafBatchResult)> {
+        let refusals: Vec<Option<Refusal>> = {
+            let b = self.blockchain.read().unwrap();
+            puts.iter()
+                .map(|put| b.schemas().validate(&put.data).err().map(Refusal::Schema))
+                .collect()
+        };
+        if refusals.iter().any(Option::is_some) {
+            let items = refusals
+                .into_iter()
+                .map(|refusal| match refusal {
+                    Some(refusal) => {
+                        api::TiafBatchItem::refused(refusal.status(), refusal.message())
+                    }
+                    None => api::TiafBatchItem::refused(424, NOT_WRITTEN.to_string()),
+                })
+                .collect();
+            return Err((422, api::TiafBatchResult { items, block: None }));
+        }
 
-            (GET) (/api/v1/statistics) => {
-                let b = blockchain.read().unwrap();
-                let mp = mem_pool.read().unwrap();
-                rouille::Response::json(
-                    &api::TiafStatistics{
-                        node_id: node_id.clone(),
-                        chain_length: b.length(),
-                        pool_size: mp.length() as u64,
-                        downstream_count: downstreams.read().unwrap().deref().downstreams().len() as u64,
-                        upstream_count: upstreams.read().unwrap().deref().upstreams().len() as u64,
+        let mut mp = self.mem_pool.write().unwrap();
+        log_dropped(self.logger, mp.expire(), "expired");
+        let mut b = self.blockchain.write().unwrap();
+        // as with submit, an entry repeating a uuid that a record on the chain, in the pool or
+        // earlier in the batch has gets that record's receipt, unless its entry differs.
+        let mut records: Vec<Record> = vec![];
+        let mut items = vec![];
+        let mut reused = false;
+        for put in puts {
+            let seen = put.uuid.and_then(|uuid| {
+                b.record_with_uuid(&uuid)
+                    .or(mp.record_with_uuid(&uuid))
+                    .or(records.iter().find(|r| r.uuid == uuid))
+            });
+            match seen {
+                Some(r) if r.entry != put.data && !r.is_redacted() => {
+                    reused = true;
+                    items.push(api::TiafBatchItem::refused(
+                        Refusal::KeyReused.status(),
+                        Refusal::KeyReused.message(),
+                    ));
+                }
+                Some(r) => items.push(api::TiafBatchItem::written(api::TiafReceipt::of(r, true))),
+                None => {
+                    let r = match put.uuid {
+                        Some(uuid) => Record::with_uuid(put.data, uuid),
+                        None => Record::new(put.data),
+                    };
+                    items.push(api::TiafBatchItem::written(api::TiafReceipt::of(&r, false)));
+                    records.push(r);
+                }
+            }
+        }
+        if reused {
+            let items = items
+                .into_iter()
+                .map(|item| match item.receipt {
+                    Some(_) => api::TiafBatchItem::refused(424, NOT_WRITTEN.to_string()),
+                    None => item,
                 })
-            },
+                .collect();
+            return Err((
+                Refusal::KeyReused.status(),
+                api::TiafBatchResult { items, block: None },
+            ));
+        }
+        // a batch that only repeats writes already made seals nothing, and is charged nothing.
+        if records.is_empty() {
+            return Ok(api::TiafBatchResult { items, block: None });
+        }
 
-            // this is the conventional place to write rows to the data table
-            (POST) (/api/v1/data) => {
-                let body: api::RecordPut = try_or_400!(rouille::input::json_input(request));
+        if let Err(l) = self.limiter.lock().unwrap().check_writes(
+            client,
+            mp.pending_from(client),
+            records.len(),
+        ) {
+            let refusal = Refusal::Limited(l);
+            let items = items
+                .iter()
+                .map(|_| api::TiafBatchItem::refused(refusal.status(), refusal.message()))
+                .collect();
+            return Err((
+                refusal.status(),
+                api::TiafBatchResult { items, block: None },
+            ));
+        }
+        let written = records.len();
+        if let Err(e) = b.append_new_records(records) {
+            let items = items
+                .iter()
+                .map(|_| api::TiafBatchItem::refused(500, e.clone()))
+                .collect();
+            return Err((500, api::TiafBatchResult { items, block: None }));
+        }
+        let mut limiter = self.limiter.lock().unwrap();
+        for _ in 0..written {
+            limiter.admitted(client);
+        }
+        let block = b.get(b.length() - 1).cloned();
+        let hash = block.as_ref().map(|block| block.hash.clone());
+        if let Some(block) = block {
+            let given_up = self.downstreams.write().unwrap().hand_over(block);
+            for block in given_up {
+                self.logger.lock().unwrap().warn(notes!(
+                    "ts",
+                    chrono::Utc::now().to_rfc3339(),
+                    "msg",
+                    "gave up pushing block to downstreams".to_string(),
+                    "block",
+                    block.hash
+                ));
+            }
+        }
+        Ok(api::TiafBatchResult { items, block: hash })
+    }
+}
 
-                let mut mp = mem_pool.write().unwrap();
-                let r = Record::new(body.data);
-                _ = mp.put(r);
-                // log the write
-                logger.lock().unwrap().info(notes!("ts", chrono::Utc::now().to_rfc3339(), "msg", "data added to mempool".to_string()));
+// NOT_WRITTEN is the result of an entry of an atomic batch that was fine, when another was not.
+const NOT_WRITTEN: &str = "not written: another entry of the atomic batch was refused";
 
-                rouille::Response::json(&TiafBoringResponse::Ok)
-            },
-            (OPTIONS) (/api/v1/data) => {
-                rouille::Response::json(&TiafBoringResponse::Ok)
-            },
+// MAX_BATCH_BYTES bounds the body of a batch.
+const MAX_BATCH_BYTES: u64 = 16 * 1024 * 1024;
 
-            // the record endpoint is used for sharing new records between peers.
-             (POST) (/api/v1/record) => {
-                let r: Record = try_or_400!(rouille::input::json_input(request));
-                let mut mp = mem_pool.write().unwrap();
-                _ = mp.put(r);
-                // log the write
-                logger.lock().unwrap().info(notes!("ts", chrono::Utc::now().to_rfc3339(), "msg", "record added to mempool".to_string()));
+// batch_entries reads the body of a batch: a JSON array of entries, or with an NDJSON content
+// type, one entry per line. A line that cannot be read fails only that entry.
+fn batch_entries(request: &Request) -> Result<Vec<Result<api::RecordPut, String>>, String> {
+    let mut body = String::new();
+    request
+        .data()
+        .ok_or("the body has already been read")?
+        .take(MAX_BATCH_BYTES + 1)
+        .read_to_string(&mut body)
+        .map_err(|e| format!("failed to read body: {e}"))?;
+    if body.len() as u64 > MAX_BATCH_BYTES {
+        return Err(format!("a batch may be at most {MAX_BATCH_BYTES} bytes"));
+    }
+    let ndjson = request
+        .header("Content-Type")
+        .is_some_and(|t| t.contains("ndjson"));
+    if !ndjson {
+        let puts: Vec<api::RecordPut> =
+            serde_json::from_str(&body).map_err(|e| format!("failed to read batch: {e}"))?;
+        return Ok(puts.into_iter().map(Ok).collect());
+    }
+    Ok(body
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).map_err(|e| format!("failed to read entry: {e}")))
+        .collect())
+}
 
-                rouille::Response::json(&TiafBoringResponse::Ok)
-            },
-            (OPTIONS) (/api/v1/record) => {
-                rouille::Response::json(&TiafBoringResponse::Ok)
-            },
-            (GET) (/api/v1/query) => {
+fn log_dropped(logger: &Mutex<Logger>, records: Vec<Record>, reason: &str) {
+    for r in records {
+        logger.lock().unwrap().warn(notes!(
+            "ts",
+            chrono::Utc::now().to_rfc3339(),
+            "msg",
+            format!("record dropped unsealed: {reason}"),
+            "record",
+            r.hash
+        ));
+    }
+}
 
-                match request.get_param("q")  {
-                    Some(q) =>  {
-                        let clozed_query = query_chain::Query::new(q).unwrap().parse();
+// no_kv_view refuses key-value reads on a chain that keeps no view. It is not a 404, which
+// says the key has no value.
+fn no_kv_view() -> Response {
+    rouille::Response::json(&TiafBoringResponse::Error(
+        "this node keeps no key-value view".to_string(),
+    ))
+    .with_status_code(501)
+}
 
-                        let b = blockchain.read().unwrap();
+// Node is what every chain a node hosts shares.
+struct Node {
+    id: String,
+    namespaces: Arc<Namespaces>,
+    logger: &'static Mutex<Logger>,
+    // in_cluster is whether the node can tell members of its cluster, by signature or client
+    // certificate, from anyone else.
+    in_cluster: bool,
+}
 
-                        match b.query(clozed_query) {
-                            Ok(result) => rouille::Response::json(&result),
-                            Err(e) => {
-                                logger.lock().unwrap().error(notes!("ts", chrono::Utc::now().to_rfc3339(), "error", e.to_string()));
-                                return rouille::Response::json(&TiafBoringResponse::Error(e))
-                                .with_status_code(400);
-                            }
-                        }
+// route serves a request made of the chain ns, its path relative to the chain, by client. A
+// member of the cluster relays records to us without counting against any client's quota.
+fn route(
+    node: &Node,
+    ns: &Arc<Namespace>,
+    request: &Request,
+    client: &str,
+    member: bool,
+) -> Response {
+    let (node_id, logger) = (&node.id, node.logger);
+    let (blockchain, mem_pool) = (&ns.blockchain, &ns.mem_pool);
+    let (downstreams, upstreams) = (&ns.downstreams, &ns.upstreams);
+    let (limiter, block_policy) = (&ns.limiter, &ns.config().block_policy);
+    let ingest = Ingest {
+        blockchain,
+        mem_pool,
+        downstreams,
+        limiter,
+        receipts: &ns.receipts,
+        logger,
+    };
+    router!(request,
+        (GET) (/chain) => {
+            let b = blockchain.read().unwrap();
+            rouille::Response::json(b.deref())
+        },
+
+        (GET) (/chain/tail/{n: u64}) => {
+            let b= blockchain.read().unwrap();
+            let blocks = b.tail(n);
+            let nublocks = blocks.iter()
+            .map(|b| (**b).clone()).collect();
+            let response = api::TiafPartialChain{
+                partial_blocks: nublocks,
+                total_length: b.length(),
+            };
+            rouille::Response::json(&response)
+        },
+
+        (GET) (/chain/since/{hash: String}) => {
+            let b = blockchain.read().unwrap();
+            let blocks = b.since(&hash);
+            match blocks {
+                Ok(blocks) => {
+                    let nublocks = blocks.iter()
+                    .map(|b| (**b).clone()).collect();
+                    let response = api::TiafPartialChain{
+                        partial_blocks: nublocks,
+                        total_length: b.length()
+                    };
+                    rouille::Response::json(&response)
+                },
+                Err(e) => {
+                    logger.lock().unwrap().error(notes!("ts", chrono::Utc::now().to_rfc3339(), "error", e.to_string()));
+                    rouille::Response::json(&TiafBoringResponse::Error(e)).with_status_code(500)
+                }
+            }
+        },
+
+        (POST) (/chain/compare) => {
+            let body: Blockchain = try_or_400!(rouille::input::json_input(request));
+            let b = blockchain.read().unwrap();
+            let result = b.compare_other_chain(&body);
+
+            logger.lock().unwrap().info(notes!("result", format!("{:?}", result)));
+            rouille::Response::json(&api::TiafCompareResult{ result })
+        },
+        (OPTIONS) (/chain/compare) => {
+            rouille::Response::json(&TiafBoringResponse::Ok)
+        },
+
+        (GET) (/statistics) => {
+            let b = blockchain.read().unwrap();
+            let mp = mem_pool.read().unwrap();
+            rouille::Response::json(
+                &api::TiafStatistics{
+                    node_id: node_id.clone(),
+                    chain_length: b.length(),
+                    pool_size: mp.length() as u64,
+                    pool_capacity: mp.capacity() as u64,
+                    pool_dropped: mp.dropped(),
+                    downstream_count: downstreams.read().unwrap().deref().downstreams().len() as u64,
+                    upstream_count: upstreams.read().unwrap().deref().upstreams().len() as u64,
+                    clients: limiter.lock().unwrap().counters(),
+            })
+        },
+
+        // this is the conventional place to write rows to the data table
+        (POST) (/data) => {
+            let body: api::RecordPut = try_or_400!(rouille::input::json_input(request));
+            let key = request.header("Idempotency-Key").map(str::to_string);
+            match ingest.submit(client, key, body) {
+                Ok(receipt) => {
+                    // log the write
+                    logger.lock().unwrap().info(notes!("ts", chrono::Utc::now().to_rfc3339(), "msg", "data added to mempool".to_string()));
+                    rouille::Response::json(&receipt)
+                }
+                Err(refusal) => refusal.response(block_policy),
+            }
+        },
+        (OPTIONS) (/data) => {
+            rouille::Response::json(&TiafBoringResponse::Ok)
+        },
+
+        // writes many entries at once, each with its own result. With atomic=true, the
+        // entries are sealed together into a block of their own, or none are written.
+        (POST) (/data/batch) => {
+            let entries = match batch_entries(request) {
+                Ok(entries) => entries,
+                Err(e) => return rouille::Response::json(&TiafBoringResponse::Error(e)).with_status_code(400),
+            };
+            if request.get_param("atomic").is_some_and(|a| a == "true") {
+                let puts = match entries.into_iter().collect::<Result<Vec<_>, _>>() {
+                    Ok(puts) => puts,
+                    Err(e) => return rouille::Response::json(&TiafBoringResponse::Error(e)).with_status_code(400),
+                };
+                // an empty block would be sealed, and pushed, for nothing.
+                if puts.is_empty() {
+                    return rouille::Response::json(&TiafBoringResponse::Error("an atomic batch needs at least one entry".to_string())).with_status_code(400);
+                }
+                return match ingest.submit_block(client, puts) {
+                    Ok(result) => {
+                        logger.lock().unwrap().info(notes!("ts", chrono::Utc::now().to_rfc3339(), "msg", "batch sealed".to_string(), "records", result.items.len().to_string()));
+                        rouille::Response::json(&result)
                     }
-                    None => {
-                        rouille::Response::json(&TiafBoringResponse::Error("missing query parameter".to_string()))
-                        .with_status_code(400)
+                    Err((status, result)) => rouille::Response::json(&result).with_status_code(status),
+                };
+            }
+            let items: Vec<api::TiafBatchItem> = entries.into_iter().map(|entry| match entry {
+                Ok(put) => match ingest.submit(client, None, put) {
+                    Ok(receipt) => api::TiafBatchItem::written(receipt),
+                    Err(refusal) => api::TiafBatchItem::refused(refusal.status(), refusal.message()),
+                },
+                Err(e) => api::TiafBatchItem::refused(400, e),
+            }).collect();
+            logger.lock().unwrap().info(notes!("ts", chrono::Utc::now().to_rfc3339(), "msg", "batch added to mempool".to_string(),
+                "records", items.iter().filter(|i| i.receipt.is_some()).count().to_string()));
+            rouille::Response::json(&api::TiafBatchResult{ items, block: None })
+        },
+        (OPTIONS) (/data/batch) => {
+            rouille::Response::json(&TiafBoringResponse::Ok)
+        },
+
+        // the record endpoint is used for sharing new records between peers.
+         (POST) (/record) => {
+            let r: Record = try_or_400!(rouille::input::json_input(request));
+            if let Err(e) = unsealed(&r) {
+                return rouille::Response::json(&TiafBoringResponse::Error(e)).with_status_code(400);
+            }
+            if let Err(e) = blockchain.read().unwrap().schemas().validate(&r.entry) {
+                return schema_error(e);
+            }
+            let mut mp = mem_pool.write().unwrap();
+            let client = (!member).then_some(client);
+            if let Some(client) = client {
+                if let Err(l) = limiter.lock().unwrap().check(client, mp.pending_from(client)) {
+                    return limited(l, block_policy);
+                }
+            }
+            if let Err(e) = admit(&mut mp, r, client, logger) {
+                logger.lock().unwrap().warn(notes!("ts", chrono::Utc::now().to_rfc3339(), "msg", "mempool full, record refused".to_string()));
+                return pool_full(e, block_policy);
+            }
+            if let Some(client) = client {
+                limiter.lock().unwrap().admitted(client);
+            }
+            // log the write
+            logger.lock().unwrap().info(notes!("ts", chrono::Utc::now().to_rfc3339(), "msg", "record added to mempool".to_string()));
+
+            rouille::Response::json(&TiafBoringResponse::Ok)
+        },
+        (OPTIONS) (/record) => {
+            rouille::Response::json(&TiafBoringResponse::Ok)
+        },
+
+        // a peer hands over a block it sealed outside its pool. It is appended if it follows our
+        // tip; otherwise its records are pooled, to be sealed here like any others.
+        (POST) (/block) => {
+            let block: Block = try_or_400!(rouille::input::json_input(request));
+            if let Err(e) = block.validate() {
+                return rouille::Response::json(&TiafBoringResponse::Error(e)).with_status_code(400);
+            }
+            let mut mp = mem_pool.write().unwrap();
+            let mut b = blockchain.write().unwrap();
+            if b.block_seen(&block.hash) {
+                return rouille::Response::json(&TiafBoringResponse::Ok);
+            }
+            if block.previous_hash() == b.tip() {
+                if let Err(e) = b.append_blocks(vec![block]) {
+                    return rouille::Response::json(&TiafBoringResponse::Error(e)).with_status_code(400);
+                }
+            } else {
+                if let Some(e) = block.data.iter().find_map(|r| unsealed(r).err()) {
+                    return rouille::Response::json(&TiafBoringResponse::Error(e)).with_status_code(400);
+                }
+                for r in block.data.into_iter().filter(|r| !b.record_seen(&r.hash)) {
+                    if let Err(e) = admit(&mut mp, r, None, logger) {
+                        return pool_full(e, block_policy);
                     }
+                }
+            }
+            logger.lock().unwrap().info(notes!("ts", chrono::Utc::now().to_rfc3339(), "msg", "block handed over by peer".to_string()));
+            rouille::Response::json(&TiafBoringResponse::Ok)
+        },
+        (GET) (/query) => {
+
+            match request.get_param("q")  {
+                Some(q) =>  {
+                    let query = match query_chain::Query::new(q) {
+                        Ok(query) => query,
+                        Err(e) => return query_error(e),
+                    };
+                    // a caller holding the chain's keys may have encrypted fields decrypted for the query.
+                    let keys = match request.header(ENCRYPTION_KEY_HEADER).map(|h| Keyring::parse(ns.name(), h)) {
+                        Some(Ok(keys)) => Some(keys),
+                        Some(Err(e)) => return rouille::Response::json(&TiafBoringResponse::Error(e)).with_status_code(400),
+                        None => None,
+                    };
+                    let query = query.with_keys(keys);
+                    let cursor = request.get_param("cursor");
 
+                    let b = blockchain.read().unwrap();
+
+                    match query.run(&b, cursor.as_deref()) {
+                        Ok(result) => rouille::Response::json(&result),
+                        Err(e) => {
+                            logger.lock().unwrap().error(notes!("ts", chrono::Utc::now().to_rfc3339(), "error", e.to_string()));
+                            query_error(e)
+                        }
+                    }
                 }
-            },
-            (GET) (/api/v1/admin/upstream) => {
-                if let Err(x) = auth(request, admin_key.clone())  {
-                    return x;
+                None => {
+                    rouille::Response::json(&TiafBoringResponse::Error("missing query parameter".to_string()))
+                    .with_status_code(400)
                 }
 
-                let response = upstreams.read().unwrap();
-                rouille::Response::json(&response.to_api())
-            },
-            (POST) (/api/v1/admin/upstream) => {
-                if let Err(x) = auth(request, admin_key.clone())  {
-                    return x;
+            }
+        },
+        (GET) (/query/explain) => {
+            match request.get_param("q")  {
+                Some(q) =>  {
+                    match query_chain::Query::new(q) {
+                        Ok(query) => {
+                            let b = blockchain.read().unwrap();
+                            rouille::Response::json(&query.explain(&b))
+                        }
+                        Err(e) => query_error(e),
+                    }
                 }
-                let r: TiafUpstreams = try_or_400!(rouille::input::json_input(request));
-                // convert TiafUpstream to Upstreams
-                let mut input = upstreams.write().unwrap();
-                *input = Upstreams::from_api(&r);
-                rouille::Response::json(&TiafBoringResponse::Ok)
-            },
-            (OPTIONS) (/api/v1/admin/upstream) => {
-                rouille::Response::json(&TiafBoringResponse::Ok)
-            },
-            (POST) (/api/v1/admin/upstream/toggle) => {
-                if let Err(x) = auth(request, admin_key.clone())  {
-                    return x;
+                None => {
+                    rouille::Response::json(&TiafBoringResponse::Error("missing query parameter".to_string()))
+                    .with_status_code(400)
                 }
-                let mut input = upstreams.write().unwrap();
-                input.sweeping = ! input.sweeping;
-                rouille::Response::json(&TiafBoringResponse::Ok)
-            },
-            (OPTIONS) (/api/v1/admin/upstream/enable) => {
-                rouille::Response::json(&TiafBoringResponse::Ok)
-            },
+            }
+        },
+        (GET) (/kv/{key: String}) => {
+            let b = blockchain.read().unwrap();
+            let view = match b.kv() {
+                Some(view) => view,
+                None => return no_kv_view(),
+            };
+            match view.get(&b, &key) {
+                Some(entry) => rouille::Response::json(&entry),
+                None => rouille::Response::json(&TiafBoringResponse::Error(format!("no value for key {key}")))
+                    .with_status_code(404),
+            }
+        },
+        (GET) (/kv/{key: String}/history) => {
+            let b = blockchain.read().unwrap();
+            match b.kv() {
+                Some(view) => rouille::Response::json(&view.history(&b, &key)),
+                None => no_kv_view(),
+            }
+        },
 
+        // streams newly sealed data as Server-Sent Events; see subscribe::Subscription.
+        (GET) (/subscribe) => {
+            let query = match request.get_param("q").map(query_chain::Query::new) {
+                Some(Ok(query)) => Some(query),
+                Some(Err(e)) => return query_error(e),
+                None => None,
+            };
+            let since = request
+                .get_param("since")
+                .or_else(|| request.header("Last-Event-ID").map(|h| h.to_string()));
+            match Subscription::new(blockchain.clone(), query, since.as_ref()) {
+                Ok(mut subscription) => {
+                    rouille::Response::from_data("text/event-stream", subscription.wait(LONGEST_WAIT))
+                        .with_no_cache()
+                }
+                Err(e) => {
+                    rouille::Response::json(&TiafBoringResponse::Error(e)).with_status_code(400)
+                }
+            }
+        },
+        (GET) (/schema) => {
+            rouille::Response::json(&api::TiafSchemas{ ids: blockchain.read().unwrap().schemas().ids() })
+        },
+        (GET) (/schema/{id: String}) => {
+            match blockchain.read().unwrap().schemas().get(&id) {
+                Some(definition) => rouille::Response::json(definition),
+                None => rouille::Response::json(&TiafBoringResponse::Error(format!("no schema {id}")))
+                    .with_status_code(404),
+            }
+        },
+        // registers a schema, and records its definition on the chain.
+        (POST) (/admin/schema) => {
+            let definition: SchemaDefined = try_or_400!(rouille::input::json_input(request));
+            let id = definition.id();
+            let mut mp = mem_pool.write().unwrap();
+            let mut b = blockchain.write().unwrap();
+            let registry = b.schemas_mut();
+            if registry.get(&id).is_some_and(|d| d.schema != definition.schema) {
+                return rouille::Response::json(&TiafBoringResponse::Error(format!("schema {id} is already registered differently")))
+                    .with_status_code(409);
+            }
+            let entry = match events::encode(&definition) {
+                Ok(entry) => entry,
+                Err(e) => return rouille::Response::json(&TiafBoringResponse::Error(e)).with_status_code(500),
+            };
+            match registry.register(definition) {
+                Ok(true) => {
+                    if let Err(e) = admit(&mut mp, Record::new(entry), None, logger) {
+                        registry.unregister(&id);
+                        return pool_full(e, block_policy);
+                    }
+                    logger.lock().unwrap().info(notes!("ts", chrono::Utc::now().to_rfc3339(), "msg", "schema registered".to_string(), "schema", id));
+                    rouille::Response::json(&TiafBoringResponse::Ok)
+                }
+                Ok(false) => rouille::Response::json(&TiafBoringResponse::Ok),
+                Err(e) => rouille::Response::json(&TiafBoringResponse::Error(e)).with_status_code(400),
+            }
+        },
+        (OPTIONS) (/admin/schema) => {
+            rouille::Response::json(&TiafBoringResponse::Ok)
+        },
+        // seals the oldest pending records into a block now, rather than when the policy
+        // would.
+        (POST) (/admin/seal) => {
+            if !block_policy.seal_on_demand {
+                return rouille::Response::json(&TiafBoringResponse::Error("sealing on demand is disabled".to_string()))
+                    .with_status_code(403);
+            }
+            let mut mp = mem_pool.write().unwrap();
+            let mut b = blockchain.write().unwrap();
+            match block_policy.seal(&mut b, &mut mp) {
+                Ok(n) => {
+                    logger.lock().unwrap().info(notes!("ts", chrono::Utc::now().to_rfc3339(), "msg", "sealed on demand".to_string(), "records", n.to_string()));
+                    rouille::Response::json(&api::TiafSealed{
+                        records: n as u64,
+                        block: if n > 0 { b.get(b.length() - 1).map(|b| b.hash.clone()) } else { None },
+                    })
+                }
+                Err(e) => rouille::Response::json(&TiafBoringResponse::Error(e)).with_status_code(500),
+            }
+        },
+        (OPTIONS) (/admin/seal) => {
+            rouille::Response::json(&TiafBoringResponse::Ok)
+        },
+        // replaces the entry of a sealed record with a tombstone, keeping its hash. Peers take
+        // up the redaction when they next sync from us.
+        (POST) (/admin/redactions) => {
+            let body: api::TiafRedact = try_or_400!(rouille::input::json_input(request));
+            let mut b = blockchain.write().unwrap();
+            if !b.record_seen(&body.record) {
+                return rouille::Response::json(&TiafBoringResponse::Error(format!("record {} is not on the chain", body.record)))
+                    .with_status_code(404);
+            }
+            let reason = body.redaction.reason.clone();
+            match b.redact(&body.record, body.redaction) {
+                Ok(()) => {
+                    logger.lock().unwrap().info(notes!("ts", chrono::Utc::now().to_rfc3339(), "msg", "record redacted".to_string(), "record", body.record, "reason", reason));
+                    rouille::Response::json(&TiafBoringResponse::Ok)
+                }
+                Err(e) => rouille::Response::json(&TiafBoringResponse::Error(e)).with_status_code(403),
+            }
+        },
+        (OPTIONS) (/admin/redactions) => {
+            rouille::Response::json(&TiafBoringResponse::Ok)
+        },
+        // a signed snapshot of the chain, which a new node can begin from rather than replay it.
+        (GET) (/admin/snapshot) => {
+            match ns.snapshot() {
+                Ok(snapshot) => {
+                    logger.lock().unwrap().info(notes!("ts", chrono::Utc::now().to_rfc3339(), "msg", "snapshot taken".to_string(), "tip", snapshot.tip.clone()));
+                    rouille::Response::json(&snapshot)
+                }
+                Err(e) => rouille::Response::json(&TiafBoringResponse::Error(e)).with_status_code(404),
+            }
+        },
+        (GET) (/admin/mempool) => {
+            let mp = mem_pool.read().unwrap();
+            rouille::Response::json(&api::TiafMemPool{
+                capacity: mp.capacity() as u64,
+                pending: mp.pending().cloned().collect(),
+            })
+        },
+        // drops pending records unsealed: those named, or every one.
+        (POST) (/admin/mempool/purge) => {
+            let body: api::TiafPurge = try_or_400!(rouille::input::json_input(request));
+            let mut mp = mem_pool.write().unwrap();
+            let purged = if body.all { mp.reset() } else { mp.purge(&body.hashes) };
+            let hashes = purged.iter().map(|r| r.hash.clone()).collect();
+            log_dropped(logger, purged, "purged by an admin");
+            rouille::Response::json(&api::TiafPurged{ hashes })
+        },
+        (OPTIONS) (/admin/mempool/purge) => {
+            rouille::Response::json(&TiafBoringResponse::Ok)
+        },
+        (GET) (/admin/index) => {
+            let b = blockchain.read().unwrap();
+            rouille::Response::json(&api::TiafIndexes{ fields: b.indexes().fields() })
+        },
+        // replaces the set of indexed fields; new indexes are built over the chain so far.
+        (POST) (/admin/index) => {
+            let r: api::TiafIndexes = try_or_400!(rouille::input::json_input(request));
+            let mut b = blockchain.write().unwrap();
+            b.set_indexes(&r.fields);
+            logger.lock().unwrap().info(notes!("ts", chrono::Utc::now().to_rfc3339(), "msg", "indexes set".to_string(), "fields", r.fields.join(",")));
+            rouille::Response::json(&TiafBoringResponse::Ok)
+        },
+        (OPTIONS) (/admin/index) => {
+            rouille::Response::json(&TiafBoringResponse::Ok)
+        },
+        (GET) (/admin/upstream) => {
 
-            (GET) (/api/v1/admin/downstream) => {
-                let response = downstreams.read().unwrap();
-                rouille::Response::json(&response.to_api())
-            },
-            (POST) (/api/v1/admin/downstream) => {
-                if let Err(x) = auth(request, admin_key.clone())  {
-                    return x;
+            let response = upstreams.read().unwrap();
+            rouille::Response::json(&response.to_api())
+        },
+        (POST) (/admin/upstream) => {
+            let r: TiafUpstreams = try_or_400!(rouille::input::json_input(request));
+            // convert TiafUpstream to Upstreams
+            let mut input = upstreams.write().unwrap();
+            *input = Upstreams::from_api(&r).with_auth(input.auth().clone());
+            rouille::Response::json(&TiafBoringResponse::Ok)
+        },
+        (OPTIONS) (/admin/upstream) => {
+            rouille::Response::json(&TiafBoringResponse::Ok)
+        },
+        (POST) (/admin/upstream/toggle) => {
+            let mut input = upstreams.write().unwrap();
+            input.sweeping = ! input.sweeping;
+            rouille::Response::json(&TiafBoringResponse::Ok)
+        },
+        (OPTIONS) (/admin/upstream/enable) => {
+            rouille::Response::json(&TiafBoringResponse::Ok)
+        },
+
+
+        (GET) (/admin/downstream) => {
+            let response = downstreams.read().unwrap();
+            rouille::Response::json(&response.to_api())
+        },
+        (POST) (/admin/downstream) => {
+            let r: TiafDownstreams = try_or_400!(rouille::input::json_input(request));
+            // convert TiafDownstream to Downstreams
+            let mut input = downstreams.write().unwrap();
+            *input = input.reconfigured(Downstreams::from_api(&r));
+            rouille::Response::json(&TiafBoringResponse::Ok)
+        },
+        (OPTIONS) (/admin/downstream) => {
+            rouille::Response::json(&TiafBoringResponse::Ok)
+        },
+        (POST) (/admin/downstream/toggle) => {
+            // convert TiafDownstream to Downstreams
+            let mut input = downstreams.write().unwrap();
+            input.sweeping = ! input.sweeping;
+            rouille::Response::json(&TiafBoringResponse::Ok)
+        },
+
+        (GET) (/admin/node-id) => {
+            let response = api::TiafNode{
+                node_id: node_id.clone()
+            };
+            rouille::Response::json(&response)
+        },
+
+
+        // the chains of the node are managed from its default chain.
+        (GET) (/admin/chains) => {
+            if ns.name() != DEFAULT {
+                return rouille::Response::empty_404();
+            }
+            rouille::Response::json(&api::TiafChains{ names: node.namespaces.names() })
+        },
+        // hosts a new named chain, and starts its daemons.
+        (POST) (/admin/chains) => {
+            if ns.name() != DEFAULT {
+                return rouille::Response::empty_404();
+            }
+            let r: api::TiafCreateChain = try_or_400!(rouille::input::json_input(request));
+            if node.namespaces.get(&r.name).is_some() {
+                return rouille::Response::json(&TiafBoringResponse::Error(format!("chain {} already exists", r.name)))
+                    .with_status_code(409);
+            }
+            match node.namespaces.create(&r.name, r.config) {
+                Ok(created) => {
+                    logger.lock().unwrap().info(notes!("ts", chrono::Utc::now().to_rfc3339(), "msg", "chain created".to_string(), "chain", r.name));
+                    log_access(logger, &created, node.in_cluster);
+                    daemons::start(created);
+                    rouille::Response::json(&TiafBoringResponse::Ok)
                 }
-                let r: TiafDownstreams = try_or_400!(rouille::input::json_input(request));
-                // convert TiafDownstream to Downstreams
-                let mut input = downstreams.write().unwrap();
-                *input = Downstreams::from_api(&r);
-                rouille::Response::json(&TiafBoringResponse::Ok)
-            },
-            (OPTIONS) (/api/v1/admin/downstream) => {
-                rouille::Response::json(&TiafBoringResponse::Ok)
-            },
-            (POST) (api/v1/admin/downstream/toggle) => {
-                if let Err(x) = auth(request, admin_key.clone())  {
-                    return x;
+                Err(e) => rouille::Response::json(&TiafBoringResponse::Error(e)).with_status_code(400),
+            }
+        },
+        (OPTIONS) (/admin/chains) => {
+            rouille::Response::json(&TiafBoringResponse::Ok)
+        },
+
+        _ => rouille::Response::empty_404())
+}
+
+pub fn launch_server(
+    node_id: String,
+    ip: String,
+    port: u16,
+    namespaces: Arc<Namespaces>,
+    cluster: Option<ClusterKey>,
+    tls: Option<TlsConfig>,
+) {
+    let logger = woody::new(woody::Level::Info);
+
+    let endpoint = format!("{ip}:{port}");
+    logger.lock().unwrap().info(notes!(
+        "ts",
+        chrono::Utc::now().to_rfc3339(),
+        "msg",
+        "starting http server".to_string(),
+        "endpoint",
+        endpoint.clone(),
+        "tls",
+        tls.is_some().to_string()
+    ));
+    let tls_clients_verified = tls.as_ref().is_some_and(|t| t.client_ca_file.is_some());
+    let tls = tls.map(|config| tls::server_config(&config).expect("failed to load tls"));
+    let serving_tls = tls.is_some();
+    let origins = Origins::default();
+    let relayed = origins.clone();
+    let in_cluster = cluster.is_some() || tls_clients_verified;
+    for name in namespaces.names() {
+        if let Some(ns) = namespaces.get(&name) {
+            log_access(logger, &ns, in_cluster);
+        }
+    }
+    let node = Node {
+        id: node_id,
+        namespaces,
+        logger,
+        in_cluster,
+    };
+
+    let handler = move |request: &Request| {
+        use std::time::Instant;
+
+        let start = Instant::now();
+        let ts = chrono::Utc::now();
+
+        // over tls, every request comes through a relay, which knows who is on the other end.
+        let origin = if serving_tls {
+            relayed.of(request.remote_addr())
+        } else {
+            None
+        };
+        let remote = origin.map_or(request.remote_addr().ip(), |o| o.addr.ip());
+        let signed = peer_signed(request, cluster.as_ref());
+        let peer = signed.as_ref().ok().and_then(|p| p.as_ref());
+        let request = peer.map_or(request, |p| &p.request);
+        let member = peer.is_some() || origin.is_some_and(|o| o.client_cert);
+        let mut result = match &signed {
+            _ if serving_tls && origin.is_none() => rouille::Response::json(
+                &TiafBoringResponse::Error("requests must come over tls".to_string()),
+            )
+            .with_status_code(403),
+            Err(e) => unauthenticated(e.clone()),
+            Ok(_) => match namespaced(&node.namespaces, request) {
+                Some((ns, request)) => {
+                    let mut result = match authorize(&request, &ns.access, member, node.in_cluster)
+                    {
+                        Err(refused) => refused,
+                        Ok(()) => match request.header(GENESIS_HEADER) {
+                            Some(theirs) if theirs != ns.genesis() => rouille::Response::json(
+                                &TiafBoringResponse::Error("peer is on another chain".to_string()),
+                            )
+                            .with_status_code(409),
+                            _ => {
+                                let client = client_of(&request, &ns.access, remote);
+                                route(&node, &ns, &request, &client, member)
+                            }
+                        },
+                    };
+                    // peers check they are on the same chain as we are by this.
+                    result
+                        .headers
+                        .push((GENESIS_HEADER.into(), ns.genesis().clone().into()));
+                    result
                 }
-                // convert TiafDownstream to Downstreams
-                let mut input = downstreams.write().unwrap();
-                input.sweeping = ! input.sweeping;
-                rouille::Response::json(&TiafBoringResponse::Ok)
-            },
+                None => router!(request,
+                    (GET) (/) => {
+                        rouille::Response::text("index")
+                    },
 
-            (GET) (/api/v1/admin/node-id) => {
-                let response = api::TiafNode{
-                    node_id: node_id.clone()
-                };
-                rouille::Response::json(&response)
+                    (GET) (/healthz) => {
+                        rouille::Response::text("OK")
+                    },
+
+                    _ => rouille::Response::empty_404()),
             },
+        };
 
-            _ => rouille::Response::empty_404());
+        if let (Some(cluster), Some(peer)) = (&cluster, peer) {
+            sign_response(&mut result, cluster, &peer.signature);
+        }
 
         let code = result.status_code;
-        logger.lock().unwrap().info(vec![
+        let mut line = vec![
             Attributes::KV("ts", ts.to_rfc3339()),
             Attributes::KV("duration", format!("{:?}", start.elapsed()).to_string()),
             Attributes::KV("method", request.method().to_string()),
             Attributes::KV("url", request.url()),
             Attributes::KV("code", code.to_string()),
-        ]);
+        ];
+        if let Some(peer) = peer {
+            line.push(Attributes::KV("peer", peer.node.clone()));
+        }
+        logger.lock().unwrap().info(line);
 
         result
             .headers
@@ -270,5 +1281,18 @@ pub fn launch_server(
         ));
 
         result
-    });
+    };
+
+    match tls {
+        None => rouille::start_server(endpoint, handler),
+        Some(tls) => {
+            // the http server listens on loopback, behind the relays.
+            let server =
+                rouille::Server::new("127.0.0.1:0", handler).expect("failed to start http server");
+            let backend = server.server_addr();
+            let listener = std::net::TcpListener::bind(&endpoint).expect("failed to bind");
+            std::thread::spawn(move || tls::serve(listener, tls, backend, origins));
+            server.run();
+        }
+    }
 }
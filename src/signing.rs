@@ -0,0 +1,97 @@
+// Signing keys. Admins sign redactions, and nodes sign snapshots, with ed25519 keys: the private
+// half is kept in a file, in hex, and the public half goes in the config of the chains that
+// trust what it signs.
+use crate::hexdisplay::{hex, unhex};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use std::fmt;
+use std::path::Path;
+
+/// PrivateKey is an ed25519 private key. Only its public half is ever shown.
+#[derive(Clone)]
+pub struct PrivateKey {
+    key: SigningKey,
+}
+
+impl fmt::Debug for PrivateKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PrivateKey")
+            .field("public", &self.public())
+            .finish()
+    }
+}
+
+impl PrivateKey {
+    pub fn generate() -> PrivateKey {
+        PrivateKey {
+            key: SigningKey::from_bytes(&rand::random::<[u8; 32]>()),
+        }
+    }
+
+    // load reads a private key, in hex, from a file holding it.
+    pub fn load(path: &Path) -> Result<PrivateKey, String> {
+        let secret = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+        unhex(secret.trim())
+            .and_then(|k| <[u8; 32]>::try_from(k).ok())
+            .map(|k| PrivateKey {
+                key: SigningKey::from_bytes(&k),
+            })
+            .ok_or_else(|| format!("{} does not hold a private key", path.display()))
+    }
+
+    // secret is the private key in hex, to be written to a file.
+    pub fn secret(&self) -> String {
+        hex(self.key.as_bytes())
+    }
+
+    pub fn public(&self) -> String {
+        hex(self.key.verifying_key().as_bytes())
+    }
+
+    // sign signs message, returning the signature in hex.
+    pub fn sign(&self, message: &[u8]) -> String {
+        hex(&self.key.sign(message).to_bytes())
+    }
+}
+
+// public_key reads a public key as hex.
+pub fn public_key(key: &str) -> Result<VerifyingKey, String> {
+    unhex(key)
+        .and_then(|k| <[u8; 32]>::try_from(k).ok())
+        .and_then(|k| VerifyingKey::from_bytes(&k).ok())
+        .ok_or_else(|| format!("{key} is not a public key"))
+}
+
+// verify checks that signature, in hex, is of message by the public key key.
+pub fn verify(key: &str, message: &[u8], signature: &str) -> Result<(), String> {
+    let public = public_key(key)?;
+    let signature = unhex(signature)
+        .and_then(|s| Signature::from_slice(&s).ok())
+        .ok_or_else(|| "signature is malformed".to_string())?;
+    public
+        .verify(message, &signature)
+        .map_err(|_| format!("not signed by {key}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signing() {
+        let file = std::env::temp_dir().join(format!("tiaf-key-{}", uuid::Uuid::new_v4()));
+        let key = PrivateKey::generate();
+        std::fs::write(&file, key.secret()).unwrap();
+        let loaded = PrivateKey::load(&file).unwrap();
+        std::fs::remove_file(file).unwrap();
+        assert_eq!(loaded.public(), key.public());
+        assert!(!format!("{key:?}").contains(&key.secret()));
+
+        let signature = loaded.sign(b"message");
+        verify(&key.public(), b"message", &signature).unwrap();
+        assert!(verify(&key.public(), b"massage", &signature).is_err());
+        assert!(verify(&PrivateKey::generate().public(), b"message", &signature).is_err());
+        assert!(verify(&key.public(), b"message", "00").is_err());
+        assert!(public_key("not hex").is_err());
+    }
+}
@@ -0,0 +1,149 @@
+// Snapshots. A new node would otherwise replay every block of a chain from an upstream,
+// rehashing each and rebuilding its indexes and key-value view as it goes. A snapshot is a
+// chain as one node holds it at some tip: its blocks, and the indexes and view derived from
+// them, signed by the node that took it. A node taking up a snapshot trusts the signature in
+// place of the replay, and then catches up from the snapshot's tip. Queries and the key-value
+// view read entries from blocks, so the blocks are part of the snapshot.
+use crate::chain::ChainState;
+use crate::hexdisplay::hex;
+use crate::signing::PrivateKey;
+use crate::types::{Hashtype, Time};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use std::path::Path;
+
+/// Snapshot is a chain at tip, as the node holding key signed it. state is the chain's
+/// ChainState as JSON, kept as the very text that was signed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub chain: String,
+    pub genesis: Hashtype,
+    pub tip: Hashtype,
+    pub length: u64,
+    pub taken_at: Time,
+    pub state: String,
+    pub key: String,
+    pub signature: String,
+}
+
+// message is what a snapshot signs: everything it says of the chain, and a digest of its state.
+fn message(snapshot: &Snapshot) -> Vec<u8> {
+    format!(
+        "tiaf-snapshot\n{}\n{}\n{}\n{}\n{}\n{}",
+        snapshot.chain,
+        snapshot.genesis,
+        snapshot.tip,
+        snapshot.length,
+        snapshot.taken_at,
+        hex(&Sha3_256::digest(snapshot.state.as_bytes()))
+    )
+    .into_bytes()
+}
+
+impl Snapshot {
+    // open checks that the snapshot was signed by one of keys, and reads the chain state it
+    // holds, which must agree with what the snapshot says of it.
+    pub fn open(&self, keys: &[String]) -> Result<ChainState, String> {
+        if !keys.contains(&self.key) {
+            return Err(format!(
+                "snapshot was signed by {}, which is not a snapshot key",
+                self.key
+            ));
+        }
+        crate::signing::verify(&self.key, &message(self), &self.signature)
+            .map_err(|e| format!("snapshot is not valid: {e}"))?;
+
+        let state: ChainState = serde_json::from_str(&self.state)
+            .map_err(|e| format!("failed to parse snapshot state: {e}"))?;
+        let (first, last) = match (state.blocks.first(), state.blocks.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Err("snapshot holds no blocks".to_string()),
+        };
+        if first.hash != self.genesis || last.hash != self.tip {
+            return Err("snapshot state does not match its tip".to_string());
+        }
+        if state.blocks.len() as u64 != self.length {
+            return Err("snapshot state does not match its length".to_string());
+        }
+        Ok(state)
+    }
+
+    // load reads a snapshot from a file, as the admin snapshot endpoint serves it.
+    pub fn load(path: &Path) -> Result<Snapshot, String> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read snapshot {}: {e}", path.display()))?;
+        serde_json::from_str(&json)
+            .map_err(|e| format!("failed to parse snapshot {}: {e}", path.display()))
+    }
+}
+
+/// SnapshotKey is the private key a node signs its snapshots with. Its public half goes in the
+/// snapshot_keys of the chains that take them up.
+pub type SnapshotKey = PrivateKey;
+
+impl Snapshot {
+    // take takes a snapshot of state, the chain called chain, now, signed with the node's key.
+    pub fn take(key: &SnapshotKey, chain: &str, state: &ChainState) -> Result<Snapshot, String> {
+        let (genesis, tip) = match (state.blocks.first(), state.blocks.last()) {
+            (Some(first), Some(last)) => (first.hash.clone(), last.hash.clone()),
+            _ => return Err("chain holds no blocks".to_string()),
+        };
+        let mut snapshot = Snapshot {
+            chain: chain.to_string(),
+            genesis,
+            tip,
+            length: state.blocks.len() as u64,
+            taken_at: chrono::Utc::now().timestamp() as Time,
+            state: serde_json::to_string(state).map_err(|e| e.to_string())?,
+            key: key.public(),
+            signature: String::new(),
+        };
+        snapshot.signature = key.sign(&message(&snapshot));
+        Ok(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::Blockchain;
+    use crate::kv::KvConfig;
+    use crate::record::Record;
+
+    #[test]
+    fn test_snapshots() {
+        let mut chain = Blockchain::new();
+        chain.set_indexes(&["id".to_string()]);
+        chain.set_kv(Some(KvConfig::new("id")));
+        for id in ["a", "b"] {
+            chain
+                .append_records(vec![Record::new(format!(r#"{{"id": "{id}"}}"#))])
+                .unwrap();
+        }
+        let node = SnapshotKey::generate();
+        let snapshot = Snapshot::take(&node, "default", &chain.state()).unwrap();
+        assert_eq!(&snapshot.tip, chain.tip());
+
+        let state = snapshot.open(&[node.public()]).unwrap();
+        let restored = Blockchain::restore(state, &[]).unwrap();
+        assert_eq!(restored, chain);
+        assert_eq!(restored.indexes().fields(), vec!["id"]);
+        assert_eq!(restored.kv().unwrap().len(), 2);
+        restored.full_validate().unwrap();
+
+        // snapshots are taken up only from trusted keys, and only as they were signed.
+        assert!(snapshot.open(&[SnapshotKey::generate().public()]).is_err());
+        let longer = Snapshot {
+            length: 4,
+            ..snapshot.clone()
+        };
+        assert!(longer.open(&[node.public()]).is_err());
+        let mut tampered = chain.state();
+        tampered.blocks[2].data[0].entry = r#"{"id": "c"}"#.to_string();
+        let tampered = Snapshot {
+            state: serde_json::to_string(&tampered).unwrap(),
+            ..snapshot
+        };
+        assert!(tampered.open(&[node.public()]).is_err());
+    }
+}
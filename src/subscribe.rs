@@ -0,0 +1,154 @@
+use crate::api::{TiafSubscriptionData, TiafSubscriptionEvent};
+use crate::chain::Blockchain;
+use crate::query_chain::Query;
+use crate::types::Hashtype;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How often a subscription looks at the chain for new blocks.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+// How long one response of a quiet subscription waits for news before it ends.
+pub const LONGEST_WAIT: Duration = Duration::from_secs(15);
+
+/// Subscription streams what is sealed into the chain to one client as Server-Sent Events.
+///
+/// With a query, every newly sealed record the query matches is sent as a `record` event, or a
+/// `row` event when the query has a select list. Without one, every new block is sent as a
+/// `block` event. The id of each event is the hash of the block it came from, so a client
+/// resumes by passing the last id it saw back as `since` or `Last-Event-ID`.
+///
+/// The server underneath rouille buffers streamed bodies, so rather than hold one response open,
+/// each response carries the events of one wait and ends. It closes with an event of nothing
+/// but the id of the last block it covered, so a client reconnecting with that id, as an
+/// EventSource does, misses nothing.
+pub struct Subscription {
+    chain: Arc<RwLock<Blockchain>>,
+    query: Option<Query>,
+    // next is the index of the first block not yet sent.
+    next: u64,
+}
+
+impl Subscription {
+    // new subscribes to the blocks after since, or to blocks yet to be sealed when since is None.
+    pub fn new(
+        chain: Arc<RwLock<Blockchain>>,
+        query: Option<Query>,
+        since: Option<&Hashtype>,
+    ) -> Result<Subscription, String> {
+        if query.as_ref().is_some_and(|q| q.is_aggregate()) {
+            return Err("aggregation queries cannot be subscribed to".to_string());
+        }
+        let next = {
+            let b = chain.read().unwrap();
+            match since {
+                Some(hash) => {
+                    b.blocks()
+                        .position(|block| block.hash == *hash)
+                        .ok_or(format!("unknown block {hash}"))? as u64
+                        + 1
+                }
+                None => b.length(),
+            }
+        };
+        Ok(Subscription { chain, query, next })
+    }
+
+    // poll collects the events for every block sealed since the last poll.
+    pub fn poll(&mut self) -> Vec<TiafSubscriptionEvent> {
+        let b = self.chain.read().unwrap();
+        let mut events = vec![];
+        while let Some(block) = b.get(self.next) {
+            self.next += 1;
+            let query = match &self.query {
+                Some(query) => query,
+                None => {
+                    events.push(TiafSubscriptionEvent {
+                        id: block.hash.clone(),
+                        data: TiafSubscriptionData::Block(block.clone()),
+                    });
+                    continue;
+                }
+            };
+            for r in block.data.iter().filter(|r| query.matches(r)) {
+                let data = if query.select().is_empty() {
+                    TiafSubscriptionData::Record(r.clone())
+                } else {
+                    TiafSubscriptionData::Row(query.project(r))
+                };
+                events.push(TiafSubscriptionEvent {
+                    id: block.hash.clone(),
+                    data,
+                });
+            }
+        }
+        events
+    }
+
+    // wait polls until there are events, or until timeout has passed, and returns the body of
+    // one response: the events, then the id of the last block they cover.
+    pub fn wait(&mut self, timeout: Duration) -> String {
+        let start = Instant::now();
+        let mut events = self.poll();
+        while events.is_empty() && start.elapsed() < timeout {
+            thread::sleep(POLL_INTERVAL.min(timeout.saturating_sub(start.elapsed())));
+            events = self.poll();
+        }
+        let mut out: String = events.iter().map(TiafSubscriptionEvent::to_sse).collect();
+        if let Some(last) = self.chain.read().unwrap().get(self.next - 1) {
+            out.push_str(&format!("id: {}\n\n", last.hash));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::Record;
+
+    #[test]
+    fn test_subscription_poll() {
+        let chain = Arc::new(RwLock::new(Blockchain::new()));
+        let rec = |k: &str| Record::new(format!("{{\"k\": \"{k}\"}}"));
+        chain
+            .write()
+            .unwrap()
+            .append_records(vec![rec("a")])
+            .unwrap();
+
+        let mut blocks = Subscription::new(chain.clone(), None, None).unwrap();
+        let query = Query::new("k == \"b\"".to_string()).unwrap();
+        let mut matching = Subscription::new(chain.clone(), Some(query), None).unwrap();
+        assert!(blocks.poll().is_empty());
+
+        chain
+            .write()
+            .unwrap()
+            .append_records(vec![rec("b"), rec("c")])
+            .unwrap();
+        let hash = chain.read().unwrap().get(2).unwrap().hash.clone();
+        let events = blocks.poll();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].id, hash);
+        let events = matching.poll();
+        assert_eq!(events.len(), 1);
+        assert!(
+            matches!(&events[0].data, TiafSubscriptionData::Record(r) if r.entry.contains("\"b\""))
+        );
+        assert!(matching.poll().is_empty());
+
+        // Resuming from the first block replays the second.
+        let first = chain.read().unwrap().get(1).unwrap().hash.clone();
+        let mut resumed = Subscription::new(chain.clone(), None, Some(&first)).unwrap();
+        assert_eq!(resumed.poll()[0].id, hash);
+
+        assert!(Subscription::new(chain.clone(), None, Some(&"nope".to_string())).is_err());
+        let aggregate = Query::new("select count(*)".to_string()).unwrap();
+        assert!(Subscription::new(chain.clone(), Some(aggregate), None).is_err());
+
+        // A quiet wait still says where to resume from.
+        let mut quiet = Subscription::new(chain, None, None).unwrap();
+        assert_eq!(quiet.wait(Duration::ZERO), format!("id: {hash}\n\n"));
+    }
+}
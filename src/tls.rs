@@ -0,0 +1,302 @@
+// TLS for the HTTP server and client. rouille cannot check client certificates, so a node serving
+// TLS terminates it itself: it accepts connections on its public address and relays each one to
+// the HTTP server, which listens on loopback. What the handshake established, the client's
+// address and whether it presented a certificate from the cluster's CA, is kept under the address
+// the relay connects from, where the server looks it up.
+use rustls::server::{AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient};
+use rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig, ServerConnection};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{self, BufReader, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// A client gets this long to finish its handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// TlsConfig is the `[tls]` section of the server config: the node's certificate chain and key,
+/// in PEM files. With client_ca_file set, a client may present a certificate signed by that CA,
+/// which marks it as a member of the cluster; require_client_cert refuses clients that do not.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TlsConfig {
+    pub cert_file: PathBuf,
+    pub key_file: PathBuf,
+    #[serde(default)]
+    pub client_ca_file: Option<PathBuf>,
+    #[serde(default)]
+    pub require_client_cert: bool,
+}
+
+/// ClientTlsConfig is how a client connects to nodes over TLS. ca_file pins the CA node
+/// certificates must be signed by, in place of the system's; cert_file and key_file are the
+/// certificate the client presents, if any.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClientTlsConfig {
+    #[serde(default)]
+    pub ca_file: Option<PathBuf>,
+    #[serde(default)]
+    pub cert_file: Option<PathBuf>,
+    #[serde(default)]
+    pub key_file: Option<PathBuf>,
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|e| format!("failed to read {}: {e}", path.display()))
+}
+
+fn certificates(path: &Path) -> Result<Vec<Certificate>, String> {
+    let pem = read(path)?;
+    let certs = rustls_pemfile::certs(&mut pem.as_slice())
+        .map_err(|e| format!("failed to read certificates in {}: {e}", path.display()))?;
+    if certs.is_empty() {
+        return Err(format!("no certificates in {}", path.display()));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn private_key(path: &Path) -> Result<PrivateKey, String> {
+    let pem = read(path)?;
+    let items = rustls_pemfile::read_all(&mut BufReader::new(pem.as_slice()))
+        .map_err(|e| format!("failed to read key in {}: {e}", path.display()))?;
+    items
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| format!("no private key in {}", path.display()))
+}
+
+// server_config reads the certificates and key config names.
+pub fn server_config(config: &TlsConfig) -> Result<Arc<ServerConfig>, String> {
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match &config.client_ca_file {
+        Some(ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in certificates(ca)? {
+                roots
+                    .add(&cert)
+                    .map_err(|e| format!("bad client CA certificate: {e}"))?;
+            }
+            if config.require_client_cert {
+                builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+            } else {
+                builder.with_client_cert_verifier(
+                    AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed(),
+                )
+            }
+        }
+        None if config.require_client_cert => {
+            return Err("require_client_cert needs a client_ca_file".to_string())
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let config = builder
+        .with_single_cert(
+            certificates(&config.cert_file)?,
+            private_key(&config.key_file)?,
+        )
+        .map_err(|e| format!("bad certificate or key: {e}"))?;
+    Ok(Arc::new(config))
+}
+
+/// Origin is where a relayed connection came from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Origin {
+    pub addr: SocketAddr,
+    // client_cert is whether the client presented a certificate the client CA signed.
+    pub client_cert: bool,
+}
+
+/// Origins holds the origin of every open relay, by the address it connects to the server from.
+#[derive(Debug, Clone, Default)]
+pub struct Origins(Arc<Mutex<HashMap<SocketAddr, Origin>>>);
+
+impl Origins {
+    pub fn of(&self, relay: &SocketAddr) -> Option<Origin> {
+        self.0.lock().unwrap().get(relay).copied()
+    }
+}
+
+// serve accepts TLS connections on listener, relaying each to the HTTP server at backend.
+pub fn serve(
+    listener: TcpListener,
+    config: Arc<ServerConfig>,
+    backend: SocketAddr,
+    origins: Origins,
+) {
+    for stream in listener.incoming().flatten() {
+        let (config, origins) = (config.clone(), origins.clone());
+        thread::spawn(move || {
+            // a failed handshake or a dropped connection is the client's problem.
+            let _ = relay(stream, config, backend, origins);
+        });
+    }
+}
+
+fn relay(
+    mut stream: TcpStream,
+    config: Arc<ServerConfig>,
+    backend: SocketAddr,
+    origins: Origins,
+) -> Result<(), String> {
+    let addr = stream.peer_addr().map_err(|e| e.to_string())?;
+    stream
+        .set_read_timeout(Some(HANDSHAKE_TIMEOUT))
+        .map_err(|e| e.to_string())?;
+    let mut conn = ServerConnection::new(config).map_err(|e| e.to_string())?;
+    while conn.is_handshaking() {
+        conn.complete_io(&mut stream).map_err(|e| e.to_string())?;
+    }
+    let origin = Origin {
+        addr,
+        client_cert: conn.peer_certificates().is_some_and(|c| !c.is_empty()),
+    };
+
+    let server = TcpStream::connect(backend).map_err(|e| e.to_string())?;
+    let local = server.local_addr().map_err(|e| e.to_string())?;
+    origins.0.lock().unwrap().insert(local, origin);
+    let result = pump(stream, conn, server);
+    origins.0.lock().unwrap().remove(&local);
+    result
+}
+
+// pump copies between the client and the server until either hangs up. Each direction reads
+// its own socket; they share only the TLS state, which is locked to encrypt or decrypt, and
+// never across a read or a write of a socket.
+fn pump(stream: TcpStream, conn: ServerConnection, server: TcpStream) -> Result<(), String> {
+    stream.set_read_timeout(None).map_err(|e| e.to_string())?;
+    let conn = Arc::new(Mutex::new(conn));
+    let out = Arc::new(Mutex::new(stream.try_clone().map_err(|e| e.to_string())?));
+
+    let answering = {
+        let (conn, out) = (conn.clone(), out.clone());
+        let mut from_server = server.try_clone().map_err(|e| e.to_string())?;
+        thread::spawn(move || {
+            let mut buf = [0; 16 * 1024];
+            loop {
+                let n = match from_server.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => n,
+                };
+                let sealed = conn.lock().unwrap().writer().write_all(&buf[..n]);
+                if sealed.and_then(|_| flush(&conn, &out)).is_err() {
+                    break;
+                }
+            }
+            conn.lock().unwrap().send_close_notify();
+            let _ = flush(&conn, &out);
+            let _ = out.lock().unwrap().shutdown(Shutdown::Both);
+        })
+    };
+
+    let (mut from_client, mut to_server) = (stream, server);
+    let mut buf = [0; 16 * 1024];
+    // the request may have come in with the end of the handshake, and be waiting already.
+    let mut n = 0;
+    loop {
+        let (request, open) = open_records(&conn, &buf[..n]);
+        // alerts, and whatever else reading made for the client, go out as answers do.
+        if flush(&conn, &out).is_err() || to_server.write_all(&request).is_err() || !open {
+            break;
+        }
+        n = match from_client.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+    }
+    let _ = to_server.shutdown(Shutdown::Write);
+    answering.join().map_err(|_| "relay failed".to_string())
+}
+
+// open_records decrypts the records in tls, returning what they held, and whether the client
+// may send more.
+fn open_records(conn: &Mutex<ServerConnection>, mut tls: &[u8]) -> (Vec<u8>, bool) {
+    let mut conn = conn.lock().unwrap();
+    let mut plain = vec![];
+    loop {
+        // the reader runs dry with WouldBlock, and ends once the client has closed.
+        match conn.reader().read_to_end(&mut plain) {
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            _ => return (plain, false),
+        }
+        if tls.is_empty() {
+            return (plain, true);
+        }
+        if conn.read_tls(&mut tls).is_err() || conn.process_new_packets().is_err() {
+            return (plain, false);
+        }
+    }
+}
+
+// flush writes the records conn has made to the client. The socket is held from taking the
+// records until they are written, so records reach the client in the order they were made.
+fn flush(conn: &Mutex<ServerConnection>, out: &Mutex<TcpStream>) -> io::Result<()> {
+    let mut out = out.lock().unwrap();
+    let mut records = vec![];
+    {
+        let mut conn = conn.lock().unwrap();
+        while conn.wants_write() {
+            conn.write_tls(&mut records)?;
+        }
+    }
+    out.write_all(&records)
+}
+
+/// ClientTls is a client's TLS settings, read from their files.
+#[derive(Clone, Default)]
+pub struct ClientTls {
+    ca: Option<reqwest::Certificate>,
+    identity: Option<reqwest::Identity>,
+}
+
+impl ClientTls {
+    pub fn load(config: &ClientTlsConfig) -> Result<ClientTls, String> {
+        let ca = match &config.ca_file {
+            Some(path) => Some(
+                reqwest::Certificate::from_pem(&read(path)?)
+                    .map_err(|e| format!("bad CA certificate: {e}"))?,
+            ),
+            None => None,
+        };
+        let identity = match (&config.cert_file, &config.key_file) {
+            (Some(cert), Some(key)) => Some(
+                reqwest::Identity::from_pkcs8_pem(&read(cert)?, &read(key)?)
+                    .map_err(|e| format!("bad client certificate or key: {e}"))?,
+            ),
+            (None, None) => None,
+            _ => return Err("a client certificate needs both cert_file and key_file".to_string()),
+        };
+        Ok(ClientTls { ca, identity })
+    }
+
+    // apply has builder connect with these settings.
+    pub fn apply(
+        &self,
+        mut builder: reqwest::blocking::ClientBuilder,
+    ) -> reqwest::blocking::ClientBuilder {
+        if let Some(ca) = &self.ca {
+            builder = builder
+                .tls_built_in_root_certs(false)
+                .add_root_certificate(ca.clone());
+        }
+        if let Some(identity) = &self.identity {
+            builder = builder.identity(identity.clone());
+        }
+        builder
+    }
+}
+
+impl std::fmt::Debug for ClientTls {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ClientTls")
+            .field("ca", &self.ca.is_some())
+            .field("identity", &self.identity.is_some())
+            .finish()
+    }
+}
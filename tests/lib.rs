@@ -1,6 +1,19 @@
-use tiaf;
-
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+use tiaf::chain::Blockchain;
+use tiaf::cluster::ClusterKey;
+use tiaf::credentials::{KeyConfig, Keys, Scope};
+use tiaf::encryption::{is_encrypted, Encrypted, Encryption, EncryptionKey};
+use tiaf::genesis::Genesis;
+use tiaf::namespace::{NamespaceConfig, Namespaces, DEFAULT};
+use tiaf::peers::{ReadHost, Upstreams};
+use tiaf::query_chain::QueryRows;
+use tiaf::redaction::{Redaction, RedactionKey};
+use tiaf::snapshot::SnapshotKey;
+use tiaf::tls::{ClientTls, ClientTlsConfig, TlsConfig};
 
 #[allow(dead_code)]
 struct TestContext {}
@@ -24,3 +37,1112 @@ fn test_add() {
     // https://docs.rs/rouille/latest/rouille/struct.Server.html#method.stoppable
     // tiaf::server::launch_server(blockchain, mem_pool);
 }
+
+// The server binary is run as a node is, and written to as clients and peers write.
+#[test]
+fn test_writes_reach_a_running_server() {
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mut server = std::process::Command::new(env!("CARGO_BIN_EXE_tiaf-server"))
+        .args(["--ip", "127.0.0.1", "--port", &port.to_string()])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    let client = tiaf::api::TiafClient::new(format!("http://127.0.0.1:{port}"), None);
+    let up = (0..250).any(|_| {
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        client.get_statistics().is_ok()
+    });
+    let put: tiaf::api::RecordPut = serde_json::from_str(r#"{"data": "{\"n\": \"1\"}"}"#).unwrap();
+    let written = client.put_data(&put).map(|_| ());
+    let replicated = client.put_record(&tiaf::record::Record::new("{\"n\": \"2\"}".to_string()));
+    server.kill().unwrap();
+    server.wait().unwrap();
+
+    assert!(up, "server did not start");
+    written.unwrap();
+    replicated.unwrap();
+}
+
+// start_node serves a fresh node on a free local port, returning its url along with the chain
+// and pool behind it. Nothing sweeps the pool; tests seal it themselves.
+fn start_node(
+    mempool_size: usize,
+) -> (
+    String,
+    Arc<RwLock<tiaf::chain::Blockchain>>,
+    Arc<RwLock<tiaf::mempool::MemPool>>,
+) {
+    let keys = Keys::load(&[KeyConfig::new("test", "test", &[]).admin()]).unwrap();
+    start_node_with(
+        keys,
+        NamespaceConfig {
+            mempool_size,
+            ..NamespaceConfig::default()
+        },
+    )
+}
+
+fn start_node_with(
+    admins: Keys,
+    config: NamespaceConfig,
+) -> (
+    String,
+    Arc<RwLock<tiaf::chain::Blockchain>>,
+    Arc<RwLock<tiaf::mempool::MemPool>>,
+) {
+    let namespaces = Namespaces::new(admins, Duration::from_secs(60));
+    let default = namespaces.open(DEFAULT, config).unwrap();
+    let url = serve(Arc::new(namespaces), None);
+    (url, default.blockchain.clone(), default.mem_pool.clone())
+}
+
+// serve serves the chains of namespaces over http on a free local port, returning its url once
+// it is up.
+fn serve(namespaces: Arc<Namespaces>, cluster: Option<ClusterKey>) -> String {
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    thread::spawn(move || {
+        tiaf::server::launch_server(
+            "test".to_string(),
+            "127.0.0.1".to_string(),
+            port,
+            namespaces,
+            cluster,
+            None,
+        )
+    });
+    let url = format!("http://127.0.0.1:{port}");
+    for _ in 0..100 {
+        if reqwest::blocking::get(format!("{url}/healthz")).is_ok() {
+            return url;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    panic!("node did not start");
+}
+
+fn seal(
+    chain: &Arc<RwLock<tiaf::chain::Blockchain>>,
+    mem_pool: &Arc<RwLock<tiaf::mempool::MemPool>>,
+) {
+    let mut mp = mem_pool.write().unwrap();
+    let records = mp.reset();
+    chain.write().unwrap().append_new_records(records).unwrap();
+}
+
+#[test]
+fn test_flooded_node_keeps_acknowledged_writes() {
+    let (url, chain, mem_pool) = start_node(4);
+    let client = tiaf::api::TiafClient::new(url.clone(), None);
+    assert_eq!(client.get_statistics().unwrap().pool_capacity, 4);
+
+    let flooding = Arc::new(AtomicBool::new(true));
+    let sealer = {
+        let (chain, mem_pool, flooding) = (chain.clone(), mem_pool.clone(), flooding.clone());
+        thread::spawn(move || {
+            while flooding.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(500));
+                seal(&chain, &mem_pool);
+            }
+        })
+    };
+
+    let writers: Vec<_> = (0..4)
+        .map(|w| {
+            let client = tiaf::api::TiafClient::new(url.clone(), None);
+            thread::spawn(move || {
+                let (mut acked, mut refused) = (vec![], 0);
+                for i in 0..25 {
+                    let data = format!("{{\"writer\": \"{w}\", \"n\": \"{i}\"}}");
+                    match client.put_data(&tiaf::api::RecordPut::new(data.clone())) {
+                        Ok(_) => acked.push(data),
+                        Err(e) => {
+                            assert!(e.starts_with("mempool is full"), "{e}");
+                            refused += 1;
+                        }
+                    }
+                }
+                (acked, refused)
+            })
+        })
+        .collect();
+    let (mut acked, mut refused) = (vec![], 0);
+    for writer in writers {
+        let (a, r) = writer.join().unwrap();
+        acked.extend(a);
+        refused += r;
+    }
+    flooding.store(false, Ordering::SeqCst);
+    sealer.join().unwrap();
+    seal(&chain, &mem_pool);
+
+    assert!(refused > 0, "the pool never filled");
+    let chain = chain.read().unwrap();
+    let sealed: HashSet<&String> = chain
+        .blocks()
+        .flat_map(|b| b.data.iter().map(|r| &r.entry))
+        .collect();
+    for data in &acked {
+        assert!(sealed.contains(data), "acknowledged write lost: {data}");
+    }
+}
+
+#[test]
+fn test_retried_writes_are_idempotent() {
+    let (url, chain, mem_pool) = start_node(8);
+    let client = tiaf::api::TiafClient::new(url.clone(), None);
+    let put = tiaf::api::RecordPut::new("{\"n\": \"1\"}".to_string());
+
+    let first = client.put_data_with_key(&put, Some("k1")).unwrap();
+    assert!(!first.duplicate);
+    let again = client.put_data_with_key(&put, Some("k1")).unwrap();
+    assert_eq!(again.record, first.record);
+    assert!(again.duplicate);
+    let other = tiaf::api::RecordPut::new("{\"n\": \"2\"}".to_string());
+    assert!(client.put_data_with_key(&other, Some("k1")).is_err());
+
+    // a client-chosen uuid works as a key too, and still holds once the record is sealed.
+    let mut with_uuid = tiaf::api::RecordPut::new("{\"n\": \"3\"}".to_string());
+    with_uuid.uuid = Some(uuid::Uuid::new_v4());
+    let first = client.put_data(&with_uuid).unwrap();
+    assert_eq!(Some(first.uuid), with_uuid.uuid);
+    seal(&chain, &mem_pool);
+    assert!(client.put_data(&with_uuid).unwrap().duplicate);
+    assert_eq!(mem_pool.read().unwrap().length(), 0);
+    assert_eq!(chain.read().unwrap().length(), 2);
+
+    // the uuid holds for another client too, and for a record a peer relayed.
+    let admin = tiaf::api::TiafClient::new(url.clone(), None).with_api_key(Some("test".into()));
+    assert!(admin.put_data(&with_uuid).unwrap().duplicate);
+    with_uuid.uuid = Some(uuid::Uuid::new_v4());
+    let relayed = tiaf::record::Record::with_uuid(with_uuid.data.clone(), with_uuid.uuid.unwrap());
+    client.put_record(&relayed).unwrap();
+    assert_eq!(client.put_data(&with_uuid).unwrap().record, relayed.hash);
+    with_uuid.data = "{\"n\": \"4\"}".to_string();
+    assert!(client.put_data(&with_uuid).is_err());
+
+    // retries racing each other make one record between them.
+    let retries: Vec<_> = (0..8)
+        .map(|_| {
+            let url = url.clone();
+            thread::spawn(move || {
+                let put = tiaf::api::RecordPut::new("{\"n\": \"5\"}".to_string());
+                tiaf::api::TiafClient::new(url, None)
+                    .put_data_with_key(&put, Some("k2"))
+                    .unwrap()
+            })
+        })
+        .collect();
+    let receipts: HashSet<_> = retries
+        .into_iter()
+        .map(|retry| retry.join().unwrap().record)
+        .collect();
+    assert_eq!(receipts.len(), 1);
+    assert_eq!(mem_pool.read().unwrap().length(), 2);
+
+    // the entries of an atomic batch are held to their uuids as well.
+    let reused = client.put_batch(&[with_uuid], true).unwrap();
+    assert_eq!(reused.items[0].status, 409);
+    assert!(reused.block.is_none());
+    let mut repeated = tiaf::api::RecordPut::new(relayed.entry.clone());
+    repeated.uuid = Some(relayed.uuid);
+    let fresh = tiaf::api::RecordPut::new("{\"n\": \"6\"}".to_string());
+    let sealed = client.put_batch(&[repeated, fresh], true).unwrap();
+    let receipts: Vec<_> = sealed
+        .items
+        .into_iter()
+        .map(|i| i.receipt.unwrap())
+        .collect();
+    assert_eq!(receipts[0].record, relayed.hash);
+    assert!(receipts[0].duplicate && !receipts[1].duplicate);
+    assert_eq!(chain.read().unwrap().get(2).unwrap().data.len(), 1);
+}
+
+#[test]
+fn test_batch_writes() {
+    let (url, chain, mem_pool) = start_node(3);
+    let client = tiaf::api::TiafClient::new(url.clone(), None);
+    let puts: Vec<tiaf::api::RecordPut> = (0..5)
+        .map(|i| tiaf::api::RecordPut::new(format!("{{\"n\": \"{i}\"}}")))
+        .collect();
+
+    // one at a time, the pool takes what it has room for.
+    let result = client.put_batch(&puts, false).unwrap();
+    let statuses: Vec<u16> = result.items.iter().map(|i| i.status).collect();
+    assert_eq!(statuses, vec![200, 200, 200, 503, 503]);
+    assert_eq!(mem_pool.read().unwrap().length(), 3);
+
+    // atomically, all of them go into a block of their own.
+    let result = client.put_batch(&puts, true).unwrap();
+    assert!(result.items.iter().all(|i| i.receipt.is_some()));
+    let chain_length = chain.read().unwrap().length();
+    let block = chain.read().unwrap().get(chain_length - 1).unwrap().clone();
+    assert_eq!(result.block, Some(block.hash));
+    assert_eq!(block.data.len(), 5);
+    assert_eq!(mem_pool.read().unwrap().length(), 3);
+
+    // NDJSON, with a line that cannot be read.
+    let body = "{\"data\": \"x\"}\nnot json\n";
+    let resp = reqwest::blocking::Client::new()
+        .post(format!("{url}/api/v1/data/batch"))
+        .header("Content-Type", "application/x-ndjson")
+        .body(body)
+        .send()
+        .unwrap();
+    let result: tiaf::api::TiafBatchResult = resp.json().unwrap();
+    let statuses: Vec<u16> = result.items.iter().map(|i| i.status).collect();
+    assert_eq!(statuses, vec![503, 400]);
+}
+
+#[test]
+fn test_atomic_batches_reach_downstreams() {
+    let keys = || Keys::load(&[KeyConfig::new("test", "test", &[]).admin()]).unwrap();
+    let downstream = Namespaces::new(keys(), Duration::from_secs(60));
+    let theirs = downstream
+        .open(DEFAULT, NamespaceConfig::default())
+        .unwrap();
+    let their_url = serve(Arc::new(downstream), None);
+    let upstream = Namespaces::new(keys(), Duration::from_secs(60));
+    let config = NamespaceConfig {
+        downstreams: vec![their_url],
+        rate_limit: Some(tiaf::quota::RateLimitConfig {
+            requests_per_sec: 0.001,
+            burst: 3.0,
+            mempool_quota: None,
+        }),
+        ..NamespaceConfig::default()
+    };
+    let ours = upstream.open(DEFAULT, config).unwrap();
+    let client = tiaf::api::TiafClient::new(serve(Arc::new(upstream), None), None);
+    let puts: Vec<tiaf::api::RecordPut> = (0..4)
+        .map(|i| tiaf::api::RecordPut::new(format!("{{\"n\": {i}}}")))
+        .collect();
+
+    // an empty batch seals nothing, and is charged nothing.
+    assert!(client.put_batch(&[], true).is_err());
+
+    // a batch is charged a write for each entry, so it uses up the burst on its own.
+    let block = client.put_batch(&puts, true).unwrap().block.unwrap();
+    assert!(client.put_data(&puts[0]).is_err());
+
+    // the sealed block is pushed whole to a downstream whose chain it follows.
+    assert!(ours.downstreams.write().unwrap().push_sealed().is_empty());
+    assert_eq!(theirs.blockchain.read().unwrap().tip(), &block);
+
+    // once the downstream's chain has moved on, the block's records are pooled there instead.
+    theirs
+        .mem_pool
+        .write()
+        .unwrap()
+        .put(tiaf::record::Record::new("{\"n\": 9}".to_string()))
+        .unwrap();
+    seal(&theirs.blockchain, &theirs.mem_pool);
+    let mut chain = ours.blockchain.write().unwrap();
+    let records = (4..6)
+        .map(|i| tiaf::record::Record::new(format!("{{\"n\": {i}}}")))
+        .collect();
+    chain.append_records(records).unwrap();
+    let sealed = chain.get(chain.length() - 1).unwrap().clone();
+    let mut downstreams = ours.downstreams.write().unwrap();
+    assert!(downstreams.hand_over(sealed).is_empty());
+    assert!(downstreams.push_sealed().is_empty());
+    assert_eq!(theirs.mem_pool.read().unwrap().length(), 2);
+}
+
+#[test]
+fn test_scoped_keys() {
+    let admins = Keys::load(&[KeyConfig::new("root", "root", &[]).admin()]).unwrap();
+    let config = NamespaceConfig {
+        api_keys: vec![
+            KeyConfig::new("analyst", "analyst", &[Scope::Read]),
+            KeyConfig::new("producer", "producer", &[Scope::Write]),
+        ],
+        anonymous_scopes: vec![],
+        ..NamespaceConfig::default()
+    };
+    let (url, _chain, mem_pool) = start_node_with(admins, config);
+    let put = tiaf::api::RecordPut::new("{\"k\": \"v\"}".to_string());
+    let status = |path: &str, key: Option<&str>| {
+        let mut builder = reqwest::blocking::Client::new().get(format!("{url}{path}"));
+        if let Some(key) = key {
+            builder = builder.header("X-TIAF-API-KEY", key);
+        }
+        builder.send().unwrap().status().as_u16()
+    };
+
+    assert_eq!(status("/healthz", None), 200);
+    assert_eq!(status("/api/v1/statistics", None), 401);
+    assert_eq!(status("/api/v1/statistics", Some("wrong")), 401);
+    assert_eq!(status("/api/v1/statistics", Some("analyst")), 200);
+    assert_eq!(status("/api/v1/statistics", Some("producer")), 403);
+    assert_eq!(status("/api/v1/admin/downstream", Some("analyst")), 403);
+    assert_eq!(status("/api/v1/admin/node-id", Some("root")), 200);
+
+    let client = |key: &str| {
+        tiaf::api::TiafClient::new(url.clone(), None).with_api_key(Some(key.to_string()))
+    };
+    assert!(client("analyst").put_data(&put).is_err());
+    assert!(client("producer").put_data(&put).is_ok());
+    assert!(client("producer").get_statistics().is_err());
+    assert!(client("producer")
+        .put_record(&tiaf::record::Record::new("x".to_string()))
+        .is_err());
+    assert_eq!(mem_pool.read().unwrap().length(), 1);
+}
+
+#[test]
+fn test_named_chains() {
+    let dir = std::env::temp_dir().join(format!("tiaf-chains-{}", uuid::Uuid::new_v4()));
+    let admins = || Keys::load(&[KeyConfig::new("root", "root", &[]).admin()]).unwrap();
+    let namespaces =
+        Namespaces::new(admins(), Duration::from_secs(60)).with_data_dir(Some(dir.clone()));
+    let default = namespaces
+        .open(DEFAULT, NamespaceConfig::default())
+        .unwrap();
+    let url = serve(Arc::new(namespaces), None);
+
+    let admin = |chain: Option<&str>| {
+        tiaf::api::TiafClient::new(url.clone(), Some("root".to_string()))
+            .with_namespace(chain.map(str::to_string))
+    };
+    let mut payments = tiaf::api::TiafCreateChain {
+        name: "payments".to_string(),
+        config: NamespaceConfig {
+            api_keys: vec![KeyConfig::new("payer", "payer", &[Scope::Write])],
+            anonymous_scopes: vec![Scope::Read],
+            ..NamespaceConfig::default()
+        },
+    };
+    assert!(tiaf::api::TiafClient::new(url.clone(), None)
+        .create_chain(&payments)
+        .is_err());
+    // the config is stored, so keys are given as files rather than inline.
+    assert!(admin(None).create_chain(&payments).is_err());
+    let key_file = dir.with_extension("payer");
+    std::fs::write(&key_file, "payer").unwrap();
+    payments.config.api_keys[0].key = None;
+    payments.config.api_keys[0].key_file = Some(key_file.clone());
+    admin(None).create_chain(&payments).unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let stored = std::fs::metadata(dir.join("chains/payments/config.json")).unwrap();
+        assert_eq!(stored.permissions().mode() & 0o777, 0o600);
+    }
+    assert!(admin(None).create_chain(&payments).is_err());
+    assert_eq!(
+        admin(None).get_chains().unwrap().names,
+        vec!["default", "payments"]
+    );
+    let status = reqwest::blocking::Client::new()
+        .get(format!("{url}/api/v1/chains/payments/admin/chains"))
+        .header("X-TIAF-ADMIN-KEY", "root")
+        .send()
+        .unwrap()
+        .status();
+    assert_eq!(status, 404);
+
+    // each chain has its own access policy and its own pool.
+    let put = tiaf::api::RecordPut::new("{\"amount\": \"10\"}".to_string());
+    let client = |chain: Option<&str>, key: Option<&str>| {
+        tiaf::api::TiafClient::new(url.clone(), None)
+            .with_namespace(chain.map(str::to_string))
+            .with_api_key(key.map(str::to_string))
+    };
+    assert!(client(Some("payments"), None).put_data(&put).is_err());
+    assert!(client(None, Some("payer")).put_data(&put).is_err());
+    client(Some("payments"), Some("payer"))
+        .put_data(&put)
+        .unwrap();
+    assert_eq!(default.mem_pool.read().unwrap().length(), 0);
+    assert_eq!(admin(Some("payments")).seal().unwrap().records, 1);
+    assert_eq!(default.blockchain.read().unwrap().length(), 1);
+    let stats = client(Some("payments"), None).get_statistics().unwrap();
+    assert_eq!(stats.chain_length, 2);
+    let status = reqwest::blocking::get(format!("{url}/api/v1/chains/nope/statistics"))
+        .unwrap()
+        .status();
+    assert_eq!(status, 404);
+    // a chain without a key-value view says so, rather than that the key has no value.
+    assert!(admin(Some("payments")).kv_get("payer").is_err());
+
+    // the new chain's daemons store its blocks, and a restarted node hosts it again.
+    let stored = dir.join("chains/payments/blocks.jsonl");
+    let mut lines = 0;
+    for _ in 0..100 {
+        lines = std::fs::read_to_string(&stored)
+            .map(|s| s.lines().count())
+            .unwrap_or(0);
+        if lines == 2 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    assert_eq!(lines, 2);
+    let restarted =
+        Namespaces::new(admins(), Duration::from_secs(60)).with_data_dir(Some(dir.clone()));
+    let loaded = restarted.load_all().unwrap();
+    assert_eq!(loaded.len(), 1);
+    let chain = client(Some("payments"), None).get_full_chain().unwrap();
+    assert_eq!(*loaded[0].blockchain.read().unwrap(), chain);
+    std::fs::remove_dir_all(dir).unwrap();
+    std::fs::remove_file(key_file).unwrap();
+}
+
+#[test]
+fn test_cluster_members_sign_peer_traffic() {
+    let keys = Keys::load(&[KeyConfig::new("test", "test", &[]).admin()]).unwrap();
+    let namespaces = Namespaces::new(keys, Duration::from_secs(60));
+    let config = NamespaceConfig {
+        anonymous_scopes: vec![Scope::Read],
+        ..NamespaceConfig::default()
+    };
+    let mem_pool = namespaces.open(DEFAULT, config).unwrap().mem_pool.clone();
+    let url = serve(
+        Arc::new(namespaces),
+        Some(ClusterKey::new("upstream", "shared")),
+    );
+    let client = |cluster: Option<ClusterKey>| {
+        tiaf::api::TiafClient::new(url.clone(), None).with_cluster(cluster)
+    };
+    let member = client(Some(ClusterKey::new("downstream", "shared")));
+    let outsider = client(Some(ClusterKey::new("downstream", "guessed")));
+    let record = tiaf::record::Record::new("{\"k\": \"v\"}".to_string());
+
+    assert!(client(None).put_record(&record).is_err());
+    assert!(outsider.put_record(&record).is_err());
+    assert!(member.put_record(&record).is_ok());
+    assert_eq!(mem_pool.read().unwrap().length(), 1);
+
+    // sync reads are signed both ways, so blocks are taken only from a member.
+    assert_eq!(member.get_peer_chain().unwrap().total_length, 1);
+    assert!(outsider.get_peer_chain().is_err());
+}
+
+#[test]
+fn test_clusters_keep_peer_traffic_to_members() {
+    let keys = Keys::load(&[KeyConfig::new("test", "test", &[]).admin()]).unwrap();
+    let namespaces = Namespaces::new(keys, Duration::from_secs(60));
+    let mem_pool = namespaces
+        .open(DEFAULT, NamespaceConfig::default())
+        .unwrap()
+        .mem_pool
+        .clone();
+    let url = serve(
+        Arc::new(namespaces),
+        Some(ClusterKey::new("upstream", "shared")),
+    );
+    let anonymous = tiaf::api::TiafClient::new(url.clone(), None);
+    let record = tiaf::record::Record::new("{\"k\": \"v\"}".to_string());
+
+    // by default anyone may write and hand over records, but in a cluster only members relay.
+    assert!(anonymous.put_record(&record).is_err());
+    assert!(anonymous
+        .put_data(&tiaf::api::RecordPut::new("{\"k\": \"w\"}".to_string()))
+        .is_ok());
+    let member = anonymous.with_cluster(Some(ClusterKey::new("downstream", "shared")));
+    assert!(member.put_record(&record).is_ok());
+    assert_eq!(mem_pool.read().unwrap().length(), 2);
+}
+
+#[test]
+fn test_quotas_hold_clients_to_their_own_keys() {
+    let keys = Keys::load(&[KeyConfig::new("test", "test", &[]).admin()]).unwrap();
+    let namespaces = Namespaces::new(keys, Duration::from_secs(60));
+    let config = NamespaceConfig {
+        api_keys: vec![KeyConfig::new("relay", "relay", &[Scope::Peer])],
+        anonymous_scopes: vec![Scope::Read, Scope::Write],
+        rate_limit: Some(tiaf::quota::RateLimitConfig {
+            requests_per_sec: 0.001,
+            burst: 1.0,
+            mempool_quota: None,
+        }),
+        ..NamespaceConfig::default()
+    };
+    namespaces.open(DEFAULT, config).unwrap();
+    let url = serve(
+        Arc::new(namespaces),
+        Some(ClusterKey::new("upstream", "shared")),
+    );
+    let n = std::sync::atomic::AtomicUsize::new(0);
+    let post = |path: &str, key: Option<&str>| {
+        let entry = format!("{{\"n\": {}}}", n.fetch_add(1, Ordering::SeqCst));
+        let body = match path {
+            "/record" => serde_json::to_value(tiaf::record::Record::new(entry)).unwrap(),
+            _ => serde_json::to_value(tiaf::api::RecordPut::new(entry)).unwrap(),
+        };
+        let mut builder = reqwest::blocking::Client::new()
+            .post(format!("{url}/api/v1{path}"))
+            .json(&body);
+        if let Some(key) = key {
+            builder = builder.header("X-TIAF-API-KEY", key);
+        }
+        builder.send().unwrap().status().as_u16()
+    };
+
+    assert_eq!(post("/data", None), 200);
+    assert_eq!(post("/data", None), 429);
+    // a made-up key is refused, rather than taken as a new client with a bucket of its own.
+    assert_eq!(post("/data", Some("made-up")), 401);
+    assert_eq!(post("/record", Some("relay")), 200);
+    assert_eq!(post("/record", Some("relay")), 429);
+
+    // members of the cluster relay records without limit.
+    let member = tiaf::api::TiafClient::new(url.clone(), None)
+        .with_cluster(Some(ClusterKey::new("downstream", "shared")));
+    for i in 0..3 {
+        let record = tiaf::record::Record::new(format!("{{\"relayed\": {i}}}"));
+        member.put_record(&record).unwrap();
+    }
+}
+
+#[test]
+fn test_peers_on_another_chain_are_refused() {
+    let genesis = |chain_id: &str| Genesis {
+        chain_id: chain_id.to_string(),
+        created: "2026-01-01T00:00:00Z".to_string(),
+        authorities: vec![],
+        records: vec![],
+    };
+    let node = |genesis: Genesis| {
+        let namespaces = Namespaces::new(Keys::default(), Duration::from_secs(60));
+        let ns = namespaces
+            .open(
+                DEFAULT,
+                NamespaceConfig {
+                    genesis: Some(genesis),
+                    ..NamespaceConfig::default()
+                },
+            )
+            .unwrap();
+        (serve(Arc::new(namespaces), None), ns)
+    };
+    let (ours, ns) = node(genesis("acme"));
+    let (theirs, _) = node(genesis("other"));
+    let peer = |url: &String| {
+        tiaf::api::TiafClient::new(url.clone(), None).with_genesis(Some(ns.genesis().clone()))
+    };
+    let record = tiaf::record::Record::new("{\"k\": \"v\"}".to_string());
+
+    assert!(peer(&ours).put_record(&record).is_ok());
+    assert_eq!(peer(&ours).get_peer_chain().unwrap().total_length, 1);
+    assert!(peer(&theirs).put_record(&record).is_err());
+    assert!(peer(&theirs).get_peer_chain().is_err());
+
+    // a peer that does not say which chain it is on is still refused blocks of another.
+    let mut upstreams = Upstreams::new(vec![ReadHost::new(&theirs)]);
+    let mut chain = Blockchain::from_genesis(&genesis("acme")).unwrap();
+    let refused = upstreams.sweep_all_upstreams(&mut chain).unwrap_err();
+    assert!(refused.contains("another chain"), "{refused}");
+    let mut upstreams = Upstreams::new(vec![ReadHost::new(&ours)]);
+    assert!(upstreams.sweep_all_upstreams(&mut chain).is_ok());
+}
+
+#[test]
+fn test_redacted_records_leave_queries_disk_and_peers() {
+    let dir = std::env::temp_dir().join(format!("tiaf-redact-{}", uuid::Uuid::new_v4()));
+    let redactor = RedactionKey::generate();
+    let admins = || Keys::load(&[KeyConfig::new("root", "root", &[]).admin()]).unwrap();
+    let config = NamespaceConfig {
+        redaction_keys: vec![redactor.public()],
+        ..NamespaceConfig::default()
+    };
+    let namespaces =
+        Namespaces::new(admins(), Duration::from_secs(60)).with_data_dir(Some(dir.clone()));
+    let ns = namespaces.open(DEFAULT, config.clone()).unwrap();
+    let url = serve(Arc::new(namespaces), None);
+    let admin = tiaf::api::TiafClient::new(url.clone(), Some("root".to_string()));
+
+    for entry in ["{\"ssn\": \"078-05-1120\"}", "{\"ssn\": \"219-09-9999\"}"] {
+        admin
+            .put_data(&tiaf::api::RecordPut::new(entry.to_string()))
+            .unwrap();
+    }
+    seal(&ns.blockchain, &ns.mem_pool);
+    ns.flush().unwrap();
+    let unredacted = ns.blockchain.read().unwrap().get(1).unwrap().clone();
+    let target = unredacted.data[0].hash.clone();
+    let redact = |record: &String, key: &RedactionKey| tiaf::api::TiafRedact {
+        record: record.clone(),
+        redaction: Redaction::sign(key, record, "erasure request 17"),
+    };
+
+    assert!(admin
+        .redact(&redact(&target, &RedactionKey::generate()))
+        .is_err());
+    assert!(tiaf::api::TiafClient::new(url.clone(), None)
+        .redact(&redact(&target, &redactor))
+        .is_err());
+    assert!(admin.redact(&redact(&"0".repeat(64), &redactor)).is_err());
+    admin.redact(&redact(&target, &redactor)).unwrap();
+
+    let found = |q: &str| match admin.query(q.to_string(), None).unwrap().rows {
+        QueryRows::Records(records) => records.len(),
+        rows => panic!("unexpected rows {rows:?}"),
+    };
+    assert_eq!(found("ssn == \"078-05-1120\""), 0);
+    assert_eq!(found("ssn == \"219-09-9999\""), 1);
+
+    // a peer cannot have a tombstone under its own key sealed here.
+    let forger = RedactionKey::generate();
+    let mut forged = tiaf::record::Record::new("{\"ssn\": \"123-45-6789\"}".to_string());
+    forged
+        .redact(Redaction::sign(&forger, &forged.hash, "forged"))
+        .unwrap();
+    let peer = tiaf::api::TiafClient::new(url.clone(), None);
+    assert!(peer.put_record(&forged).is_err());
+    assert_eq!(ns.mem_pool.read().unwrap().length(), 0);
+
+    // the entry is gone from disk, and the stored chain still loads.
+    ns.flush().unwrap();
+    let stored = std::fs::read_to_string(dir.join("chains/default/blocks.jsonl")).unwrap();
+    assert!(!stored.contains("078-05-1120"));
+    assert!(stored.contains("219-09-9999"));
+    let restarted =
+        Namespaces::new(admins(), Duration::from_secs(60)).with_data_dir(Some(dir.clone()));
+    let reloaded = restarted.open(DEFAULT, config).unwrap();
+    assert_eq!(
+        *reloaded.blockchain.read().unwrap(),
+        *ns.blockchain.read().unwrap()
+    );
+    assert!(Namespaces::new(admins(), Duration::from_secs(60))
+        .with_data_dir(Some(dir.clone()))
+        .open(DEFAULT, NamespaceConfig::default())
+        .is_err());
+
+    // a peer holding the record takes up the tombstone when it syncs.
+    let mut peer = Blockchain::new();
+    peer.set_redaction_keys(&[redactor.public()]).unwrap();
+    peer.append_blocks(vec![unredacted]).unwrap();
+    let mut upstreams = Upstreams::new(vec![ReadHost::new(&url)]);
+    upstreams.sweep_all_upstreams(&mut peer).unwrap();
+    assert!(peer.get(1).unwrap().data[0].is_redacted());
+    peer.full_validate().unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_schemas_are_rebuilt_from_the_chain() {
+    let dir = std::env::temp_dir().join(format!("tiaf-schemas-{}", uuid::Uuid::new_v4()));
+    let admins = || Keys::load(&[KeyConfig::new("root", "root", &[]).admin()]).unwrap();
+    let start = || {
+        let namespaces =
+            Namespaces::new(admins(), Duration::from_secs(60)).with_data_dir(Some(dir.clone()));
+        let ns = namespaces
+            .open(DEFAULT, NamespaceConfig::default())
+            .unwrap();
+        (serve(Arc::new(namespaces), None), ns)
+    };
+    let status = |url: &str, entry: &str| {
+        reqwest::blocking::Client::new()
+            .post(format!("{url}/api/v1/data"))
+            .json(&tiaf::api::RecordPut::new(entry.to_string()))
+            .send()
+            .unwrap()
+            .status()
+            .as_u16()
+    };
+    let (good, bad) = (
+        r#"{"$schema": "payment@1", "amount": 3}"#,
+        r#"{"$schema": "payment@1", "amount": "three"}"#,
+    );
+
+    let (url, ns) = start();
+    tiaf::api::TiafClient::new(url.clone(), Some("root".to_string()))
+        .register_schema(&tiaf::schema::SchemaDefined {
+            name: "payment".to_string(),
+            version: 1,
+            schema: serde_json::json!({
+                "type": "object",
+                "properties": {"amount": {"type": "integer"}},
+                "required": ["amount"]
+            }),
+        })
+        .unwrap();
+    assert_eq!(status(&url, bad), 422);
+    seal(&ns.blockchain, &ns.mem_pool);
+    ns.flush().unwrap();
+
+    // a restarted node, and a peer that synced the chain, hold entries to the schema too.
+    let (url, _) = start();
+    assert_eq!(status(&url, bad), 422);
+    assert_eq!(status(&url, good), 200);
+    let mut peer = Blockchain::new();
+    Upstreams::new(vec![ReadHost::new(&url)])
+        .sweep_all_upstreams(&mut peer)
+        .unwrap();
+    assert_eq!(peer.schemas().ids(), vec!["payment@1"]);
+    assert!(peer.schemas().validate(bad).is_err());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_subscriptions_follow_the_chain() {
+    let admins = Keys::load(&[KeyConfig::new("root", "root", &[]).admin()]).unwrap();
+    let namespaces = Namespaces::new(admins, Duration::from_secs(60));
+    let ns = namespaces
+        .open(DEFAULT, NamespaceConfig::default())
+        .unwrap();
+    let url = serve(Arc::new(namespaces), None);
+    let client = tiaf::api::TiafClient::new(url.clone(), None);
+    let put = |n: &str| {
+        client
+            .put_data(&tiaf::api::RecordPut::new(format!("{{\"n\": \"{n}\"}}")))
+            .unwrap();
+        seal(&ns.blockchain, &ns.mem_pool);
+    };
+    put("1");
+
+    // a response is plain HTTP: it carries what was sealed since, then where to resume.
+    let genesis = ns.blockchain.read().unwrap().get(0).unwrap().hash.clone();
+    let tip = ns.blockchain.read().unwrap().tip().clone();
+    let resp = reqwest::blocking::get(format!("{url}/api/v1/subscribe?since={genesis}")).unwrap();
+    assert_eq!(resp.status(), 200);
+    assert!(resp.headers().get("upgrade").is_none());
+    assert_ne!(
+        resp.headers().get("connection").map(|v| v.as_bytes()),
+        Some(&b"upgrade"[..])
+    );
+    assert_eq!(resp.headers()["content-type"], "text/event-stream");
+    let length = resp.content_length();
+    let body = resp.text().unwrap();
+    assert_eq!(length, Some(body.len() as u64));
+    assert!(body.starts_with(&format!("id: {tip}\nevent: block\n")));
+    assert!(body.ends_with(&format!("id: {tip}\n\n")));
+
+    // the client follows the chain across responses, missing nothing sealed in between.
+    let subscriber = tiaf::api::TiafClient::new(url.clone(), None);
+    let events = thread::spawn(move || {
+        subscriber
+            .subscribe(Some("n != 2".to_string()), Some(tip))
+            .unwrap()
+            .take(2)
+            .collect::<Vec<_>>()
+    });
+    for n in ["2", "3", "4"] {
+        put(n);
+    }
+    let entries: Vec<String> = events
+        .join()
+        .unwrap()
+        .into_iter()
+        .map(|e| match e.unwrap().data {
+            tiaf::api::TiafSubscriptionData::Record(r) => r.entry,
+            data => panic!("unexpected event {data:?}"),
+        })
+        .collect();
+    assert_eq!(entries, vec!["{\"n\": \"3\"}", "{\"n\": \"4\"}"]);
+}
+
+#[test]
+fn test_overflowing_queries_are_bad_requests() {
+    let admins = Keys::load(&[KeyConfig::new("root", "root", &[]).admin()]).unwrap();
+    let namespaces = Namespaces::new(admins, Duration::from_secs(60));
+    let ns = namespaces
+        .open(DEFAULT, NamespaceConfig::default())
+        .unwrap();
+    let url = serve(Arc::new(namespaces), None);
+    tiaf::api::TiafClient::new(url.clone(), None)
+        .put_data(&tiaf::api::RecordPut::new(
+            "{\"n\": \"2147483647\"}".to_string(),
+        ))
+        .unwrap();
+    seal(&ns.blockchain, &ns.mem_pool);
+
+    let status = |q: &str| {
+        reqwest::blocking::Client::new()
+            .get(format!("{url}/api/v1/query"))
+            .query(&[("q", q)])
+            .send()
+            .unwrap()
+            .status()
+            .as_u16()
+    };
+    assert_eq!(status("n > 0"), 200);
+    assert_eq!(status("n + 1 > 0"), 400);
+    assert_eq!(status("n == 2147483647 * 2"), 400);
+}
+
+#[test]
+fn test_encrypted_fields() {
+    let admins = || Keys::load(&[KeyConfig::new("root", "root", &[]).admin()]).unwrap();
+    let config = NamespaceConfig {
+        indexed_fields: vec!["ssn".to_string()],
+        kv: Some(tiaf::kv::KvConfig::new("name")),
+        ..NamespaceConfig::default()
+    };
+    let namespaces = Namespaces::new(admins(), Duration::from_secs(60));
+    let ns = namespaces.open(DEFAULT, config).unwrap();
+    let url = serve(Arc::new(namespaces), None);
+    let key = EncryptionKey::generate("2026").unwrap();
+    let encryption = Encryption {
+        keys: vec![key.clone()],
+        encrypted: Encrypted::Fields(vec!["ssn".to_string()]),
+    };
+    let writer = tiaf::api::TiafClient::new(url.clone(), Some("root".to_string()))
+        .with_encryption(Some(encryption));
+    let reader = tiaf::api::TiafClient::new(url.clone(), Some("root".to_string()));
+
+    let put =
+        tiaf::api::RecordPut::new("{\"name\": \"ada\", \"ssn\": \"078-05-1120\"}".to_string());
+    let first = writer.put_data_with_key(&put, Some("k1")).unwrap();
+    // a retried write encrypts alike, so it is the same write.
+    assert!(
+        writer
+            .put_data_with_key(&put, Some("k1"))
+            .unwrap()
+            .duplicate
+    );
+    writer
+        .put_data(&tiaf::api::RecordPut::new(
+            "{\"name\": \"bob\", \"ssn\": \"219-09-9999\"}".to_string(),
+        ))
+        .unwrap();
+    seal(&ns.blockchain, &ns.mem_pool);
+
+    // the chain holds only ciphertext, and anyone can still verify it.
+    let (stored, record) = {
+        let chain = ns.blockchain.read().unwrap();
+        chain.full_validate().unwrap();
+        let record = chain.get(1).unwrap().data[0].clone();
+        (serde_json::to_string(&*chain).unwrap(), record)
+    };
+    assert!(!stored.contains("078-05-1120"));
+    assert!(stored.contains("ada"));
+
+    let found = |client: &tiaf::api::TiafClient, q: &str| match client
+        .query(q.to_string(), None)
+        .unwrap()
+        .rows
+    {
+        QueryRows::Records(records) => records,
+        rows => panic!("unexpected rows {rows:?}"),
+    };
+    let rows = found(&writer, "ssn == \"078-05-1120\"");
+    assert_eq!(rows.len(), 1);
+    assert!(rows[0].entry.contains("078-05-1120"));
+    assert!(found(&reader, "ssn == \"078-05-1120\"").is_empty());
+
+    // the record as stored decrypts for holders of the key only.
+    assert_eq!(record.hash, first.record);
+    assert!(writer
+        .decrypt(&record)
+        .unwrap()
+        .entry
+        .contains("078-05-1120"));
+    assert_eq!(reader.decrypt(&record).unwrap(), record);
+
+    let ada = writer.kv_get("ada").unwrap().unwrap();
+    assert_eq!(ada.fields.unwrap()["ssn"], "078-05-1120");
+    let ada = reader.kv_get("ada").unwrap().unwrap();
+    assert!(is_encrypted(&ada.fields.unwrap()["ssn"]));
+    assert_eq!(reader.kv_get("grace").unwrap(), None);
+
+    // an entry encrypted whole is queried as its fields, once decrypted.
+    let whole = tiaf::api::TiafClient::new(url.clone(), Some("root".to_string())).with_encryption(
+        Some(Encryption {
+            keys: vec![key],
+            encrypted: Encrypted::Entry,
+        }),
+    );
+    whole
+        .put_data(&tiaf::api::RecordPut::new(
+            "{\"name\": \"eve\", \"ssn\": \"123-45-6789\"}".to_string(),
+        ))
+        .unwrap();
+    seal(&ns.blockchain, &ns.mem_pool);
+    let rows = found(&whole, "name == \"eve\"");
+    assert_eq!(rows.len(), 1);
+    assert!(rows[0].entry.contains("123-45-6789"));
+    assert!(found(&reader, "name == \"eve\"").is_empty());
+}
+
+#[test]
+fn test_new_nodes_begin_from_snapshots() {
+    let admins = || Keys::load(&[KeyConfig::new("root", "root", &[]).admin()]).unwrap();
+    let node_key = SnapshotKey::generate();
+    let config = NamespaceConfig {
+        indexed_fields: vec!["id".to_string()],
+        kv: Some(tiaf::kv::KvConfig::new("id")),
+        ..NamespaceConfig::default()
+    };
+    let namespaces = Namespaces::new(admins(), Duration::from_secs(60))
+        .with_snapshot_key(Some(node_key.clone()));
+    let ns = namespaces.open(DEFAULT, config.clone()).unwrap();
+    let url = serve(Arc::new(namespaces), None);
+    let admin = tiaf::api::TiafClient::new(url.clone(), Some("root".to_string()));
+    let put = |id: &str| {
+        admin
+            .put_data(&tiaf::api::RecordPut::new(format!("{{\"id\": \"{id}\"}}")))
+            .unwrap();
+        seal(&ns.blockchain, &ns.mem_pool);
+    };
+    put("a");
+    put("b");
+
+    assert!(tiaf::api::TiafClient::new(url.clone(), None)
+        .snapshot()
+        .is_err());
+    let snapshot = admin.snapshot().unwrap();
+    assert_eq!(snapshot.length, 3);
+    put("c");
+
+    // a new node takes up the snapshot, then catches up from its tip.
+    let fresh = Namespaces::new(admins(), Duration::from_secs(60));
+    let untrusting = fresh.open(DEFAULT, config.clone()).unwrap();
+    assert!(untrusting.import(&snapshot).is_err());
+    let fresh = Namespaces::new(admins(), Duration::from_secs(60));
+    let node = fresh
+        .open(
+            DEFAULT,
+            NamespaceConfig {
+                snapshot_keys: vec![node_key.public()],
+                upstreams: vec![url.clone()],
+                ..config
+            },
+        )
+        .unwrap();
+    assert!(node.import(&snapshot).unwrap());
+    assert_eq!(node.blockchain.read().unwrap().length(), 3);
+    {
+        let mut chain = node.blockchain.write().unwrap();
+        node.upstreams
+            .write()
+            .unwrap()
+            .catch_up(&mut chain)
+            .unwrap();
+        assert_eq!(*chain, *ns.blockchain.read().unwrap());
+        assert_eq!(chain.kv().unwrap().len(), 3);
+        chain.full_validate().unwrap();
+    }
+    assert!(!node.import(&snapshot).unwrap());
+
+    // a node that signs no snapshots takes none.
+    let unsigned = Namespaces::new(admins(), Duration::from_secs(60));
+    unsigned.open(DEFAULT, NamespaceConfig::default()).unwrap();
+    let url = serve(Arc::new(unsigned), None);
+    assert!(tiaf::api::TiafClient::new(url, Some("root".to_string()))
+        .snapshot()
+        .is_err());
+}
+
+// certificate makes a certificate named name, signed by ca, or a CA of its own if there is none.
+fn certificate(
+    dir: &std::path::Path,
+    name: &str,
+    ca: Option<&rcgen::Certificate>,
+) -> (rcgen::Certificate, std::path::PathBuf, std::path::PathBuf) {
+    let mut params = rcgen::CertificateParams::new(vec!["127.0.0.1".to_string()]);
+    params
+        .distinguished_name
+        .push(rcgen::DnType::CommonName, name);
+    if ca.is_none() {
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+    }
+    let cert = rcgen::Certificate::from_params(params).unwrap();
+    let pem = match ca {
+        Some(ca) => cert.serialize_pem_with_signer(ca).unwrap(),
+        None => cert.serialize_pem().unwrap(),
+    };
+    let (cert_file, key_file) = (
+        dir.join(format!("{name}.pem")),
+        dir.join(format!("{name}.key")),
+    );
+    std::fs::write(&cert_file, pem).unwrap();
+    std::fs::write(&key_file, cert.serialize_private_key_pem()).unwrap();
+    (cert, cert_file, key_file)
+}
+
+#[test]
+fn test_tls_and_client_certificates() {
+    let dir = std::env::temp_dir().join(format!("tiaf-tls-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (ca, ca_file, _) = certificate(&dir, "ca", None);
+    let (_, node_cert, node_key) = certificate(&dir, "node", Some(&ca));
+    let (_, peer_cert, peer_key) = certificate(&dir, "peer", Some(&ca));
+
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let namespaces = Namespaces::new(Keys::default(), Duration::from_secs(60));
+    let config = NamespaceConfig {
+        anonymous_scopes: vec![Scope::Read],
+        ..NamespaceConfig::default()
+    };
+    let ns = namespaces.open(DEFAULT, config).unwrap();
+    let mem_pool = ns.mem_pool.clone();
+    let tls = TlsConfig {
+        cert_file: node_cert,
+        key_file: node_key,
+        client_ca_file: Some(ca_file.clone()),
+        require_client_cert: false,
+    };
+    thread::spawn(move || {
+        tiaf::server::launch_server(
+            "tls".to_string(),
+            "127.0.0.1".to_string(),
+            port,
+            Arc::new(namespaces),
+            None,
+            Some(tls),
+        )
+    });
+
+    let url = format!("https://127.0.0.1:{port}");
+    let client = |cert: Option<(&std::path::Path, &std::path::Path)>| {
+        let config = ClientTlsConfig {
+            ca_file: Some(ca_file.clone()),
+            cert_file: cert.map(|(c, _)| c.to_path_buf()),
+            key_file: cert.map(|(_, k)| k.to_path_buf()),
+        };
+        tiaf::api::TiafClient::new(url.clone(), None)
+            .with_tls(Some(ClientTls::load(&config).unwrap()))
+            .unwrap()
+    };
+    let pinned = client(None);
+    let mut up = false;
+    for _ in 0..100 {
+        if pinned.get_statistics().is_ok() {
+            up = true;
+            break;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    assert!(up);
+
+    // the node's certificate is not one the system trusts, and it speaks no plain http.
+    assert!(tiaf::api::TiafClient::new(url.clone(), None)
+        .get_statistics()
+        .is_err());
+    assert!(
+        tiaf::api::TiafClient::new(format!("http://127.0.0.1:{port}"), None)
+            .get_statistics()
+            .is_err()
+    );
+
+    // a certificate from the cluster's CA lets a peer hand over records.
+    let record = tiaf::record::Record::new("{\"k\": \"v\"}".to_string());
+    assert!(pinned.put_record(&record).is_err());
+    let member = client(Some((&peer_cert, &peer_key)));
+    assert!(member.put_record(&record).is_ok());
+    assert_eq!(mem_pool.read().unwrap().length(), 1);
+
+    // requests and answers larger than a TLS record are relayed whole, both ways.
+    let large = tiaf::record::Record::new(format!("{{\"k\": \"{}\"}}", "v".repeat(100_000)));
+    member.put_record(&large).unwrap();
+    seal(&ns.blockchain, &mem_pool);
+    let chain = pinned.get_full_chain().unwrap();
+    assert_eq!(chain, *ns.blockchain.read().unwrap());
+    assert!(chain.get(1).unwrap().data.contains(&large));
+    std::fs::remove_dir_all(dir).unwrap();
+}